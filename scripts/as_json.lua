@@ -1,14 +1,71 @@
 json = require "scripts/json"
 
+local threads = {}
+local MAX_SAMPLES = 10
+
 function setup(thread)
    thread0 = thread0 or thread
+   table.insert(threads, thread)
+   thread:set("id", #threads)
 end
                    
 function init(args)
    file = args[1] or "/dev/null"
+   statuses = {}
+   -- a fraction of the response bodies is written to <file>.<thread id>
+   sample_rate = tonumber(args[2] or "0") or 0
+   samples = 0
+   if sample_rate > 0 then
+      math.randomseed(os.time() + id)
+      sample_file = io.open(file .. "." .. id, "w")
+   end
+   -- the optional request: method, body and "Name: value" headers
+   if args[3] then
+      wrk.method = args[3]
+   end
+   if args[4] and args[4] ~= "" then
+      wrk.body = args[4]
+   end
+   for i = 5, #args do
+      local name, value = args[i]:match("^([^:]+):%s*(.*)$")
+      if name then
+         wrk.headers[name] = value
+      end
+   end
+end
+
+-- wrk parses the responses, which slows it down, only for a script defining response(), and
+-- decides it before init() gets the args: the environment asks for the statuses or the samples
+if os.getenv("AS_JSON_RESPONSES") == "1" then
+   function response(status, headers, body)
+      statuses[status] = (statuses[status] or 0) + 1
+      if sample_file and samples < MAX_SAMPLES and math.random() < sample_rate then
+         samples = samples + 1
+         sample_file:write(json.encode(body or ""), "\n")
+         sample_file:flush()
+      end
+   end
 end
 
 function done(summary, latency, requests)
+   -- the status counts of every thread, as a list to encode an empty one as json
+   local counts = {}
+   for _, thread in ipairs(threads) do
+      for status, count in pairs(thread:get("statuses") or {}) do
+         counts[status] = (counts[status] or 0) + count
+      end
+   end
+   local status_list = {}
+   for status, count in pairs(counts) do
+      table.insert(status_list, { status = status, count = count })
+   end
+
+   -- the percentiles of DISTRIBUTION_PERCENTILES in src/types.rs
+   local distribution = { { percentile = 0, latency = latency.min } }
+   for _, p in ipairs({ 1, 5, 10, 20, 30, 40, 50, 60, 70, 80, 90, 95, 99, 99.9, 100 }) do
+      table.insert(distribution, { percentile = p, latency = latency:percentile(p) })
+   end
+
    print(json.encode({
        duration      = summary.duration,
        requests      = summary.requests,
@@ -16,5 +73,16 @@ function done(summary, latency, requests)
        latency_max   = latency.max,
        latency_mean  = latency.mean,
        latency_stdev = latency.stdev,
+       latency_p50   = latency:percentile(50.0),
+       latency_p99   = latency:percentile(99.0),
+       latency_distribution = distribution,
+       errors        = {
+           connect = summary.errors.connect,
+           read    = summary.errors.read,
+           write   = summary.errors.write,
+           status  = summary.errors.status,
+           timeout = summary.errors.timeout,
+       },
+       statuses      = status_list,
    }))
 end
@@ -1,66 +1,112 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::thread;
-use std::thread::JoinHandle;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
+use failure::format_err;
+use futures::future::{try_join_all, BoxFuture};
 use url::Url;
 
-// use crate::types::NodeType;
 use crate::configuration::BootstrapEnv;
+use crate::html_report;
+use crate::influx::{self, FieldValue, Point};
+use crate::metrics;
+use crate::utils::{http_client, interruptible};
 
-pub(crate) fn start_bootstrap(env: BootstrapEnv) {
+/// Last known state of a monitored node, used for the report when the test is interrupted
+#[derive(Debug, Clone)]
+pub struct NodeProgress {
+    pub node: Url,
+    pub level: Option<i32>,
+    pub duration: Option<f64>,
+}
+
+pub async fn start_bootstrap(env: BootstrapEnv) -> Result<(), failure::Error> {
     let BootstrapEnv { nodes, level } = env;
 
-    let mut joins = Vec::new();
-    for node in nodes {
-        joins.push(spawn_monitor_thread(node, level))
-    }
+    let client = http_client()?;
+    let progress = Mutex::new(
+        nodes
+            .iter()
+            .map(|node| NodeProgress {
+                node: node.clone(),
+                level: None,
+                duration: None,
+            })
+            .collect::<Vec<_>>(),
+    );
 
-    for join in joins {
-        join.join().unwrap();
+    // the first failing node cancels the other monitors
+    let monitors = nodes.iter().enumerate().map(|(index, node)| {
+        monitor_node(
+            &client,
+            node,
+            level,
+            index,
+            &progress,
+            POLL_INTERVAL,
+            |_| None,
+        )
+    });
+    let result = interruptible(async { try_join_all(monitors).await.map(|_| ()) }).await;
+
+    if result.is_err() {
+        display_progress(&progress.lock().unwrap());
     }
+    result
 }
 
-fn spawn_monitor_thread(node: Url, bootstrap_level: i32) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let now = Instant::now();
-
-        let bootstrapping_tezedge = create_monitor_node_thread(node.clone(), bootstrap_level);
-        bootstrapping_tezedge.join().unwrap();
+/// Interval of the head level polls of the bootstrapping nodes
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
 
-        let elapsed = now.elapsed();
-        let sec = (elapsed.as_secs() as f64) + (elapsed.subsec_nanos() as f64 / 1_000_000_000.0);
-        println!("[{}] Duration in seconds: {}", node, sec);
-    })
-}
-
-fn create_monitor_node_thread(node: Url, bootstrap_level: i32) -> JoinHandle<()> {
+/// Polls the head level of the node until it reaches the bootstrap level, the progress of the
+/// node with the index is updated on every poll
+///
+/// Every polled level below the bootstrap level is passed to the `on_level` hook, the returned
+/// future, e.g. a deliberate restart of the node, is awaited before the node is polled again
+pub(crate) async fn monitor_node<'a>(
+    client: &reqwest::Client,
+    node: &Url,
+    bootstrap_level: i32,
+    index: usize,
+    progress: &Mutex<Vec<NodeProgress>>,
+    poll_interval: Duration,
+    mut on_level: impl FnMut(i32) -> Option<BoxFuture<'a, Result<(), failure::Error>>>,
+) -> Result<(), failure::Error> {
+    let now = Instant::now();
     let mut active = false;
-    thread::spawn(move || loop {
-        match is_bootstrapped(&node) {
-            Ok(response_string) => {
+
+    loop {
+        match head_level(client, node).await {
+            Ok(Some(block_level)) => {
                 active = true;
-                // empty string means, the rpc server is running, but the bootstraping has not started yet
-                if !response_string.is_empty() {
-                    let block_level: i32 = response_string.parse().unwrap();
-
-                    if block_level >= bootstrap_level {
-                        println!("[{}] Done Bootstrapping", node);
-                        break;
-                    } else {
-                        println!("[{}] Bootstrapping . . . level: {}", node, response_string);
-                        thread::sleep(Duration::from_secs(10));
-                    }
+                progress.lock().unwrap()[index].level = Some(block_level);
+                html_report::record_level(node, now.elapsed().as_secs_f64(), block_level);
+                metrics::set_head_level(node, block_level);
+                influx::emit(
+                    Point::new("bootstrap")
+                        .tag("node", node.as_str())
+                        .field("level", FieldValue::Integer(block_level.into()))
+                        .float("elapsed", now.elapsed().as_secs_f64()),
+                );
+
+                if block_level >= bootstrap_level {
+                    println!("[{}] Done Bootstrapping", node);
+                    break;
                 } else {
-                    println!(
-                        "[{}] Waiting for node to start bootstrapping...",
-                        node.to_string()
-                    );
-                    thread::sleep(Duration::from_secs(10));
+                    println!("[{}] Bootstrapping . . . level: {}", node, block_level);
+                }
+                if let Some(hook) = on_level(block_level) {
+                    hook.await?;
+                    continue;
                 }
             }
+            // the rpc server is running, but the bootstraping has not started yet
+            Ok(None) => {
+                active = true;
+                println!("[{}] Waiting for node to start bootstrapping...", node);
+            }
             Err(e) => {
                 if !active {
                     println!("[{}] Waiting for node to run", node);
@@ -68,28 +114,54 @@ fn create_monitor_node_thread(node: Url, bootstrap_level: i32) -> JoinHandle<()>
                 } else {
                     // when the node was 'active, i.e. was responding to the head reqeusts, and suddenly there is an error in the request
                     // it means the node encounterred some error and exited
-                    panic!("[{}] The watched node has exited: {}", node, e)
+                    return Err(format_err!("[{}] The watched node has exited: {}", node, e));
                 }
-
-                thread::sleep(Duration::from_secs(10));
             }
         }
-    })
+        tokio::time::sleep(poll_interval).await;
+    }
+
+    let sec = now.elapsed().as_secs_f64();
+    progress.lock().unwrap()[index].duration = Some(sec);
+    influx::emit(
+        Point::new("bootstrap")
+            .tag("node", node.as_str())
+            .float("duration", sec),
+    );
+    println!("[{}] Duration in seconds: {}", node, sec);
+    Ok(())
+}
+
+fn display_progress(progress: &[NodeProgress]) {
+    println!("Bootstrap progress:");
+    for node in progress {
+        match (node.level, node.duration) {
+            (_, Some(duration)) => println!("[{}] Done in {}s", node.node, duration),
+            (Some(level), None) => println!("[{}] Not done, level: {}", node.node, level),
+            (None, None) => println!("[{}] Not started", node.node),
+        }
+    }
 }
 
-#[allow(dead_code)]
-fn is_bootstrapped(node: &Url) -> Result<String, reqwest::Error> {
-    let response = reqwest::blocking::get(&format!("{}chains/main/blocks/head", node))?;
+/// Level of the head block, `None` when the node has not started bootstrapping
+pub async fn head_level(
+    client: &reqwest::Client,
+    node: &Url,
+) -> Result<Option<i32>, failure::Error> {
+    let response = client
+        .get(format!("{}chains/main/blocks/head", node))
+        .send()
+        .await?;
 
     // if there is no response, the node has not started bootstrapping
     if response.status().is_success() {
         let response_node: serde_json::value::Value =
-            serde_json::from_str(&response.text()?).expect("JSON was not well-formatted");
+            serde_json::from_str(&response.text().await?)?;
 
         Ok(response_node["header"]["level"]
-            .to_string()
-            .replace("\"", ""))
+            .as_i64()
+            .map(|level| level as i32))
     } else {
-        Ok(String::new())
+        Ok(None)
     }
 }
@@ -0,0 +1,382 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{join_all, try_join_all};
+use itertools::Itertools;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::bootstrap::{monitor_node, NodeProgress, POLL_INTERVAL};
+use crate::configuration::BootstrapLoadEnv;
+use crate::influx::{self, FieldValue, Point};
+use crate::mixed_workload::{sample, summarize, Sample};
+use crate::types::{Branch, RpcRequest, WrkResult};
+use crate::utils::{get_requests, http_client, interruptible};
+use crate::wrk::{calc_max_latency, us_to_ms};
+
+/// Request of the load with the last known head level of the node when it was sent
+#[derive(Debug, Clone)]
+struct LoadSample {
+    level: i32,
+    sample: Sample,
+}
+
+/// Requests and head levels of a single node
+#[derive(Debug, Clone, Default)]
+struct NodeLoad {
+    samples: Vec<LoadSample>,
+    /// Every new head level with the seconds since the start of the load
+    levels: Vec<(f64, i32)>,
+}
+
+impl NodeLoad {
+    fn record_level(&mut self, elapsed: f64, level: i32) {
+        if self.levels.last().is_none_or(|(_, last)| *last != level) {
+            self.levels.push((elapsed, level));
+        }
+    }
+}
+
+/// Requests and bootstrap speed of a range of levels
+#[derive(Debug, Clone)]
+pub struct LevelRange {
+    pub start: i32,
+    pub end: i32,
+    pub result: WrkResult,
+    /// Applied blocks per second while the head was in the range
+    pub blocks_per_second: f64,
+}
+
+/// Sends the weighted mix of the urls to the bootstrapping node at the constant rate, until the
+/// node is done bootstrapping
+async fn load_node(
+    client: &reqwest::Client,
+    branch: &Branch,
+    urls: &[RpcRequest],
+    rate: f64,
+    progress: &Mutex<Vec<NodeProgress>>,
+    loads: &Arc<Mutex<Vec<NodeLoad>>>,
+) -> Result<(), failure::Error> {
+    let index = branch.sort_key;
+    let weights = WeightedIndex::new(urls.iter().map(|rpc| rpc.weight))?;
+    let mut rng = StdRng::seed_from_u64(index as u64);
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+    let start = Instant::now();
+    let mut pending = Vec::new();
+
+    loop {
+        interval.tick().await;
+        let NodeProgress {
+            level, duration, ..
+        } = progress.lock().unwrap()[index].clone();
+        // the load starts with the bootstrapping and stops when the node is done
+        let level = match (level, duration) {
+            (Some(level), None) => level,
+            (Some(level), Some(_)) => {
+                loads.lock().unwrap()[index].record_level(start.elapsed().as_secs_f64(), level);
+                break;
+            }
+            (None, _) => continue,
+        };
+        loads.lock().unwrap()[index].record_level(start.elapsed().as_secs_f64(), level);
+
+        // the requests are sent at the rate also when the node is slow to answer them
+        let url = weights.sample(&mut rng);
+        let (client, branch, rpc, loads) = (
+            client.clone(),
+            branch.clone(),
+            urls[url].clone(),
+            loads.clone(),
+        );
+        pending.retain(|request: &tokio::task::JoinHandle<()>| !request.is_finished());
+        pending.push(tokio::spawn(async move {
+            let sample = sample(&client, &branch, &rpc, url).await;
+            loads.lock().unwrap()[branch.sort_key]
+                .samples
+                .push(LoadSample { level, sample });
+        }));
+    }
+    join_all(pending).await;
+    Ok(())
+}
+
+/// Splits the load of the node into the ranges of levels
+fn level_ranges(load: &NodeLoad, level_range: i32) -> Vec<LevelRange> {
+    let mut samples: BTreeMap<i32, Vec<&Sample>> = BTreeMap::new();
+    for LoadSample { level, sample } in &load.samples {
+        samples.entry(level / level_range).or_default().push(sample);
+    }
+    let ranges = samples
+        .keys()
+        .copied()
+        .chain(load.levels.iter().map(|(_, level)| level / level_range))
+        .collect::<BTreeSet<_>>();
+
+    ranges
+        .into_iter()
+        .filter_map(|range| {
+            let start = range * level_range;
+            let end = start + level_range;
+            // from the first head in the range to the first head after it
+            let first = load.levels.iter().find(|(_, level)| *level >= start);
+            let last = load
+                .levels
+                .iter()
+                .find(|(_, level)| *level >= end)
+                .or_else(|| load.levels.last());
+            let (seconds, blocks_per_second) = match (first, last) {
+                (Some((t0, l0)), Some((t1, l1))) if t1 > t0 => {
+                    (t1 - t0, f64::from(l1 - l0) / (t1 - t0))
+                }
+                _ => (0.0, 0.0),
+            };
+            let range_samples = samples.get(&range).map_or(&[][..], Vec::as_slice);
+            // the last head level alone does not make a range
+            if range_samples.is_empty() && seconds == 0.0 {
+                return None;
+            }
+            Some(LevelRange {
+                start,
+                end,
+                result: summarize((seconds * 1_000_000.0) as f32, range_samples),
+                blocks_per_second,
+            })
+        })
+        .collect()
+}
+
+fn display_node(
+    branch: &Branch,
+    progress: &NodeProgress,
+    baseline: Option<f64>,
+    ranges: &[LevelRange],
+) {
+    match (progress.duration, progress.level) {
+        (Some(duration), _) => {
+            print!(
+                "[{}] Done bootstrapping under load in {:.2}s",
+                branch.name, duration
+            );
+            match baseline {
+                Some(baseline) => print!(
+                    ", {:.2}s without load: slowdown {:.2}%",
+                    baseline,
+                    (duration - baseline) / baseline * 100.0
+                ),
+                None => print!(", slowdown unknown without a --baseline"),
+            }
+            println!();
+        }
+        (None, Some(level)) => println!("[{}] Not done, level: {}", branch.name, level),
+        (None, None) => println!("[{}] Not started", branch.name),
+    }
+    if ranges.is_empty() {
+        return;
+    }
+
+    println!(
+        "{:<24} {:>9} {:>10} {:>10} {:>10} {:>8} {:>10}",
+        "levels", "requests", "p50 ms", "p99 ms", "max ms", "errors", "blocks/s"
+    );
+    for range in ranges {
+        println!(
+            "{:<24} {:>9} {:>10.3} {:>10.3} {:>10.3} {:>7.2}% {:>10.2}",
+            format!("{}..{}", range.start, range.end),
+            range.result.requests(),
+            us_to_ms(range.result.latency_p50()),
+            us_to_ms(range.result.latency_p99()),
+            calc_max_latency(range.result.latency_max()),
+            range.result.error_rate() * 100.0,
+            range.blocks_per_second
+        );
+    }
+    println!();
+}
+
+/// Bootstraps the targets under a constant rate of rpc requests, reports the latency of the
+/// requests by the ranges of levels and the bootstrap time compared to the baseline without load
+pub async fn bootstrap_load(env: BootstrapLoadEnv) -> Result<(), failure::Error> {
+    let BootstrapLoadEnv {
+        targets,
+        level,
+        url_file,
+        rate,
+        level_range,
+        baselines,
+    } = env;
+
+    let client = http_client()?;
+    let branches = targets
+        .iter()
+        .enumerate()
+        .map(|(sort_key, target)| target.branch(sort_key))
+        .collect_vec();
+    let urls = get_requests(&url_file)?;
+    let progress = Mutex::new(
+        branches
+            .iter()
+            .map(|branch| NodeProgress {
+                node: branch.url.clone(),
+                level: None,
+                duration: None,
+            })
+            .collect_vec(),
+    );
+    let loads = Arc::new(Mutex::new(vec![NodeLoad::default(); branches.len()]));
+
+    // the load runs on every target, the duration without load has to come from another run
+    for branch in &branches {
+        if !baselines.iter().any(|(name, _)| *name == branch.name) {
+            println!(
+                "[{}] Warning: no --baseline {}=SECONDS, the slowdown by the load is not measured, \
+                 bootstrap the target without load first, e.g. with the bootstrap subcommand",
+                branch.name, branch.name
+            );
+        }
+    }
+    println!(
+        "Bootstrapping {} targets to level {} under the mix of {} urls at {} req/s on every target",
+        branches.len(),
+        level,
+        urls.len(),
+        rate
+    );
+    // the first failing node cancels the other monitors and loads
+    let nodes = branches.iter().map(|branch| {
+        let (client, urls, progress, loads) = (&client, &urls, &progress, &loads);
+        async move {
+            futures::try_join!(
+                monitor_node(
+                    client,
+                    &branch.url,
+                    level,
+                    branch.sort_key,
+                    progress,
+                    POLL_INTERVAL,
+                    |_| None
+                ),
+                load_node(client, branch, urls, rate, progress, loads),
+            )
+            .map(|_| ())
+        }
+    });
+    let result = interruptible(async { try_join_all(nodes).await.map(|_| ()) }).await;
+
+    println!("------------------------------------------------------");
+    let progress = progress.into_inner().unwrap();
+    let loads = loads.lock().unwrap().clone();
+    for ((branch, progress), load) in branches.iter().zip(&progress).zip(&loads) {
+        let baseline = baselines
+            .iter()
+            .find(|(name, _)| *name == branch.name)
+            .map(|(_, baseline)| *baseline);
+        let ranges = level_ranges(load, level_range);
+        display_node(branch, progress, baseline, &ranges);
+
+        for range in &ranges {
+            influx::emit(
+                Point::new("bootstrap_load")
+                    .branch(branch)
+                    .tag("levels", &format!("{}..{}", range.start, range.end))
+                    .field(
+                        "requests",
+                        FieldValue::Integer(*range.result.requests() as i64),
+                    )
+                    .float("latency_p50_ms", us_to_ms(range.result.latency_p50()))
+                    .float("latency_p99_ms", us_to_ms(range.result.latency_p99()))
+                    .float(
+                        "latency_max_ms",
+                        calc_max_latency(range.result.latency_max()),
+                    )
+                    .float("error_rate", range.result.error_rate())
+                    .float("blocks_per_second", range.blocks_per_second),
+            );
+        }
+        if let Some(duration) = progress.duration {
+            let mut point = Point::new("bootstrap_load")
+                .branch(branch)
+                .float("duration", duration);
+            if let Some(baseline) = baseline {
+                point = point.float("slowdown", (duration - baseline) / baseline);
+            }
+            influx::emit(point);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RequestError;
+
+    fn load_sample(level: i32, error: Option<RequestError>) -> LoadSample {
+        LoadSample {
+            level,
+            sample: Sample {
+                index: 0,
+                latency: 2000.0,
+                status: error.map_or(Some(200), |_| None),
+                error,
+            },
+        }
+    }
+
+    #[test]
+    fn record_level_skips_the_same_level() {
+        let mut load = NodeLoad::default();
+        load.record_level(0.0, 10);
+        load.record_level(1.0, 10);
+        load.record_level(2.0, 12);
+        assert_eq!(load.levels, vec![(0.0, 10), (2.0, 12)]);
+    }
+
+    #[test]
+    fn level_ranges_of_the_load() {
+        let load = NodeLoad {
+            samples: vec![
+                load_sample(50, None),
+                load_sample(99, None),
+                load_sample(150, Some(RequestError::Timeout)),
+            ],
+            levels: vec![(0.0, 50), (10.0, 150), (20.0, 250)],
+        };
+        let ranges = level_ranges(&load, 100);
+
+        // the last head level alone does not make a range
+        assert_eq!(
+            ranges
+                .iter()
+                .map(|range| (range.start, range.end, range.blocks_per_second))
+                .collect_vec(),
+            vec![(0, 100, 10.0), (100, 200, 10.0)]
+        );
+        assert_eq!(*ranges[0].result.requests(), 2.0);
+        assert_eq!(*ranges[0].result.duration(), 10_000_000.0);
+        assert_eq!(*ranges[0].result.latency_p99(), 2000.0);
+        assert_eq!(ranges[0].result.error_rate(), 0.0);
+        assert_eq!(*ranges[1].result.requests(), 0.0);
+        assert_eq!(ranges[1].result.error_rate(), 1.0);
+    }
+
+    #[test]
+    fn level_ranges_without_progress() {
+        // requests sent while the head did not move
+        let load = NodeLoad {
+            samples: vec![load_sample(1200, None)],
+            levels: vec![(0.0, 1200)],
+        };
+        let ranges = level_ranges(&load, 1000);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].start, ranges[0].end), (1000, 2000));
+        assert_eq!(ranges[0].blocks_per_second, 0.0);
+        assert_eq!(*ranges[0].result.requests(), 1.0);
+
+        assert!(level_ranges(&NodeLoad::default(), 1000).is_empty());
+    }
+}
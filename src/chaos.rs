@@ -0,0 +1,318 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use failure::{bail, format_err};
+use futures::FutureExt;
+use itertools::Itertools;
+use url::Url;
+
+use crate::bootstrap::{head_level, monitor_node, NodeProgress};
+use crate::configuration::ChaosTestEnv;
+use crate::influx::{self, FieldValue, Point};
+use crate::utils::{http_client, interruptible};
+
+/// Outcome of a deliberate restart of the node
+#[derive(Debug, Clone)]
+pub struct Restart {
+    /// Head level before the stop
+    pub level: i32,
+    /// Seconds from the stop command until the rpc stopped answering
+    pub stop_seconds: f64,
+    /// Seconds from the start command until the rpc answered again
+    pub rpc_seconds: Option<f64>,
+    /// First head level after the start
+    pub resumed_level: Option<i32>,
+    /// Seconds from the start command until the head got back to the level
+    pub recovery_seconds: Option<f64>,
+    /// Level regressions and changed block hashes
+    pub problems: Vec<String>,
+}
+
+/// Runs the shell command to completion
+async fn run_command(command: &str) -> Result<(), failure::Error> {
+    println!("Running: {}", command);
+    let shell_command = command.to_string();
+    let status = tokio::task::spawn_blocking(move || {
+        Command::new("sh").arg("-c").arg(&shell_command).status()
+    })
+    .await??;
+
+    if !status.success() {
+        bail!("Command {:?} failed with {}", command, status);
+    }
+    Ok(())
+}
+
+/// Hash of the block at the level
+async fn block_hash(
+    client: &reqwest::Client,
+    node: &Url,
+    level: i32,
+) -> Result<String, failure::Error> {
+    let response = client
+        .get(format!("{}chains/main/blocks/{}/hash", node, level))
+        .send()
+        .await?
+        .error_for_status()?;
+    let hash: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+
+    hash.as_str().map(str::to_string).ok_or_else(|| {
+        format_err!(
+            "[{}] Unexpected hash of the block {}: {}",
+            node,
+            level,
+            hash
+        )
+    })
+}
+
+/// Levels spread evenly up to the head, ending at the head
+fn sample_levels(head: i32, samples: i32) -> Vec<i32> {
+    (1..=samples)
+        .map(|sample| (i64::from(head) * i64::from(sample) / i64::from(samples)) as i32)
+        .filter(|level| *level > 0)
+        .dedup()
+        .collect()
+}
+
+/// Hashes of the blocks at the levels spread evenly up to the head
+async fn sample_hashes(
+    client: &reqwest::Client,
+    node: &Url,
+    head: i32,
+    samples: i32,
+) -> Result<Vec<(i32, String)>, failure::Error> {
+    let mut hashes = Vec::new();
+    for level in sample_levels(head, samples) {
+        hashes.push((level, block_hash(client, node, level).await?));
+    }
+    Ok(hashes)
+}
+
+/// Stops and starts the node at the level and waits until its head is back at the level
+async fn restart_node(
+    env: &ChaosTestEnv,
+    client: &reqwest::Client,
+    level: i32,
+) -> Result<Restart, failure::Error> {
+    let node = &env.node;
+    let poll_interval = Duration::from_secs(env.poll_interval);
+    let timeout = Duration::from_secs(env.recovery_timeout);
+    let hashes = sample_hashes(client, node, level, env.hash_samples).await?;
+
+    println!("[{}] Restarting the node at level {}", node, level);
+    let stop = Instant::now();
+    run_command(&env.stop_command).await?;
+    while head_level(client, node).await.is_ok() {
+        if stop.elapsed() > timeout {
+            bail!(
+                "[{}] The node still answers {}s after the stop command",
+                node,
+                env.recovery_timeout
+            );
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+    let stop_seconds = stop.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    run_command(&env.start_command).await?;
+    let mut rpc_seconds = None;
+    let mut resumed_level = None;
+    let recovery_seconds = loop {
+        // an error means the node is still starting
+        if let Ok(head) = head_level(client, node).await {
+            rpc_seconds.get_or_insert(start.elapsed().as_secs_f64());
+            if let Some(head) = head {
+                resumed_level.get_or_insert(head);
+                if head >= level {
+                    break Some(start.elapsed().as_secs_f64());
+                }
+            }
+        }
+        if start.elapsed() > timeout {
+            break None;
+        }
+        tokio::time::sleep(poll_interval).await;
+    };
+
+    let mut problems = Vec::new();
+    match resumed_level {
+        Some(resumed) if resumed < level => problems.push(format!(
+            "[{}] Level regression after the restart at level {}: resumed at level {}",
+            node, level, resumed
+        )),
+        Some(_) => (),
+        None => problems.push(format!(
+            "[{}] No head level {}s after the restart at level {}",
+            node, env.recovery_timeout, level
+        )),
+    }
+    if recovery_seconds.is_none() {
+        problems.push(format!(
+            "[{}] Not back at level {} {}s after the restart",
+            node, level, env.recovery_timeout
+        ));
+    }
+    if rpc_seconds.is_some() {
+        for (hash_level, before) in &hashes {
+            match block_hash(client, node, *hash_level).await {
+                Ok(after) if after == *before => (),
+                Ok(after) => problems.push(format!(
+                    "[{}] Block hash at level {} changed after the restart at level {}: {} -> {}",
+                    node, hash_level, level, before, after
+                )),
+                Err(e) => problems.push(format!(
+                    "[{}] Missing block at level {} after the restart at level {}: {}",
+                    node, hash_level, level, e
+                )),
+            }
+        }
+    }
+
+    Ok(Restart {
+        level,
+        stop_seconds,
+        rpc_seconds,
+        resumed_level,
+        recovery_seconds,
+        problems,
+    })
+}
+
+fn display_restart(node: &Url, restart: &Restart) {
+    let seconds = |seconds: Option<f64>| seconds.map_or("-".to_string(), |s| format!("{:.2}s", s));
+    println!(
+        "[{}] Restart at level {}: stopped in {:.2}s, rpc after {}, resumed at level {}, recovered in {}",
+        node,
+        restart.level,
+        restart.stop_seconds,
+        seconds(restart.rpc_seconds),
+        restart
+            .resumed_level
+            .map_or("-".to_string(), |level| level.to_string()),
+        seconds(restart.recovery_seconds)
+    );
+}
+
+/// Bootstraps the node, restarts it when its head reaches the restart levels and verifies it
+/// resumes from its persisted state, an exit of the node outside of the restarts fails the test
+pub async fn chaos_test(env: ChaosTestEnv) -> Result<(), failure::Error> {
+    let client = http_client()?;
+    let node = &env.node;
+    let progress = Mutex::new(vec![NodeProgress {
+        node: node.clone(),
+        level: None,
+        duration: None,
+    }]);
+    let mut restart_levels = env.restart_levels.iter().copied().peekable();
+    let restarts = Mutex::new(Vec::new());
+
+    let result = interruptible(monitor_node(
+        &client,
+        node,
+        env.level,
+        0,
+        &progress,
+        Duration::from_secs(env.poll_interval),
+        |level| {
+            // a single restart for all the restart levels crossed since the last poll
+            let crossed = restart_levels
+                .peeking_take_while(|restart| level >= *restart)
+                .collect_vec();
+            if crossed.is_empty() {
+                return None;
+            }
+            if crossed.len() > 1 {
+                println!(
+                    "[{}] Restart levels {} crossed by a single poll, restarting once",
+                    node,
+                    crossed.iter().join(", ")
+                );
+            }
+            let (env, client, restarts) = (&env, &client, &restarts);
+            Some(
+                async move {
+                    let restart = restart_node(env, client, level).await?;
+                    display_restart(node, &restart);
+                    restarts.lock().unwrap().push(restart);
+                    Ok(())
+                }
+                .boxed(),
+            )
+        },
+    ))
+    .await;
+    let restarts = restarts.into_inner().unwrap();
+
+    println!("------------------------------------------------------");
+    for restart in &restarts {
+        display_restart(node, restart);
+        let mut point = Point::new("chaos")
+            .tag("node", node.as_str())
+            .field("level", FieldValue::Integer(restart.level.into()))
+            .float("stop_seconds", restart.stop_seconds)
+            .field(
+                "problems",
+                FieldValue::Integer(restart.problems.len() as i64),
+            );
+        if let Some(resumed_level) = restart.resumed_level {
+            point = point.field("resumed_level", FieldValue::Integer(resumed_level.into()));
+        }
+        if let Some(rpc_seconds) = restart.rpc_seconds {
+            point = point.float("rpc_seconds", rpc_seconds);
+        }
+        if let Some(recovery_seconds) = restart.recovery_seconds {
+            point = point.float("recovery_seconds", recovery_seconds);
+        }
+        influx::emit(point);
+    }
+    let skipped = restart_levels.collect_vec();
+    if result.is_ok() && !skipped.is_empty() {
+        println!(
+            "[{}] Not restarted at the levels {}, the node was done bootstrapping",
+            node,
+            skipped.iter().join(", ")
+        );
+    }
+    let problems = restarts
+        .iter()
+        .flat_map(|restart| &restart.problems)
+        .collect_vec();
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    result?;
+
+    if !problems.is_empty() {
+        bail!("{}", problems.iter().join("\n"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_levels_end_at_the_head() {
+        assert_eq!(sample_levels(1000, 4), vec![250, 500, 750, 1000]);
+        assert_eq!(sample_levels(10, 3), vec![3, 6, 10]);
+        assert_eq!(sample_levels(1000, 1), vec![1000]);
+        // the levels of a short chain are not repeated
+        assert_eq!(sample_levels(3, 10), vec![1, 2, 3]);
+        assert_eq!(sample_levels(0, 10), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn sample_levels_of_a_long_chain() {
+        let levels = sample_levels(i32::MAX, 10);
+        assert_eq!(levels.len(), 10);
+        assert_eq!(levels.last(), Some(&i32::MAX));
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
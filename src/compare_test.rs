@@ -0,0 +1,88 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use assert_json_diff::assert_json_eq_no_panic;
+use failure::bail;
+use futures::future::try_join_all;
+use serde_json::Value;
+
+use crate::configuration::CompareTestEnv;
+use crate::types::{reference_index, RpcRequest, Target};
+use crate::utils::{http_client, interruptible, rpc_request};
+
+async fn get_response(
+    client: &reqwest::Client,
+    target: &Target,
+    rpc: &RpcRequest,
+) -> Result<Value, failure::Error> {
+    let response = rpc_request(client, &target.url, rpc).send().await?;
+    if !response.status().is_success() {
+        bail!(
+            "[{}] Request {} failed: {}",
+            target.name,
+            rpc,
+            response.status()
+        );
+    }
+
+    // non-json responses are compared as strings
+    let text = response.text().await?;
+    Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+}
+
+pub async fn test_responses(env: CompareTestEnv) -> Result<(), failure::Error> {
+    let CompareTestEnv { targets, url_file } = env;
+
+    let client = http_client()?;
+    let reference_index = reference_index(&targets);
+    let reference = &targets[reference_index];
+    let mut mismatches = Vec::new();
+
+    let result = interruptible(async {
+        for rpc in super::utils::get_requests(&url_file)? {
+            println!("Comparing responses for rpc: {}", rpc);
+            let mut responses = try_join_all(
+                targets
+                    .iter()
+                    .map(|target| get_response(&client, target, &rpc)),
+            )
+            .await?;
+            let expected = responses.remove(reference_index);
+
+            for (target, response) in targets
+                .iter()
+                .filter(|t| t.name != reference.name)
+                .zip(responses)
+            {
+                if let Err(diff) = assert_json_eq_no_panic(&response, &expected) {
+                    println!(
+                        "[{}] Response differs from {}:\n{}",
+                        target.name, reference.name, diff
+                    );
+                    mismatches.push(format!("[{}] {}", target.name, rpc));
+                }
+            }
+        }
+        Ok(())
+    })
+    .await;
+
+    if result.is_err() && !mismatches.is_empty() {
+        println!(
+            "Responses differing before the failure:\n{}",
+            mismatches.join("\n")
+        );
+    }
+    result?;
+
+    if !mismatches.is_empty() {
+        bail!(
+            "{} responses differ from the {} node:\n{}",
+            mismatches.len(),
+            reference.name,
+            mismatches.join("\n")
+        );
+    }
+    println!("Json responses are identical!");
+    Ok(())
+}
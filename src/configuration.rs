@@ -1,35 +1,363 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
 use clap::{App, Arg, SubCommand};
+use serde::Deserialize;
 use url::Url;
 
+use crate::replay::ReplaySpeed;
+use crate::scenario::{Scenario, DEFAULT_SCENARIO};
+use crate::schedule::BranchOrder;
+use crate::types::{BranchType, Target};
+
+pub const DEFAULT_WRK_TEST_DURATION: &str = "10";
+pub const DEFAULT_MAX_LATENCY_THRESHOLD: &str = "10";
+pub const DEFAULT_THROUGHPUT_THRESHOLD: &str = "10";
+pub const DEFAULT_ERROR_THRESHOLD: &str = "0";
+pub const DEFAULT_WRK_REQUEST_RATE: &str = "100";
+pub const DEFAULT_DURATION_THRESHOLD: &str = "10";
+pub const DEFAULT_CONNECTIONS: &str = "10";
+pub const DEFAULT_MAX_CONNECTIONS: &str = "64";
+pub const DEFAULT_GENERATED_CYCLES: &str = "3";
+pub const DEFAULT_GENERATED_BLOCKS: &str = "100";
+pub const DEFAULT_MAX_URLS: &str = "1000";
+pub const DEFAULT_SEED: &str = "0";
+pub const DEFAULT_SOAK_DURATION: &str = "3600";
+pub const DEFAULT_SOAK_WINDOW: &str = "300";
+pub const DEFAULT_P99_THRESHOLD: &str = "20";
+pub const DEFAULT_MEMORY_THRESHOLD: &str = "20";
+pub const DEFAULT_LOAD_RATE: &str = "10";
+pub const DEFAULT_LEVEL_RANGE: &str = "1000";
+pub const DEFAULT_RECOVERY_TIMEOUT: &str = "600";
+pub const DEFAULT_POLL_INTERVAL: &str = "1";
+pub const DEFAULT_HASH_SAMPLES: &str = "10";
+
+/// A single invalid or missing option
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigProblem {
+    Missing {
+        option: String,
+    },
+    Invalid {
+        option: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigProblem::Missing { option } => write!(f, "--{}: missing", option),
+            ConfigProblem::Invalid {
+                option,
+                value,
+                reason,
+            } => write!(f, "--{}: invalid value {:?}: {}", option, value, reason),
+        }
+    }
+}
+
+/// Every problem found while reading the args of a subcommand
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            writeln!(f, "    {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads the args of a subcommand, collecting the problems instead of failing on the first one
+struct ArgReader<'a, 'b> {
+    args: &'a clap::ArgMatches<'b>,
+    problems: Vec<ConfigProblem>,
+}
+
+impl<'a, 'b> ArgReader<'a, 'b> {
+    fn new(args: &'a clap::ArgMatches<'b>) -> Self {
+        Self {
+            args,
+            problems: Vec::new(),
+        }
+    }
+
+    fn missing(&mut self, option: &str) {
+        self.problems.push(ConfigProblem::Missing {
+            option: option.to_string(),
+        })
+    }
+
+    fn invalid(&mut self, option: &str, value: &str, reason: impl fmt::Display) {
+        self.problems.push(ConfigProblem::Invalid {
+            option: option.to_string(),
+            value: value.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Value of a required option, options with a default value are always present
+    fn value<T>(&mut self, option: &str) -> T
+    where
+        T: FromStr + Default,
+        T::Err: fmt::Display,
+    {
+        match self.args.value_of(option) {
+            Some(value) => value.parse().unwrap_or_else(|e| {
+                self.invalid(option, value, e);
+                T::default()
+            }),
+            None => {
+                self.missing(option);
+                T::default()
+            }
+        }
+    }
+
+    fn flag(&self, option: &str) -> bool {
+        self.args.is_present(option)
+    }
+
+    fn urls(&mut self, option: &str) -> Vec<Url> {
+        let values = self
+            .args
+            .values_of(option)
+            .map(|values| values.map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if values.is_empty() {
+            self.missing(option);
+        }
+        values
+            .iter()
+            .filter_map(|value| self.url(option, value))
+            .collect()
+    }
+
+    fn url(&mut self, option: &str, value: &str) -> Option<Url> {
+        match value
+            .parse()
+            .map_err(failure::Error::from)
+            .and_then(normalize_url)
+        {
+            Ok(url) => Some(url),
+            Err(e) => {
+                self.invalid(option, value, e);
+                None
+            }
+        }
+    }
+
+    fn has_problem(&self, option: &str) -> bool {
+        self.problems.iter().any(|problem| match problem {
+            ConfigProblem::Missing { option: o } | ConfigProblem::Invalid { option: o, .. } => {
+                o == option
+            }
+        })
+    }
+
+    /// Semantic check of an option, which has been read without problems
+    fn check(&mut self, option: &str, valid: bool, reason: &str) {
+        if valid || self.has_problem(option) {
+            return;
+        }
+        let value = self.args.value_of(option).unwrap_or("").to_string();
+        self.invalid(option, &value, reason);
+    }
+
+    /// Node urls from the --nodes args followed by the urls of the --target args
+    fn node_urls(&mut self) -> Vec<Url> {
+        let mut nodes = Vec::new();
+        if self.args.is_present("nodes") || !self.args.is_present("target") {
+            nodes = self.urls("nodes");
+        }
+        if self.args.is_present("target") {
+            nodes.extend(self.targets(&[]).into_iter().map(|target| target.url));
+        }
+        nodes
+    }
+
+    /// Targets from the --target args preceded by the legacy node url args
+    fn targets(&mut self, legacy: &[(&str, &str, BranchType)]) -> Vec<Target> {
+        let mut targets = Vec::new();
+
+        for (option, name, branch_type) in legacy {
+            if let Some(value) = self.args.value_of(option) {
+                if let Some(url) = self.url(option, value) {
+                    targets.push(Target::new(name, url, branch_type.clone()));
+                }
+            }
+        }
+
+        let values = self
+            .args
+            .values_of("target")
+            .map(|values| values.map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default();
+        for value in values {
+            match value.parse::<Target>().and_then(normalize_target) {
+                Ok(target) => targets.push(target),
+                Err(e) => self.invalid("target", &value, e),
+            }
+        }
+
+        if targets.is_empty() && !self.has_problem("target") {
+            self.missing("target");
+        }
+        for (i, target) in targets.iter().enumerate() {
+            if targets[..i].iter().any(|t| t.name == target.name) {
+                self.invalid("target", &target.name, "duplicate target name");
+            }
+        }
+        if targets
+            .iter()
+            .filter(|t| t.branch_type == BranchType::Stable)
+            .count()
+            > 1
+        {
+            self.invalid("target", "", "only one target can have the baseline role");
+        }
+
+        targets
+    }
+
+    /// Percentage of the responses sampled by the load runners
+    fn sample_rate(&mut self) -> f32 {
+        let sample_rate: f32 = self.value("sample-rate");
+        self.check(
+            "sample-rate",
+            (0.0..=100.0).contains(&sample_rate),
+            "must be between 0 and 100",
+        );
+        sample_rate
+    }
+
+    /// Thresholds of the regressions compared to the reference and their no-fail flags
+    fn gates(&mut self) -> Gates {
+        let max_latency_threshold: f32 = self.value("max-latency-threshold");
+        self.check(
+            "max-latency-threshold",
+            max_latency_threshold >= 0.0,
+            "must not be negative",
+        );
+        let throughput_threshold: f32 = self.value("throughput-threshold");
+        self.check(
+            "throughput-threshold",
+            (0.0..=100.0).contains(&throughput_threshold),
+            "must be between 0 and 100",
+        );
+        let error_threshold: f32 = self.value("error-threshold");
+        self.check(
+            "error-threshold",
+            (0.0..=100.0).contains(&error_threshold),
+            "must be between 0 and 100",
+        );
+
+        Gates {
+            max_latency_threshold: max_latency_threshold * 0.01,
+            throughput_threshold: throughput_threshold * 0.01,
+            error_threshold: error_threshold * 0.01,
+            latency_no_fail: self.flag("latency-no-fail"),
+            throughput_no_fail: self.flag("throughput-no-fail"),
+            errors_no_fail: self.flag("errors-no-fail"),
+        }
+    }
+
+    fn finish<T>(self, env: T) -> Result<T, ConfigError> {
+        if self.problems.is_empty() {
+            Ok(env)
+        } else {
+            Err(ConfigError {
+                problems: self.problems,
+            })
+        }
+    }
+}
+
+/// Only accepts http(s) urls and adds the trailing slash, so rpc paths can be appended directly
+fn normalize_url(mut url: Url) -> Result<Url, failure::Error> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        failure::bail!("expected a http or https url");
+    }
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Ok(url)
+}
+
+fn normalize_target(mut target: Target) -> Result<Target, failure::Error> {
+    target.url = normalize_url(target.url)?;
+    target.indexer = target.indexer.map(normalize_url).transpose()?;
+    Ok(target)
+}
+
+/// Legacy node arguments, which are translated into targets: (arg name, target name, role)
+const LEGACY_NODE_ARGS: [(&str, &str, BranchType); 3] = [
+    ("tezedge-old-node", "tezedge-old", BranchType::Stable),
+    ("ocaml-node", "ocaml", BranchType::Ocaml),
+    ("tezedge-new-node", "tezedge-new", BranchType::Feature),
+];
+
 pub struct SequentialTestEnv {
     pub cycles: i32,
     pub nodes: Vec<Url>,
+    pub scenario: Scenario,
+    pub duration_threshold: f32,
+    pub duration_no_fail: bool,
 }
 
 impl SequentialTestEnv {
-    pub fn from_args(args: &clap::ArgMatches) -> Self {
-        let nodes: Vec<Url> = if let Some(nodes) = args.values_of("nodes") {
-            nodes
-                .map(|v| {
-                    v.parse()
-                        .expect("Provided value cannot be converted into valid url")
-                })
-                .collect()
-        } else {
-            panic!("No nodes provided in the --nodes arg")
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let cycles = reader.value("cycles");
+        reader.check("cycles", cycles > 0, "must be greater than 0");
+        let nodes = reader.node_urls();
+        reader.check(
+            if args.is_present("target") {
+                "target"
+            } else {
+                "nodes"
+            },
+            nodes.len() == 2,
+            "exactly two nodes are compared",
+        );
+        let scenario = match args.value_of("scenario") {
+            Some(file) => Scenario::from_file(file).unwrap_or_else(|e| {
+                reader.invalid("scenario", file, e);
+                DEFAULT_SCENARIO.parse().expect("Invalid default scenario")
+            }),
+            None => DEFAULT_SCENARIO.parse().expect("Invalid default scenario"),
         };
 
-        SequentialTestEnv {
-            cycles: args
-                .value_of("cycles")
-                .unwrap_or("")
-                .parse::<i32>()
-                .expect("Provided value cannot be converted into valid i32"),
+        let duration_threshold: f32 = reader.value("duration-threshold");
+        reader.check(
+            "duration-threshold",
+            duration_threshold >= 0.0,
+            "must not be negative",
+        );
+        let duration_no_fail = reader.flag("duration-no-fail");
+
+        reader.finish(SequentialTestEnv {
+            cycles,
             nodes,
-        }
+            scenario,
+            duration_threshold: duration_threshold * 0.01,
+            duration_no_fail,
+        })
     }
 }
 
@@ -39,184 +367,793 @@ pub struct BootstrapEnv {
 }
 
 impl BootstrapEnv {
-    pub fn from_args(args: &clap::ArgMatches) -> Self {
-        let nodes: Vec<Url> = if let Some(nodes) = args.values_of("nodes") {
-            nodes
-                .map(|v| {
-                    v.parse()
-                        .expect("Provided value cannot be converted into valid url")
-                })
-                .collect()
-        } else {
-            panic!("No nodes provided in the --nodes arg")
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let level = reader.value("level");
+        reader.check("level", level > 0, "must be greater than 0");
+        let nodes = reader.node_urls();
+
+        reader.finish(BootstrapEnv { level, nodes })
+    }
+}
+
+pub struct BootstrapLoadEnv {
+    pub targets: Vec<Target>,
+    pub level: i32,
+    pub url_file: String,
+    /// Requests per second sent to every target
+    pub rate: f64,
+    pub level_range: i32,
+    /// Bootstrap durations of the targets without load in seconds, by their names
+    pub baselines: Vec<(String, f64)>,
+}
+
+impl BootstrapLoadEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let targets = reader.targets(&[]);
+        let level = reader.value("level");
+        reader.check("level", level > 0, "must be greater than 0");
+        let url_file = reader.value("url-file");
+        let rate: f64 = reader.value("rate");
+        reader.check("rate", rate > 0.0, "must be greater than 0");
+        let level_range = reader.value("level-range");
+        reader.check("level-range", level_range > 0, "must be greater than 0");
+
+        let mut baselines = Vec::new();
+        for value in args.values_of("baseline").into_iter().flatten() {
+            match value
+                .split_once('=')
+                .map(|(name, seconds)| (name, seconds.parse::<f64>()))
+            {
+                Some((name, Ok(seconds))) if seconds > 0.0 => {
+                    if targets.iter().any(|t| t.name == name) {
+                        baselines.push((name.to_string(), seconds))
+                    } else {
+                        reader.invalid("baseline", value, "unknown target name")
+                    }
+                }
+                _ => reader.invalid("baseline", value, "expected NAME=SECONDS"),
+            }
+        }
+
+        reader.finish(BootstrapLoadEnv {
+            targets,
+            level,
+            url_file,
+            rate,
+            level_range,
+            baselines,
+        })
+    }
+}
+
+pub struct ChaosTestEnv {
+    pub node: Url,
+    pub level: i32,
+    /// Levels the node is restarted at, ascending
+    pub restart_levels: Vec<i32>,
+    pub stop_command: String,
+    pub start_command: String,
+    pub recovery_timeout: u64,
+    pub poll_interval: u64,
+    pub hash_samples: i32,
+}
+
+impl ChaosTestEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let node = match args.value_of("node") {
+            Some(value) => reader.url("node", value),
+            None => {
+                reader.missing("node");
+                None
+            }
         };
+        let level = reader.value("level");
+        reader.check("level", level > 0, "must be greater than 0");
 
-        BootstrapEnv {
-            level: args
-                .value_of("level")
-                .unwrap_or("")
-                .parse::<i32>()
-                .expect("Provided value cannot be converted into valid i32"),
-            nodes,
+        let mut restart_levels = Vec::new();
+        for value in args.values_of("restart-at").into_iter().flatten() {
+            match value.parse::<i32>() {
+                Ok(restart_level) if restart_level > 0 && restart_level < level => {
+                    restart_levels.push(restart_level)
+                }
+                Ok(_) => reader.invalid(
+                    "restart-at",
+                    value,
+                    "must be greater than 0 and less than the level",
+                ),
+                Err(e) => reader.invalid("restart-at", value, e),
+            }
         }
+        if args.values_of("restart-at").is_none() {
+            reader.missing("restart-at");
+        }
+        restart_levels.sort_unstable();
+        restart_levels.dedup();
+
+        let (stop_command, start_command) = match (
+            args.value_of("container"),
+            args.value_of("stop-command"),
+            args.value_of("start-command"),
+        ) {
+            (Some(container), None, None) => (
+                format!("docker stop {}", container),
+                format!("docker start {}", container),
+            ),
+            (None, Some(stop), Some(start)) => (stop.to_string(), start.to_string()),
+            (Some(container), _, _) => {
+                reader.invalid(
+                    "container",
+                    container,
+                    "conflicts with --stop-command and --start-command",
+                );
+                Default::default()
+            }
+            (None, None, None) => {
+                reader.missing("container");
+                Default::default()
+            }
+            (None, Some(_), None) => {
+                reader.missing("start-command");
+                Default::default()
+            }
+            (None, None, Some(_)) => {
+                reader.missing("stop-command");
+                Default::default()
+            }
+        };
+
+        let recovery_timeout = reader.value("recovery-timeout");
+        reader.check(
+            "recovery-timeout",
+            recovery_timeout > 0,
+            "must be greater than 0",
+        );
+        let poll_interval = reader.value("poll-interval");
+        reader.check("poll-interval", poll_interval > 0, "must be greater than 0");
+        let hash_samples = reader.value("hash-samples");
+        reader.check("hash-samples", hash_samples > 0, "must be greater than 0");
+
+        reader.finish(ChaosTestEnv {
+            node: node.unwrap_or_else(|| Url::parse("http://localhost/").unwrap()),
+            level,
+            restart_levels,
+            stop_command,
+            start_command,
+            recovery_timeout,
+            poll_interval,
+            hash_samples,
+        })
     }
 }
 
-pub struct RpcPerformanceTestEnv {
-    pub ocaml_node: Url,
-    pub tezedge_new_node: Url,
-    pub tezedge_old_node: Option<Url>,
-    pub url_file: String,
-    pub wrk_test_duration: u64,
+/// Regression thresholds of the feature branches compared to a stable reference as
+/// fractions, a regression fails the test unless its no-fail flag is set
+#[derive(Debug, Clone, Copy)]
+pub struct Gates {
     pub max_latency_threshold: f32,
     pub throughput_threshold: f32,
+    /// Tolerated rise of the error rate
+    pub error_threshold: f32,
     pub latency_no_fail: bool,
     pub throughput_no_fail: bool,
+    pub errors_no_fail: bool,
+}
+
+pub struct RpcPerformanceTestEnv {
+    pub targets: Vec<Target>,
+    pub url_file: String,
+    pub wrk_test_duration: u64,
+    pub gates: Gates,
+    pub count_statuses: bool,
+    pub mixed_workload: bool,
+    pub connections: usize,
+    pub sweep: bool,
+    pub max_connections: usize,
+    pub sample_rate: f32,
+    pub warm_up: u64,
+    pub cold_warm: bool,
+    pub branch_order: BranchOrder,
+    pub seed: u64,
+    pub concurrent_branches: bool,
+    pub markdown: Option<String>,
 }
 
 pub struct RpcLatencyTestEnv {
-    pub ocaml_node: Url,
-    pub tezedge_new_node: Url,
-    pub tezedge_old_node: Option<Url>,
+    pub targets: Vec<Target>,
     pub url_file: String,
     pub wrk_test_duration: u64,
     pub wrk_request_rate: u64,
+    pub gates: Gates,
+    pub sample_rate: f32,
+    pub branch_order: BranchOrder,
+    pub seed: u64,
+    pub concurrent_branches: bool,
+    pub markdown: Option<String>,
 }
 
 impl RpcPerformanceTestEnv {
-    pub fn from_args(args: &clap::ArgMatches) -> Self {
-        RpcPerformanceTestEnv {
-            ocaml_node: args
-                .value_of("ocaml-node")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            tezedge_new_node: args
-                .value_of("tezedge-new-node")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            tezedge_old_node: {
-                if args.is_present("tezedge-old-node") {
-                    Some(
-                        args.value_of("tezedge-old-node")
-                            .unwrap_or("")
-                            .parse::<Url>()
-                            .expect("Provided value cannot be converted into valid url"),
-                    )
-                } else {
-                    None
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let targets = reader.targets(&LEGACY_NODE_ARGS);
+        let url_file = reader.value("url-file");
+        let wrk_test_duration = reader.value("wrk-test-duration");
+        reader.check(
+            "wrk-test-duration",
+            wrk_test_duration > 0,
+            "must be greater than 0",
+        );
+        let gates = reader.gates();
+        let count_statuses = reader.flag("count-statuses");
+        let sample_rate = reader.sample_rate();
+        let warm_up = reader.value("warm-up");
+        let cold_warm = reader.flag("cold-warm");
+        let branch_order = reader.value("branch-order");
+        let seed = reader.value("seed");
+        let concurrent_branches = reader.flag("concurrent-branches");
+        let mixed_workload = reader.flag("mixed-workload");
+        let connections = reader.value("connections");
+        reader.check("connections", connections > 0, "must be greater than 0");
+        let sweep = reader.flag("sweep");
+        reader.check(
+            "sweep",
+            !(sweep && mixed_workload),
+            "cannot be combined with --mixed-workload",
+        );
+        reader.check(
+            "sample-rate",
+            sample_rate == 0.0 || !(sweep || mixed_workload),
+            "cannot be combined with --sweep or --mixed-workload",
+        );
+        // the sweep and the mixed workload do not measure the branches in a scheduled order
+        let scheduled = !(sweep || mixed_workload);
+        reader.check(
+            "branch-order",
+            scheduled || branch_order == BranchOrder::Fixed,
+            "cannot be combined with --sweep or --mixed-workload",
+        );
+        reader.check(
+            "seed",
+            scheduled || args.occurrences_of("seed") == 0,
+            "cannot be combined with --sweep or --mixed-workload",
+        );
+        reader.check(
+            "concurrent-branches",
+            scheduled || !concurrent_branches,
+            "cannot be combined with --sweep or --mixed-workload",
+        );
+        let max_connections = reader.value("max-connections");
+        reader.check(
+            "max-connections",
+            max_connections > 0,
+            "must be greater than 0",
+        );
+
+        reader.finish(RpcPerformanceTestEnv {
+            targets,
+            url_file,
+            wrk_test_duration,
+            gates,
+            count_statuses,
+            mixed_workload,
+            connections,
+            sweep,
+            max_connections,
+            sample_rate: sample_rate * 0.01,
+            warm_up,
+            cold_warm,
+            branch_order,
+            seed,
+            concurrent_branches,
+            markdown: args.value_of("markdown").map(str::to_string),
+        })
+    }
+}
+
+impl RpcLatencyTestEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let targets = reader.targets(&LEGACY_NODE_ARGS);
+        let url_file = reader.value("url-file");
+        let wrk_test_duration = reader.value("wrk-test-duration");
+        reader.check(
+            "wrk-test-duration",
+            wrk_test_duration > 0,
+            "must be greater than 0",
+        );
+        let wrk_request_rate = reader.value("wrk-request-rate");
+        reader.check(
+            "wrk-request-rate",
+            wrk_request_rate > 0,
+            "must be greater than 0",
+        );
+        let gates = reader.gates();
+        let sample_rate = reader.sample_rate();
+        let branch_order = reader.value("branch-order");
+        let seed = reader.value("seed");
+        let concurrent_branches = reader.flag("concurrent-branches");
+
+        reader.finish(RpcLatencyTestEnv {
+            targets,
+            url_file,
+            wrk_test_duration,
+            wrk_request_rate,
+            gates,
+            sample_rate: sample_rate * 0.01,
+            branch_order,
+            seed,
+            concurrent_branches,
+            markdown: args.value_of("markdown").map(str::to_string),
+        })
+    }
+}
+
+pub struct CompareTestEnv {
+    pub targets: Vec<Target>,
+    pub url_file: String,
+}
+
+impl CompareTestEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let targets = reader.targets(&LEGACY_NODE_ARGS);
+        let url_file = reader.value("url-file");
+
+        reader.finish(CompareTestEnv { targets, url_file })
+    }
+}
+
+pub struct GenerateUrlsEnv {
+    pub node: Url,
+    pub level: i32,
+    pub cycles: i32,
+    pub blocks: usize,
+    pub max_urls: usize,
+    pub seed: u64,
+    pub output: String,
+}
+
+impl GenerateUrlsEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let node = match args.value_of("node") {
+            Some(value) => reader.url("node", value),
+            None => {
+                reader.missing("node");
+                None
+            }
+        };
+        let level = reader.value("level");
+        reader.check("level", level > 0, "must be greater than 0");
+        let cycles = reader.value("cycles");
+        reader.check("cycles", cycles > 0, "must be greater than 0");
+        let blocks = reader.value("blocks");
+        reader.check("blocks", blocks > 0, "must be greater than 0");
+        let max_urls = reader.value("max-urls");
+        reader.check("max-urls", max_urls > 0, "must be greater than 0");
+        let seed = reader.value("seed");
+        let output = reader.value("output");
+
+        reader.finish(GenerateUrlsEnv {
+            node: node.unwrap_or_else(|| Url::parse("http://localhost/").unwrap()),
+            level,
+            cycles,
+            blocks,
+            max_urls,
+            seed,
+            output,
+        })
+    }
+}
+
+pub struct ReplayEnv {
+    pub targets: Vec<Target>,
+    pub log: String,
+    pub speed: ReplaySpeed,
+    pub connections: usize,
+}
+
+impl ReplayEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let targets = reader.targets(&[]);
+        let log = reader.value("log");
+        let speed = reader.value("speed");
+        let connections = reader.value("connections");
+        reader.check("connections", connections > 0, "must be greater than 0");
+
+        reader.finish(ReplayEnv {
+            targets,
+            log,
+            speed,
+            connections,
+        })
+    }
+}
+
+pub struct SoakTestEnv {
+    pub targets: Vec<Target>,
+    pub url_file: String,
+    pub duration: u64,
+    pub window: u64,
+    pub connections: usize,
+    /// Local processes of the targets by their names, their memory is sampled every window
+    pub pids: Vec<(String, u32)>,
+    pub p99_threshold: f32,
+    pub throughput_threshold: f32,
+    /// Tolerated rise of the error rate
+    pub error_threshold: f32,
+    pub memory_threshold: f32,
+    pub degradation_no_fail: bool,
+}
+
+impl SoakTestEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let targets = reader.targets(&[]);
+        let url_file = reader.value("url-file");
+        let duration = reader.value("duration");
+        reader.check("duration", duration > 0, "must be greater than 0");
+        let window: u64 = reader.value("window");
+        reader.check(
+            "window",
+            window > 0 && window <= duration,
+            "must be greater than 0 and at most the duration",
+        );
+        let connections = reader.value("connections");
+        reader.check("connections", connections > 0, "must be greater than 0");
+
+        let mut pids = Vec::new();
+        for value in args.values_of("pid").into_iter().flatten() {
+            match value.split_once('=').map(|(name, pid)| (name, pid.parse())) {
+                Some((name, Ok(pid))) if targets.iter().any(|t| t.name == name) => {
+                    pids.push((name.to_string(), pid))
                 }
-            },
-            url_file: args
-                .value_of_lossy("url-file")
-                .expect("Missing URL file parameter")
-                .into_owned(),
-            wrk_test_duration: args
-                .value_of("wrk-test-duration")
-                .unwrap_or("")
-                .parse::<u64>()
-                .expect("Provided value cannot be converted into valid u64"),
-            max_latency_threshold: args
-                .value_of("max-latency-threshold")
-                .unwrap_or("")
-                .parse::<f32>()
-                .expect("Provided value cannot be converted into valid u64")
-                * 0.01,
-            throughput_threshold: args
-                .value_of("throughput-threshold")
-                .unwrap_or("")
-                .parse::<f32>()
-                .expect("Provided value cannot be converted into valid u64")
-                * 0.01,
-            latency_no_fail: args
-                .is_present("latency-no-fail"),
-            throughput_no_fail: args
-                .is_present("throughput-no-fail"),
+                Some((_, Ok(_))) => reader.invalid("pid", value, "unknown target name"),
+                _ => reader.invalid("pid", value, "expected NAME=PID"),
+            }
         }
+
+        let mut threshold = |option| {
+            let threshold: f32 = reader.value(option);
+            reader.check(option, threshold >= 0.0, "must not be negative");
+            threshold * 0.01
+        };
+        let p99_threshold = threshold("p99-threshold");
+        let throughput_threshold = threshold("throughput-threshold");
+        let error_threshold = threshold("error-threshold");
+        let memory_threshold = threshold("memory-threshold");
+        let degradation_no_fail = reader.flag("degradation-no-fail");
+
+        reader.finish(SoakTestEnv {
+            targets,
+            url_file,
+            duration,
+            window,
+            connections,
+            pids,
+            p99_threshold,
+            throughput_threshold,
+            error_threshold,
+            memory_threshold,
+            degradation_no_fail,
+        })
     }
 }
 
-impl RpcLatencyTestEnv {
-    pub fn from_args(args: &clap::ArgMatches) -> Self {
-        RpcLatencyTestEnv {
-            ocaml_node: args
-                .value_of("ocaml-node")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            tezedge_new_node: args
-                .value_of("tezedge-new-node")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            tezedge_old_node: {
-                if args.is_present("tezedge-old-node") {
-                    Some(
-                        args.value_of("tezedge-old-node")
-                            .unwrap_or("")
-                            .parse::<Url>()
-                            .expect("Provided value cannot be converted into valid url"),
-                    )
-                } else {
-                    None
+/// Phases run by the run-suite subcommand when none are configured
+pub const DEFAULT_PHASES: [&str; 4] = [
+    "bootstrap",
+    "compare-test",
+    "performance-test",
+    "latency-test",
+];
+
+pub struct RunSuiteEnv {
+    pub phases: Vec<String>,
+    pub continue_on_failure: Vec<String>,
+    pub report: Option<String>,
+}
+
+impl RunSuiteEnv {
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let values = |option| -> Vec<String> {
+            args.values_of(option)
+                .map(|values| values.map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+        let mut phases = values("phases");
+        if phases.is_empty() {
+            phases = DEFAULT_PHASES.iter().map(|p| p.to_string()).collect();
+        }
+        let continue_on_failure = values("continue-on-failure");
+
+        for (option, values) in [
+            ("phases", &phases),
+            ("continue-on-failure", &continue_on_failure),
+        ] {
+            for phase in values {
+                if !SUITE_PHASES.contains(&phase.as_str()) {
+                    reader.invalid(
+                        option,
+                        phase,
+                        format!("expected one of {}", SUITE_PHASES.join(", ")),
+                    );
                 }
-            },
-            url_file: args
-                .value_of_lossy("url-file")
-                .expect("Missing URL file parameter")
-                .into_owned(),
-            wrk_test_duration: args
-                .value_of("wrk-test-duration")
-                .unwrap_or("")
-                .parse::<u64>()
-                .expect("Provided value cannot be converted into valid u64"),
-            wrk_request_rate: args
-                .value_of("wrk-request-rate")
-                .unwrap_or("")
-                .parse::<u64>()
-                .expect("Provided value cannot be converted into valid u64"),
+            }
         }
+
+        reader.finish(RunSuiteEnv {
+            phases,
+            continue_on_failure,
+            report: args.value_of("report").map(str::to_string),
+        })
     }
 }
 
+/// Subcommands, which can be run as phases of the suite
+pub const SUITE_PHASES: [&str; 11] = [
+    "bootstrap",
+    "generate-urls",
+    "compare-test",
+    "indexer-test",
+    "performance-test",
+    "latency-test",
+    "sequential-test",
+    "replay",
+    "soak-test",
+    "bootstrap-load",
+    "chaos-test",
+];
+
 pub struct IndexerTestEnv {
     pub level: i32,
-    pub ocaml_node: Url,
-    pub tezedge_node: Url,
-    pub tezedge_indexer: Url,
-    pub ocaml_indexer: Url,
+    pub targets: Vec<Target>,
 }
 
+/// Legacy node and indexer arguments, which are translated into targets: (node arg, indexer arg, target name, role)
+const LEGACY_INDEXER_ARGS: [(&str, &str, &str, BranchType); 2] = [
+    ("ocaml-node", "ocaml-indexer", "ocaml", BranchType::Ocaml),
+    (
+        "tezedge-node",
+        "tezedge-indexer",
+        "tezedge",
+        BranchType::Feature,
+    ),
+];
+
 impl IndexerTestEnv {
-    pub fn from_args(args: &clap::ArgMatches) -> Self {
-        IndexerTestEnv {
-            level: args
-                .value_of("level")
-                .unwrap_or("")
-                .parse::<i32>()
-                .expect("Provided value cannot be converted into valid i32"),
-            ocaml_node: args
-                .value_of("ocaml-node")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            tezedge_node: args
-                .value_of("tezedge-node")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            tezedge_indexer: args
-                .value_of("tezedge-indexer")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
-            ocaml_indexer: args
-                .value_of("ocaml-indexer")
-                .unwrap_or("")
-                .parse()
-                .expect("Provided value cannot be converted into valid url"),
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Self, ConfigError> {
+        let mut reader = ArgReader::new(args);
+
+        let level = reader.value("level");
+        reader.check("level", level > 0, "must be greater than 0");
+
+        let legacy_nodes = LEGACY_INDEXER_ARGS
+            .iter()
+            .map(|(node_arg, _, name, branch_type)| (*node_arg, *name, branch_type.clone()))
+            .collect::<Vec<_>>();
+        let mut targets = reader.targets(&legacy_nodes);
+
+        for (_, indexer_arg, name, _) in &LEGACY_INDEXER_ARGS {
+            if let Some(value) = args.value_of(indexer_arg) {
+                let indexer = reader.url(indexer_arg, value);
+                if let Some(target) = targets.iter_mut().find(|t| &t.name == name) {
+                    target.indexer = indexer;
+                }
+            }
+        }
+        for target in targets.iter().filter(|t| t.indexer.is_none()) {
+            reader.invalid("target", &target.name, "missing indexer url");
+        }
+
+        reader.finish(IndexerTestEnv { level, targets })
+    }
+}
+
+/// A named target of the suite configuration file, see [`Target`]
+#[derive(Deserialize, Debug, Clone)]
+pub struct SuiteTarget {
+    pub name: String,
+    pub url: String,
+    pub role: Option<String>,
+    pub indexer: Option<String>,
+}
+
+impl SuiteTarget {
+    fn to_arg(&self) -> String {
+        let mut arg = format!("{}={}", self.name, self.url);
+        if let Some(role) = &self.role {
+            arg.push_str(&format!(",role={}", role));
+        }
+        if let Some(indexer) = &self.indexer {
+            arg.push_str(&format!(",indexer={}", indexer));
+        }
+        arg
+    }
+}
+
+/// Test suite described by a TOML file passed in the --config arg
+///
+/// ```toml
+/// run = ["bootstrap", "performance-test"]
+///
+/// [[target]]
+/// name = "ocaml"
+/// url = "http://ocaml-node:8732/"
+/// role = "ocaml"
+///
+/// [bootstrap]
+/// level = 1000
+/// nodes = ["http://ocaml-node:8732/"]
+///
+/// [performance-test]
+/// url-file = "/urls.txt"
+/// wrk-test-duration = 10
+/// ```
+///
+/// Every subcommand section holds the subcommand's args by their long name, the optional
+/// `targets` key limits the section to a list of target names. Args given on the command line
+/// take precedence over the file.
+#[derive(Deserialize, Debug, Default)]
+pub struct SuiteConfig {
+    #[serde(default)]
+    pub run: Vec<String>,
+
+    #[serde(default, rename = "target")]
+    pub targets: Vec<SuiteTarget>,
+
+    #[serde(flatten)]
+    pub sections: HashMap<String, toml::value::Table>,
+}
+
+impl SuiteConfig {
+    pub fn from_file(file: &str) -> Result<Self, failure::Error> {
+        Ok(toml::from_str(&fs::read_to_string(file)?)?)
+    }
+
+    /// Removes the key from every section, or only from the section of a `SECTION.KEY`, so
+    /// a flag of the file can be turned off on the command line
+    pub fn unset(&mut self, key: &str) {
+        match key.split_once('.') {
+            Some((section, key)) => {
+                if let Some(section) = self.sections.get_mut(section) {
+                    section.remove(key);
+                }
+            }
+            None => {
+                for section in self.sections.values_mut() {
+                    section.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Command line of the subcommand chosen on the command line, or of `run-suite` when
+    /// no subcommand is chosen, merged with its section of the file
+    pub fn command_line(&self, matches: &clap::ArgMatches, args: &[String]) -> Vec<String> {
+        match matches.subcommand() {
+            (name, Some(cli_matches)) => {
+                let cli_args = args
+                    .iter()
+                    .skip(1)
+                    .skip_while(|arg| arg.as_str() != name)
+                    .skip(1)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                self.invocation(&args[0], name, Some(cli_matches), &cli_args)
+            }
+            _ => self.invocation(&args[0], "run-suite", None, &[]),
+        }
+    }
+
+    /// Command line of a single phase of the suite
+    pub fn phase_command_line(&self, bin: &str, phase: &str) -> Vec<String> {
+        self.invocation(bin, phase, None, &[])
+    }
+
+    fn invocation(
+        &self,
+        bin: &str,
+        name: &str,
+        cli_matches: Option<&clap::ArgMatches>,
+        cli_args: &[String],
+    ) -> Vec<String> {
+        let given_on_cli = |arg: &str| cli_matches.is_some_and(|m| m.occurrences_of(arg) > 0);
+        let mut invocation = vec![bin.to_string(), name.to_string()];
+
+        let empty = toml::value::Table::new();
+        let section = self.sections.get(name).unwrap_or(&empty);
+
+        if SUBCOMMANDS_WITH_TARGETS.contains(&name) && !given_on_cli("target") {
+            for target in self.selected_targets(section) {
+                invocation.push(format!("--target={}", target.to_arg()));
+            }
+        }
+
+        // the targets are shared with the subcommands watching plain node urls
+        if SUBCOMMANDS_WITH_NODES.contains(&name)
+            && !given_on_cli("nodes")
+            && !given_on_cli("target")
+            && !section.contains_key("nodes")
+        {
+            for target in self.selected_targets(section) {
+                invocation.push(format!("--nodes={}", target.url));
+            }
+        }
+
+        if name == "run-suite" && !given_on_cli("phases") && !section.contains_key("phases") {
+            for phase in &self.run {
+                invocation.push(format!("--phases={}", phase));
+            }
+        }
+
+        for (key, value) in section.iter().filter(|(key, _)| key.as_str() != "targets") {
+            match value {
+                toml::Value::Boolean(true) if !given_on_cli(key) => {
+                    invocation.push(format!("--{}", key))
+                }
+                toml::Value::Boolean(_) => (),
+                toml::Value::Array(values) if !given_on_cli(key) => {
+                    for value in values {
+                        invocation.push(format!("--{}={}", key, toml_arg_value(value)));
+                    }
+                }
+                toml::Value::Array(_) => (),
+                value => invocation.push(format!("--{}={}", key, toml_arg_value(value))),
+            }
         }
+
+        // args given later override the ones from the file, see AllArgsOverrideSelf
+        invocation.extend(cli_args.iter().cloned());
+        invocation
+    }
+
+    /// Targets listed in the `targets` key of the section, all targets by default
+    fn selected_targets<'a>(
+        &'a self,
+        section: &'a toml::value::Table,
+    ) -> impl Iterator<Item = &'a SuiteTarget> + 'a {
+        let selected = section.get("targets").and_then(|v| v.as_array());
+        self.targets.iter().filter(move |target| {
+            selected.is_none_or(|names| {
+                names
+                    .iter()
+                    .any(|n| n.as_str() == Some(target.name.as_str()))
+            })
+        })
+    }
+}
+
+const SUBCOMMANDS_WITH_TARGETS: [&str; 7] = [
+    "performance-test",
+    "latency-test",
+    "indexer-test",
+    "compare-test",
+    "replay",
+    "soak-test",
+    "bootstrap-load",
+];
+
+const SUBCOMMANDS_WITH_NODES: [&str; 2] = ["bootstrap", "sequential-test"];
+
+fn toml_arg_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string(),
     }
 }
 
@@ -226,14 +1163,73 @@ pub fn bootstrap_app() -> App<'static, 'static> {
         .author("Adrian Nagy")
         .about("CI bootstraping and testing app")
         .setting(clap::AppSettings::AllArgsOverrideSelf)
+        .arg(
+            Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("TOML file describing the targets and the args of the subcommands; without a subcommand, runs the subcommands listed in its `run` key")
+        )
+        .arg(
+            Arg::with_name("unset")
+            .long("unset")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("[SECTION.]KEY")
+            .help("Ignores the key of the sections of the --config file, e.g. to turn off a flag set in the file")
+        )
+        .arg(
+            Arg::with_name("html-report")
+            .long("html-report")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Writes a self-contained html report with the latency charts, the bootstrap progress and the indexer mismatches of the run to the file")
+        )
+        .arg(
+            Arg::with_name("metrics-listen")
+            .long("metrics-listen")
+            .takes_value(true)
+            .value_name("ADDRESS")
+            .help("Serves the live metrics of the run in the Prometheus format on http://ADDRESS/metrics, e.g. 0.0.0.0:9100")
+        )
+        .arg(
+            Arg::with_name("metrics-file")
+            .long("metrics-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Rewrites the live metrics of the run every 5s to the file, for the textfile collector of the node exporter")
+        )
+        .arg(
+            Arg::with_name("influx")
+            .long("influx")
+            .takes_value(true)
+            .value_name("FILE|udp://HOST:PORT|URL")
+            .help("Writes every measurement in the InfluxDB line protocol to the file, the udp socket or the http write endpoint")
+        )
+        .arg(
+            Arg::with_name("commit")
+            .long("commit")
+            .takes_value(true)
+            .value_name("SHA")
+            .help("Commit tagging the measurements written to the influx sink")
+        )
         .subcommand(
             SubCommand::with_name("performance-test")
                 .about("Performance test using wrk")
                 .setting(clap::AppSettings::AllArgsOverrideSelf)
+                .arg(
+                    Arg::with_name("target")
+                    .long("target")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("NAME=URL[,role=ROLE]")
+                    .help("Labeled node to compare, ROLE is baseline, feature (default) or ocaml; every node is compared against the baseline")
+                )
                 .arg(
                     Arg::with_name("ocaml-node")
                     .long("ocaml-node")
-                    .required(true)
                     .takes_value(true)
                     .value_name("STRING")
                     .help("Ocaml node url")
@@ -241,7 +1237,6 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .arg(
                     Arg::with_name("tezedge-new-node")
                     .long("tezedge-new-node")
-                    .required(true)
                     .takes_value(true)
                     .value_name("STRING")
                     .help("Tezedge node url - with updated code from the pull request")
@@ -256,7 +1251,6 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .arg(
                     Arg::with_name("url-file")
                     .long("url-file")
-                    .required(true)
                     .takes_value(true)
                     .value_name("FILE")
                     .help("File containing a list of URLs to test")
@@ -265,13 +1259,15 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                     Arg::with_name("wrk-test-duration")
                     .long("wrk-test-duration")
                     .takes_value(true)
+                    .default_value(DEFAULT_WRK_TEST_DURATION)
                     .value_name("NUM")
-                    .help("Duration of the individual tests")
+                    .help("Duration of the individual tests in seconds")
                 )
                 .arg(
                     Arg::with_name("max-latency-threshold")
                     .long("max-latency-threshold")
                     .takes_value(true)
+                    .default_value(DEFAULT_MAX_LATENCY_THRESHOLD)
                     .value_name("NUM")
                     .help("Maximum tail latency delta between two node versions allowed in percentages")
                 )
@@ -279,30 +1275,138 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                     Arg::with_name("throughput-threshold")
                     .long("throughput-threshold")
                     .takes_value(true)
+                    .default_value(DEFAULT_THROUGHPUT_THRESHOLD)
                     .value_name("NUM")
                     .help("Maximum throughput delta between two node versions allowed in percentages")
                 )
                 .arg(
-                    Arg::with_name("latency-no-fail")
-                    .long("latency-no-fail")
-                    .takes_value(false)
+                    Arg::with_name("error-threshold")
+                    .long("error-threshold")
+                    .takes_value(true)
+                    .default_value(DEFAULT_ERROR_THRESHOLD)
+                    .value_name("NUM")
+                    .help("Maximum rise of the error rate over the baseline allowed in percentage points")
+                )
+                .arg(
+                    Arg::with_name("latency-no-fail")
+                    .long("latency-no-fail")
+                    .takes_value(false)
                     .help("Do not fail the test if max latency exceeds the threshold")
                 )
                 .arg(
                     Arg::with_name("throughput-no-fail")
                     .long("throughput-no-fail")
                     .takes_value(false)
-                    .help("Do not fail the test if max latency exceeds the threshold")
+                    .help("Do not fail the test if throughput regression exceeds the threshold")
+                )
+                .arg(
+                    Arg::with_name("errors-no-fail")
+                    .long("errors-no-fail")
+                    .takes_value(false)
+                    .help("Do not fail the test if the error rate exceeds the threshold")
+                )
+                .arg(
+                    Arg::with_name("mixed-workload")
+                    .long("mixed-workload")
+                    .takes_value(false)
+                    .help("Requests a weighted random mix of all urls concurrently instead of testing one url at a time, a url can be followed by its weight in the url file")
+                )
+                .arg(
+                    Arg::with_name("connections")
+                    .long("connections")
+                    .takes_value(true)
+                    .default_value(DEFAULT_CONNECTIONS)
+                    .value_name("NUM")
+                    .help("Number of concurrent connections of the mixed workload")
+                )
+                .arg(
+                    Arg::with_name("sweep")
+                    .long("sweep")
+                    .takes_value(false)
+                    .help("Ramps the wrk connections 1, 2, 4, ... up to --max-connections for every url to find the saturation point of every node")
+                )
+                .arg(
+                    Arg::with_name("max-connections")
+                    .long("max-connections")
+                    .takes_value(true)
+                    .default_value(DEFAULT_MAX_CONNECTIONS)
+                    .value_name("NUM")
+                    .help("Number of connections of the last step of the sweep")
+                )
+                .arg(
+                    Arg::with_name("warm-up")
+                    .long("warm-up")
+                    .takes_value(true)
+                    .default_value("0")
+                    .value_name("NUM")
+                    .help("Duration of the warm-up of every url and node in seconds, its results are discarded")
+                )
+                .arg(
+                    Arg::with_name("cold-warm")
+                    .long("cold-warm")
+                    .takes_value(false)
+                    .help("Measures a single cold request of every url and node before the warm-up and reports it separately")
+                )
+                .arg(
+                    Arg::with_name("count-statuses")
+                    .long("count-statuses")
+                    .takes_value(false)
+                    .help("Counts the http statuses of the responses, which slows wrk down")
+                )
+                .arg(
+                    Arg::with_name("sample-rate")
+                    .long("sample-rate")
+                    .takes_value(true)
+                    .default_value("0")
+                    .value_name("NUM")
+                    .help("Percentage of the responses compared to the ocaml node, or the reference node, during the test")
+                )
+                .arg(
+                    Arg::with_name("branch-order")
+                    .long("branch-order")
+                    .takes_value(true)
+                    .default_value("fixed")
+                    .value_name("fixed|random|round-robin")
+                    .help("Order the nodes are measured in for every url: the order of the targets, a seeded random order or the order rotated by one for every url")
+                )
+                .arg(
+                    Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .default_value(DEFAULT_SEED)
+                    .value_name("NUM")
+                    .help("Seed of the random branch order")
+                )
+                .arg(
+                    Arg::with_name("concurrent-branches")
+                    .long("concurrent-branches")
+                    .takes_value(false)
+                    .help("Measures all nodes at once, only meaningful when the nodes run on isolated machines")
+                )
+                .arg(
+                    Arg::with_name("markdown")
+                    .long("markdown")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Writes the summary table as markdown to the file, e.g. for a pull request comment")
                 )
             )
         .subcommand(
             SubCommand::with_name("latency-test")
                 .about("Latency test using wrk2")
                 .setting(clap::AppSettings::AllArgsOverrideSelf)
+                .arg(
+                    Arg::with_name("target")
+                    .long("target")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("NAME=URL[,role=ROLE]")
+                    .help("Labeled node to compare, ROLE is baseline, feature (default) or ocaml; every node is compared against the baseline")
+                )
                 .arg(
                     Arg::with_name("ocaml-node")
                     .long("ocaml-node")
-                    .required(true)
                     .takes_value(true)
                     .value_name("STRING")
                     .help("Ocaml node url")
@@ -310,7 +1414,6 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .arg(
                     Arg::with_name("tezedge-new-node")
                     .long("tezedge-new-node")
-                    .required(true)
                     .takes_value(true)
                     .value_name("STRING")
                     .help("Tezedge node url - with updated code from the pull request")
@@ -325,7 +1428,6 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .arg(
                     Arg::with_name("url-file")
                     .long("url-file")
-                    .required(true)
                     .takes_value(true)
                     .value_name("FILE")
                     .help("File containing a list of URLs to test")
@@ -334,17 +1436,119 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                     Arg::with_name("wrk-test-duration")
                     .long("wrk-test-duration")
                     .takes_value(true)
+                    .default_value(DEFAULT_WRK_TEST_DURATION)
                     .value_name("NUM")
-                    .help("Duration of the individual tests")
+                    .help("Duration of the individual tests in seconds")
                 )
                 .arg(
                     Arg::with_name("wrk-request-rate")
                     .long("wrk-request-rate")
                     .takes_value(true)
+                    .default_value(DEFAULT_WRK_REQUEST_RATE)
+                    .value_name("NUM")
+                    .help("Request rate for the individual test in requests per second")
+                )
+                .arg(
+                    Arg::with_name("max-latency-threshold")
+                    .long("max-latency-threshold")
+                    .takes_value(true)
+                    .default_value(DEFAULT_MAX_LATENCY_THRESHOLD)
+                    .value_name("NUM")
+                    .help("Maximum tail latency delta between two node versions allowed in percentages")
+                )
+                .arg(
+                    Arg::with_name("throughput-threshold")
+                    .long("throughput-threshold")
+                    .takes_value(true)
+                    .default_value(DEFAULT_THROUGHPUT_THRESHOLD)
+                    .value_name("NUM")
+                    .help("Maximum throughput delta between two node versions allowed in percentages")
+                )
+                .arg(
+                    Arg::with_name("error-threshold")
+                    .long("error-threshold")
+                    .takes_value(true)
+                    .default_value(DEFAULT_ERROR_THRESHOLD)
+                    .value_name("NUM")
+                    .help("Maximum rise of the error rate over the baseline allowed in percentage points")
+                )
+                .arg(
+                    Arg::with_name("latency-no-fail")
+                    .long("latency-no-fail")
+                    .takes_value(false)
+                    .help("Do not fail the test if max latency exceeds the threshold")
+                )
+                .arg(
+                    Arg::with_name("throughput-no-fail")
+                    .long("throughput-no-fail")
+                    .takes_value(false)
+                    .help("Do not fail the test if throughput regression exceeds the threshold")
+                )
+                .arg(
+                    Arg::with_name("errors-no-fail")
+                    .long("errors-no-fail")
+                    .takes_value(false)
+                    .help("Do not fail the test if the error rate exceeds the threshold")
+                )
+                .arg(
+                    Arg::with_name("sample-rate")
+                    .long("sample-rate")
+                    .takes_value(true)
+                    .default_value("0")
                     .value_name("NUM")
-                    .help("Request rate for the individual test")
+                    .help("Percentage of the responses compared to the ocaml node, or the reference node, during the test")
+                )
+                .arg(
+                    Arg::with_name("branch-order")
+                    .long("branch-order")
+                    .takes_value(true)
+                    .default_value("fixed")
+                    .value_name("fixed|random|round-robin")
+                    .help("Order the nodes are measured in for every url: the order of the targets, a seeded random order or the order rotated by one for every url")
                 )
+                .arg(
+                    Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .default_value(DEFAULT_SEED)
+                    .value_name("NUM")
+                    .help("Seed of the random branch order")
+                )
+                .arg(
+                    Arg::with_name("concurrent-branches")
+                    .long("concurrent-branches")
+                    .takes_value(false)
+                    .help("Measures all nodes at once, only meaningful when the nodes run on isolated machines")
+                )
+                .arg(
+                    Arg::with_name("markdown")
+                    .long("markdown")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Writes the summary table as markdown to the file, e.g. for a pull request comment")
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("compare-test")
+            .about("Compares the responses of the nodes to the reference node")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL[,role=ROLE]")
+                .help("Labeled node to compare, ROLE is baseline, feature (default) or ocaml; every node is compared against the baseline")
+            )
+            .arg(
+                Arg::with_name("url-file")
+                .long("url-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File containing a list of URLs to compare")
             )
+        )
         .subcommand(
             SubCommand::with_name("indexer-test")
             .about("Indexer correctness test")
@@ -356,6 +1560,15 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .value_name("NUM")
                 .help("Block level which is used in the test as an upper bound")
             )
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL,indexer=URL[,role=ROLE]")
+                .help("Labeled node with its indexer to compare, ROLE is baseline, feature (default) or ocaml")
+            )
             .arg(
                 Arg::with_name("ocaml-node")
                 .long("ocaml-node")
@@ -405,10 +1618,145 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .value_name("STRING")
                 .help("Node urls to be bootstrapped")
             )
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL[,role=ROLE]")
+                .help("Labeled node to be bootstrapped, an alternative to --nodes")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("bootstrap-load")
+            .about("Bootstraps the targets under a constant rate of rpc requests and reports their latency by level")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL[,role=ROLE]")
+                .help("Labeled node to be bootstrapped under load")
+            )
+            .arg(
+                Arg::with_name("level")
+                .long("level")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Block level which is used in the test as an upper bound")
+            )
+            .arg(
+                Arg::with_name("url-file")
+                .long("url-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File containing the URLs of the load with their optional weights")
+            )
+            .arg(
+                Arg::with_name("rate")
+                .long("rate")
+                .takes_value(true)
+                .default_value(DEFAULT_LOAD_RATE)
+                .value_name("NUM")
+                .help("Requests per second sent to every target while it is bootstrapping")
+            )
+            .arg(
+                Arg::with_name("level-range")
+                .long("level-range")
+                .takes_value(true)
+                .default_value(DEFAULT_LEVEL_RANGE)
+                .value_name("NUM")
+                .help("Number of levels in the ranges of the report")
+            )
+            .arg(
+                Arg::with_name("baseline")
+                .long("baseline")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=SECONDS")
+                .help("Bootstrap duration of the target without load, e.g. from a previous bootstrap run, the slowdown is only reported for the targets with a baseline")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("chaos-test")
+            .about("Restarts a bootstrapping node at chosen levels and verifies it recovers from its persisted state")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("node")
+                .long("node")
+                .takes_value(true)
+                .value_name("STRING")
+                .help("Node url to be bootstrapped and restarted")
+            )
+            .arg(
+                Arg::with_name("level")
+                .long("level")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Block level which is used in the test as an upper bound")
+            )
+            .arg(
+                Arg::with_name("restart-at")
+                .long("restart-at")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NUM")
+                .help("Level the node is restarted at, when its head reaches it")
+            )
+            .arg(
+                Arg::with_name("container")
+                .long("container")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Docker container of the node, stopped and started with docker")
+            )
+            .arg(
+                Arg::with_name("stop-command")
+                .long("stop-command")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Shell command stopping the node")
+            )
+            .arg(
+                Arg::with_name("start-command")
+                .long("start-command")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Shell command starting the stopped node")
+            )
+            .arg(
+                Arg::with_name("recovery-timeout")
+                .long("recovery-timeout")
+                .takes_value(true)
+                .default_value(DEFAULT_RECOVERY_TIMEOUT)
+                .value_name("NUM")
+                .help("Seconds the node has to stop, and to get back to its level after the start")
+            )
+            .arg(
+                Arg::with_name("poll-interval")
+                .long("poll-interval")
+                .takes_value(true)
+                .default_value(DEFAULT_POLL_INTERVAL)
+                .value_name("NUM")
+                .help("Seconds between the requests of the head level")
+            )
+            .arg(
+                Arg::with_name("hash-samples")
+                .long("hash-samples")
+                .takes_value(true)
+                .default_value(DEFAULT_HASH_SAMPLES)
+                .value_name("NUM")
+                .help("Number of levels up to the head, whose block hashes are compared before and after a restart")
+            )
         )
         .subcommand(
             SubCommand::with_name("sequential-test")
-            .about("Checks and waits for two nodes to be bootstrapped to the same level")
+            .about("Compares the durations and responses of a sequence of rpc requests on two nodes")
             .setting(clap::AppSettings::AllArgsOverrideSelf)
             .arg(
                 Arg::with_name("cycles")
@@ -417,6 +1765,27 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .value_name("NUM")
                 .help("Number of cycles to test")
             )
+            .arg(
+                Arg::with_name("scenario")
+                .long("scenario")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("TOML file with the [[request]] templates to run, defaults to the baking rights of every cycle")
+            )
+            .arg(
+                Arg::with_name("duration-threshold")
+                .long("duration-threshold")
+                .takes_value(true)
+                .default_value(DEFAULT_DURATION_THRESHOLD)
+                .value_name("NUM")
+                .help("Maximum total duration delta of the second node compared to the first one allowed in percentages")
+            )
+            .arg(
+                Arg::with_name("duration-no-fail")
+                .long("duration-no-fail")
+                .takes_value(false)
+                .help("Do not fail the test if the total duration regression exceeds the threshold")
+            )
             .arg(
                 Arg::with_name("nodes")
                 .long("nodes")
@@ -425,8 +1794,530 @@ pub fn bootstrap_app() -> App<'static, 'static> {
                 .min_values(2)
                 .max_values(2)
                 .value_name("STRING")
-                .help("Node urls to be bootstrapped")
+                .help("Node urls to compare, the second node is compared to the first one")
+            )
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL[,role=ROLE]")
+                .help("Labeled node to compare, an alternative to --nodes; the second node is compared to the first one")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("generate-urls")
+            .about("Crawls a node and writes a list of rpc urls for the url-file of the tests")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("node")
+                .long("node")
+                .takes_value(true)
+                .value_name("STRING")
+                .help("Node url to crawl")
+            )
+            .arg(
+                Arg::with_name("level")
+                .long("level")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Last block level crawled, the contracts, delegates and rights are requested from this level")
+            )
+            .arg(
+                Arg::with_name("cycles")
+                .long("cycles")
+                .takes_value(true)
+                .default_value(DEFAULT_GENERATED_CYCLES)
+                .value_name("NUM")
+                .help("Number of cycles up to the cycle of the level to request the baking and endorsing rights of")
+            )
+            .arg(
+                Arg::with_name("blocks")
+                .long("blocks")
+                .takes_value(true)
+                .default_value(DEFAULT_GENERATED_BLOCKS)
+                .value_name("NUM")
+                .help("Number of blocks spread evenly up to the level to crawl")
+            )
+            .arg(
+                Arg::with_name("max-urls")
+                .long("max-urls")
+                .takes_value(true)
+                .default_value(DEFAULT_MAX_URLS)
+                .value_name("NUM")
+                .help("Maximum number of urls written, shared evenly by the blocks, rights, contracts and delegates")
+            )
+            .arg(
+                Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .default_value(DEFAULT_SEED)
+                .value_name("NUM")
+                .help("Seed of the order of the urls, the same seed generates the same list from the same chain")
+            )
+            .arg(
+                Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .default_value("-")
+                .value_name("FILE")
+                .help("File the urls are written to, - for stdout")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+            .about("Replays a request log against the targets and reports their latencies and error rates")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL[,role=ROLE]")
+                .help("Labeled node to replay the log against")
+            )
+            .arg(
+                Arg::with_name("log")
+                .long("log")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Request log, JSONL with time, method and path fields or a common access log")
+            )
+            .arg(
+                Arg::with_name("speed")
+                .long("speed")
+                .takes_value(true)
+                .default_value("1")
+                .value_name("FACTOR")
+                .help("Speed of the replay compared to the original pacing, or max to send the requests as fast as possible")
+            )
+            .arg(
+                Arg::with_name("connections")
+                .long("connections")
+                .takes_value(true)
+                .default_value(DEFAULT_CONNECTIONS)
+                .value_name("NUM")
+                .help("Maximum number of concurrent requests")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("soak-test")
+            .about("Keeps a steady mixed load on the targets for hours and fails on degradation trends")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=URL[,role=ROLE]")
+                .help("Labeled node to soak, all targets are loaded at the same time")
+            )
+            .arg(
+                Arg::with_name("url-file")
+                .long("url-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File containing the URLs of the mix with their optional weights")
+            )
+            .arg(
+                Arg::with_name("duration")
+                .long("duration")
+                .takes_value(true)
+                .default_value(DEFAULT_SOAK_DURATION)
+                .value_name("NUM")
+                .help("Duration of the test in seconds")
+            )
+            .arg(
+                Arg::with_name("window")
+                .long("window")
+                .takes_value(true)
+                .default_value(DEFAULT_SOAK_WINDOW)
+                .value_name("NUM")
+                .help("Duration of the windows of the snapshots in seconds")
+            )
+            .arg(
+                Arg::with_name("connections")
+                .long("connections")
+                .takes_value(true)
+                .default_value(DEFAULT_CONNECTIONS)
+                .value_name("NUM")
+                .help("Number of concurrent connections to every target")
+            )
+            .arg(
+                Arg::with_name("pid")
+                .long("pid")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=PID")
+                .help("Local process of the target, its resident memory is sampled every window")
+            )
+            .arg(
+                Arg::with_name("p99-threshold")
+                .long("p99-threshold")
+                .takes_value(true)
+                .default_value(DEFAULT_P99_THRESHOLD)
+                .value_name("NUM")
+                .help("Highest rise of the p99 latency from the first to the last window in percent")
+            )
+            .arg(
+                Arg::with_name("throughput-threshold")
+                .long("throughput-threshold")
+                .takes_value(true)
+                .default_value(DEFAULT_THROUGHPUT_THRESHOLD)
+                .value_name("NUM")
+                .help("Highest fall of the throughput from the first to the last window in percent")
+            )
+            .arg(
+                Arg::with_name("error-threshold")
+                .long("error-threshold")
+                .takes_value(true)
+                .default_value(DEFAULT_ERROR_THRESHOLD)
+                .value_name("NUM")
+                .help("Highest rise of the error rate from the first to the last window in percentage points")
+            )
+            .arg(
+                Arg::with_name("memory-threshold")
+                .long("memory-threshold")
+                .takes_value(true)
+                .default_value(DEFAULT_MEMORY_THRESHOLD)
+                .value_name("NUM")
+                .help("Highest growth of the resident memory from the first to the last window in percent")
+            )
+            .arg(
+                Arg::with_name("degradation-no-fail")
+                .long("degradation-no-fail")
+                .takes_value(false)
+                .help("Only reports the degradations without failing the test")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("run-suite")
+            .about("Runs the phases of the suite described by the --config file and reports their results")
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("phases")
+                .long("phases")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .value_name("PHASE")
+                .help("Subcommands to run in order, defaults to the `run` key of the file or bootstrap,compare-test,performance-test,latency-test")
+            )
+            .arg(
+                Arg::with_name("continue-on-failure")
+                .long("continue-on-failure")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .value_name("PHASE")
+                .help("Phases whose failure does not stop the suite")
+            )
+            .arg(
+                Arg::with_name("report")
+                .long("report")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the combined report of the phases as json")
             )
         );
     app
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Problems found in the args of the subcommand, none when they are valid
+    fn problems(command_line: &str) -> Vec<ConfigProblem> {
+        let matches = bootstrap_app().get_matches_from(command_line.split_whitespace());
+        let result = match matches.subcommand() {
+            ("performance-test", Some(args)) => RpcPerformanceTestEnv::from_args(args).map(|_| ()),
+            ("bootstrap-load", Some(args)) => BootstrapLoadEnv::from_args(args).map(|_| ()),
+            ("chaos-test", Some(args)) => ChaosTestEnv::from_args(args).map(|_| ()),
+            ("run-suite", Some(args)) => RunSuiteEnv::from_args(args).map(|_| ()),
+            (name, _) => panic!("Unexpected subcommand {}", name),
+        };
+        result.err().map_or_else(Vec::new, |e| e.problems)
+    }
+
+    fn options(problems: &[ConfigProblem]) -> Vec<&str> {
+        problems
+            .iter()
+            .map(|problem| match problem {
+                ConfigProblem::Missing { option } | ConfigProblem::Invalid { option, .. } => {
+                    option.as_str()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_args_have_no_problems() {
+        assert_eq!(
+            problems(
+                "bin performance-test --target=a=http://a:8732 --target=b=http://b:8732,role=baseline \
+                 --url-file=urls.txt --error-threshold=5"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            problems(
+                "bin chaos-test --node=http://node:8732 --level=100 --restart-at=50 --container=node"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let problems = problems(
+            "bin performance-test --target=a=http://a:8732 --target=a=ftp://b:8732 \
+             --wrk-test-duration=0 --throughput-threshold=120 --error-threshold=x \
+             --sample-rate=-1 --connections=0 --sweep --mixed-workload",
+        );
+        assert_eq!(
+            options(&problems),
+            vec![
+                "target",
+                "url-file",
+                "wrk-test-duration",
+                "throughput-threshold",
+                "error-threshold",
+                "sample-rate",
+                "connections",
+                "sweep",
+            ]
+        );
+        assert_eq!(
+            problems[4].to_string(),
+            "--error-threshold: invalid value \"x\": invalid float literal"
+        );
+        assert_eq!(problems[1].to_string(), "--url-file: missing");
+    }
+
+    #[test]
+    fn invalid_value_is_not_checked_again() {
+        assert_eq!(
+            problems(
+                "bin chaos-test --node=http://node:8732 --level=x --restart-at=50 --container=node"
+            ),
+            vec![
+                ConfigProblem::Invalid {
+                    option: "level".to_string(),
+                    value: "x".to_string(),
+                    reason: "invalid digit found in string".to_string(),
+                },
+                ConfigProblem::Invalid {
+                    option: "restart-at".to_string(),
+                    value: "50".to_string(),
+                    reason: "must be greater than 0 and less than the level".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_targets_and_baselines() {
+        assert_eq!(
+            options(&problems(
+                "bin bootstrap-load --target=a=http://a:8732,role=baseline \
+                 --target=a=http://b:8732,role=baseline --level=10 --url-file=urls.txt \
+                 --baseline=c=10 --baseline=a"
+            )),
+            vec!["target", "target", "baseline", "baseline"]
+        );
+    }
+
+    #[test]
+    fn sampling_only_for_a_url_at_a_time() {
+        let problems = |mode| {
+            problems(&format!(
+                "bin performance-test --target=a=http://a:8732 --url-file=/urls.txt \
+                 --sample-rate=5 {}",
+                mode
+            ))
+        };
+        assert_eq!(problems(""), vec![]);
+        for mode in &["--sweep", "--mixed-workload"] {
+            assert_eq!(
+                problems(mode),
+                vec![ConfigProblem::Invalid {
+                    option: "sample-rate".to_string(),
+                    value: "5".to_string(),
+                    reason: "cannot be combined with --sweep or --mixed-workload".to_string(),
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn branch_order_only_for_a_url_at_a_time() {
+        let problems = |args: &str| {
+            options(&problems(&format!(
+                "bin performance-test --target=a=http://a:8732 --url-file=/urls.txt {}",
+                args
+            )))
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+        };
+        let scheduling = "--branch-order=random --seed=7 --concurrent-branches";
+        assert!(problems(scheduling).is_empty());
+        assert!(problems("--branch-order=fixed --sweep").is_empty());
+        for mode in &["--sweep", "--mixed-workload"] {
+            assert_eq!(
+                problems(&format!("{} {}", scheduling, mode)),
+                ["branch-order", "seed", "concurrent-branches"]
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_phases_under_their_own_option() {
+        assert_eq!(
+            problems(
+                "bin run-suite --phases=bootstrap,latency-test --continue-on-failure=bootstrap"
+            ),
+            vec![]
+        );
+        let problems = problems(
+            "bin run-suite --phases=bootstrap,soak --continue-on-failure=latency-test,bootstrapp",
+        );
+        assert_eq!(options(&problems), vec!["phases", "continue-on-failure"]);
+        assert!(matches!(
+            &problems[1],
+            ConfigProblem::Invalid { value, .. } if value == "bootstrapp"
+        ));
+    }
+
+    #[test]
+    fn restart_levels_below_the_level() {
+        let matches = bootstrap_app().get_matches_from(
+            "bin chaos-test --node=http://node:8732 --level=100 \
+             --restart-at=50 --restart-at=20 --restart-at=50 \
+             --stop-command=stop --start-command=start"
+                .split_whitespace(),
+        );
+        let env = ChaosTestEnv::from_args(matches.subcommand_matches("chaos-test").unwrap())
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(env.restart_levels, vec![20, 50]);
+        assert_eq!(
+            (env.stop_command.as_str(), env.start_command.as_str()),
+            ("stop", "start")
+        );
+
+        assert_eq!(
+            options(&problems(
+                "bin chaos-test --node=http://node:8732 --level=100 --restart-at=100 \
+                 --container=node --stop-command=stop"
+            )),
+            vec!["restart-at", "container"]
+        );
+    }
+
+    const SUITE: &str = r#"
+        run = ["bootstrap", "performance-test"]
+
+        [[target]]
+        name = "ocaml"
+        url = "http://ocaml:8732/"
+        role = "ocaml"
+
+        [[target]]
+        name = "tezedge"
+        url = "http://tezedge:18732/"
+
+        [bootstrap]
+        level = 1000
+        targets = ["tezedge"]
+
+        [performance-test]
+        url-file = "/urls.txt"
+        wrk-test-duration = 10
+        latency-no-fail = true
+        throughput-no-fail = false
+        sample-rate = 1.5
+    "#;
+
+    fn suite() -> SuiteConfig {
+        toml::from_str(SUITE).unwrap()
+    }
+
+    fn args(command_line: &str) -> Vec<String> {
+        command_line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn phase_command_line_from_the_file() {
+        let suite = suite();
+
+        assert_eq!(
+            suite.phase_command_line("bin", "performance-test"),
+            args(
+                "bin performance-test \
+                 --target=ocaml=http://ocaml:8732/,role=ocaml --target=tezedge=http://tezedge:18732/ \
+                 --latency-no-fail --sample-rate=1.5 --url-file=/urls.txt --wrk-test-duration=10"
+            )
+        );
+        assert_eq!(
+            suite.phase_command_line("bin", "bootstrap"),
+            args("bin bootstrap --nodes=http://tezedge:18732/ --level=1000")
+        );
+        assert_eq!(
+            suite.phase_command_line("bin", "latency-test"),
+            args(
+                "bin latency-test \
+                 --target=ocaml=http://ocaml:8732/,role=ocaml --target=tezedge=http://tezedge:18732/"
+            )
+        );
+        assert_eq!(
+            suite.phase_command_line("bin", "run-suite"),
+            args("bin run-suite --phases=bootstrap --phases=performance-test")
+        );
+    }
+
+    #[test]
+    fn command_line_args_override_the_file() {
+        let suite = suite();
+        let cli = args(
+            "bin --config=suite.toml performance-test --latency-no-fail \
+             --target=tezedge=http://127.0.0.1:18732 --wrk-test-duration=20",
+        );
+        let matches = bootstrap_app().get_matches_from(&cli);
+
+        // the flags and lists given on the command line are not repeated from the file
+        let command_line = suite.command_line(&matches, &cli);
+        assert_eq!(
+            command_line,
+            args(
+                "bin performance-test --sample-rate=1.5 --url-file=/urls.txt \
+                 --wrk-test-duration=10 --latency-no-fail \
+                 --target=tezedge=http://127.0.0.1:18732 --wrk-test-duration=20"
+            )
+        );
+        let matches = bootstrap_app().get_matches_from(&command_line);
+        let matches = matches.subcommand_matches("performance-test").unwrap();
+        assert_eq!(matches.value_of("wrk-test-duration"), Some("20"));
+        assert_eq!(matches.values_of("target").unwrap().count(), 1);
+    }
+
+    #[test]
+    fn unset_removes_the_key() {
+        let mut suite = suite();
+        suite.unset("performance-test.latency-no-fail");
+        suite.unset("level");
+        suite.unset("unknown.level");
+
+        assert_eq!(
+            suite.phase_command_line("bin", "bootstrap"),
+            args("bin bootstrap --nodes=http://tezedge:18732/")
+        );
+        assert!(!suite
+            .phase_command_line("bin", "performance-test")
+            .contains(&"--latency-no-fail".to_string()));
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use failure::format_err;
+use serde_json::Value;
+use url::Url;
+
+/// Protocol constants needed to compute cycle boundaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Constants {
+    pub blocks_per_cycle: i32,
+    pub preserved_cycles: i32,
+}
+
+/// Levels validated by a single protocol, which starts at a cycle boundary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolPeriod {
+    pub protocol: String,
+    pub first_level: i32,
+    pub first_cycle: i32,
+    pub constants: Constants,
+}
+
+impl fmt::Display for ProtocolPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} from level {} (cycle {}): blocks per cycle: {}, preserved cycles: {}",
+            self.protocol,
+            self.first_level,
+            self.first_cycle,
+            self.constants.blocks_per_cycle,
+            self.constants.preserved_cycles
+        )
+    }
+}
+
+/// Cycle and level arithmetic over every protocol period of the chain of a node
+///
+/// The periods are discovered from the `proto` number in the block headers, which is
+/// incremented by every protocol migration, and their constants are read once per period.
+#[derive(Debug, Clone)]
+pub struct ConstantsResolver {
+    periods: Vec<ProtocolPeriod>,
+}
+
+impl ConstantsResolver {
+    pub fn new(periods: Vec<ProtocolPeriod>) -> Self {
+        assert!(
+            !periods.is_empty(),
+            "At least one protocol period is required"
+        );
+        Self { periods }
+    }
+
+    /// Discovers the protocol periods of the chain up to the head of the node
+    pub async fn resolve(client: &reqwest::Client, node: &Url) -> Result<Self, failure::Error> {
+        Self::resolve_up_to(client, node, "head").await
+    }
+
+    /// Discovers the protocol periods of the chain up to the block
+    pub async fn resolve_up_to(
+        client: &reqwest::Client,
+        node: &Url,
+        block: &str,
+    ) -> Result<Self, failure::Error> {
+        let (head_level, head_proto) = fetch_proto(client, node, block).await?;
+        let mut periods = Vec::new();
+
+        if head_level > 1 {
+            let (_, first_proto) = fetch_proto(client, node, "1").await?;
+            let mut low = 1;
+
+            for proto in first_proto..=head_proto {
+                // the migration block is the last block of the previous protocol
+                let migration =
+                    first_level_with_proto(client, node, proto, low, head_level).await?;
+                if migration >= head_level {
+                    break;
+                }
+                periods.push(fetch_period(client, node, migration + 1).await?);
+                low = migration;
+            }
+        }
+        if periods.is_empty() {
+            periods.push(fetch_period(client, node, head_level).await?);
+        }
+
+        Ok(Self::new(periods))
+    }
+
+    pub fn periods(&self) -> &[ProtocolPeriod] {
+        &self.periods
+    }
+
+    fn period_of_level(&self, level: i32) -> &ProtocolPeriod {
+        self.periods
+            .iter()
+            .rev()
+            .find(|p| p.first_level <= level)
+            .unwrap_or(&self.periods[0])
+    }
+
+    fn period_of_cycle(&self, cycle: i32) -> &ProtocolPeriod {
+        self.periods
+            .iter()
+            .rev()
+            .find(|p| p.first_cycle <= cycle)
+            .unwrap_or(&self.periods[0])
+    }
+
+    /// Hash of the protocol validating the level
+    pub fn protocol_at(&self, level: i32) -> &str {
+        &self.period_of_level(level).protocol
+    }
+
+    /// Constants of the protocol validating the level
+    pub fn constants_at(&self, level: i32) -> Constants {
+        self.period_of_level(level).constants
+    }
+
+    /// Cycle containing the level
+    pub fn level_cycle(&self, level: i32) -> i32 {
+        let period = self.period_of_level(level);
+        let cycle = period.first_cycle
+            + (level - period.first_level).div_euclid(period.constants.blocks_per_cycle);
+        cycle.max(0)
+    }
+
+    /// First level of the cycle
+    pub fn cycle_first_level(&self, cycle: i32) -> i32 {
+        let period = self.period_of_cycle(cycle);
+        (period.first_level + (cycle - period.first_cycle) * period.constants.blocks_per_cycle)
+            .max(1)
+    }
+
+    /// First level at which the rights of the cycle can be requested
+    pub fn rights_first_level(&self, cycle: i32) -> i32 {
+        let preserved_cycles = self.period_of_cycle(cycle).constants.preserved_cycles;
+        self.cycle_first_level((cycle - preserved_cycles).max(0))
+    }
+}
+
+/// Level and protocol number of the block header
+async fn fetch_proto(
+    client: &reqwest::Client,
+    node: &Url,
+    block: &str,
+) -> Result<(i32, i32), failure::Error> {
+    let url = format!("{}chains/main/blocks/{}/header", node, block);
+    let header: Value = client.get(&url).send().await?.json().await?;
+
+    match (header["level"].as_i64(), header["proto"].as_i64()) {
+        (Some(level), Some(proto)) => Ok((level as i32, proto as i32)),
+        _ => Err(format_err!("Missing level or proto in {}", url)),
+    }
+}
+
+/// Binary search of the first level in `low..=high` with at least the protocol number
+async fn first_level_with_proto(
+    client: &reqwest::Client,
+    node: &Url,
+    proto: i32,
+    mut low: i32,
+    mut high: i32,
+) -> Result<i32, failure::Error> {
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let (_, middle_proto) = fetch_proto(client, node, &middle.to_string()).await?;
+        if middle_proto >= proto {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    Ok(low)
+}
+
+/// Protocol period containing the level, the level has to be validated by the protocol
+async fn fetch_period(
+    client: &reqwest::Client,
+    node: &Url,
+    level: i32,
+) -> Result<ProtocolPeriod, failure::Error> {
+    let url = format!("{}chains/main/blocks/{}/metadata", node, level);
+    let metadata: Value = client.get(&url).send().await?.json().await?;
+
+    // renamed in the newer protocols
+    let level_info = if metadata["level_info"].is_object() {
+        &metadata["level_info"]
+    } else {
+        &metadata["level"]
+    };
+    let (cycle, cycle_position) = match (
+        level_info["cycle"].as_i64(),
+        level_info["cycle_position"].as_i64(),
+    ) {
+        (Some(cycle), Some(position)) => (cycle as i32, position as i32),
+        _ => return Err(format_err!("Missing cycle in {}", url)),
+    };
+
+    Ok(ProtocolPeriod {
+        protocol: metadata["protocol"].as_str().unwrap_or("").to_string(),
+        first_level: level - cycle_position,
+        first_cycle: cycle,
+        constants: fetch_constants(client, node, &level.to_string()).await?,
+    })
+}
+
+/// Reads the constants of the protocol of the block from `context/constants`
+pub async fn fetch_constants(
+    client: &reqwest::Client,
+    node: &Url,
+    block: &str,
+) -> Result<Constants, failure::Error> {
+    let url = format!("{}chains/main/blocks/{}/context/constants", node, block);
+    let constants: Value = client.get(&url).send().await?.json().await?;
+
+    let constant = |names: &[&str]| -> Result<i32, failure::Error> {
+        names
+            .iter()
+            .find_map(|name| constants[*name].as_i64())
+            .map(|value| value as i32)
+            .ok_or_else(|| format_err!("Missing constant {} in {}", names[0], url))
+    };
+
+    Ok(Constants {
+        blocks_per_cycle: constant(&["blocks_per_cycle"])?,
+        // renamed in the newer protocols
+        preserved_cycles: constant(&["preserved_cycles", "consensus_rights_delay"])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cycles of 2048 blocks, migrated to cycles of 4096 blocks at the end of the cycle 1
+    fn resolver() -> ConstantsResolver {
+        ConstantsResolver::new(vec![
+            ProtocolPeriod {
+                protocol: "P1".to_string(),
+                first_level: 1,
+                first_cycle: 0,
+                constants: Constants {
+                    blocks_per_cycle: 2048,
+                    preserved_cycles: 5,
+                },
+            },
+            ProtocolPeriod {
+                protocol: "P2".to_string(),
+                first_level: 4097,
+                first_cycle: 2,
+                constants: Constants {
+                    blocks_per_cycle: 4096,
+                    preserved_cycles: 3,
+                },
+            },
+        ])
+    }
+
+    #[test]
+    fn level_cycle_across_periods() {
+        let resolver = resolver();
+        for (level, cycle) in &[
+            (0, 0),
+            (1, 0),
+            (2048, 0),
+            (2049, 1),
+            (4096, 1),
+            (4097, 2),
+            (8192, 2),
+            (8193, 3),
+        ] {
+            assert_eq!(resolver.level_cycle(*level), *cycle, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn cycle_first_level_across_periods() {
+        let resolver = resolver();
+        for (cycle, level) in &[(0, 1), (1, 2049), (2, 4097), (3, 8193), (5, 16385)] {
+            assert_eq!(
+                resolver.cycle_first_level(*cycle),
+                *level,
+                "cycle {}",
+                cycle
+            );
+            assert_eq!(resolver.level_cycle(*level), *cycle, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn rights_first_level_uses_the_preserved_cycles_of_the_cycle() {
+        let resolver = resolver();
+        assert_eq!(resolver.rights_first_level(1), 1);
+        assert_eq!(resolver.rights_first_level(3), 1);
+        assert_eq!(resolver.rights_first_level(5), 4097);
+        assert_eq!(resolver.rights_first_level(6), 8193);
+    }
+
+    #[test]
+    fn protocol_and_constants_at_level() {
+        let resolver = resolver();
+        assert_eq!(resolver.protocol_at(4096), "P1");
+        assert_eq!(resolver.protocol_at(4097), "P2");
+        assert_eq!(resolver.constants_at(0).blocks_per_cycle, 2048);
+        assert_eq!(resolver.constants_at(5000).preserved_cycles, 3);
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Write};
+
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde_json::Value;
+use url::Url;
+
+use crate::configuration::GenerateUrlsEnv;
+use crate::constants::ConstantsResolver;
+use crate::utils::{http_client, interruptible};
+
+/// Addresses found in the operations of the crawled blocks
+#[derive(Debug, Default)]
+struct Addresses {
+    contracts: BTreeSet<String>,
+    delegates: BTreeSet<String>,
+}
+
+impl Addresses {
+    fn collect(&mut self, value: &Value, key: Option<&str>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    self.collect(value, Some(key));
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.collect(value, key);
+                }
+            }
+            Value::String(address) if is_address(address) => match key {
+                Some("delegate") | Some("baker") => {
+                    self.delegates.insert(address.clone());
+                }
+                Some("source") | Some("destination") | Some("contract") => {
+                    self.contracts.insert(address.clone());
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Prefixes of the protocol hashes, from Genesis up to Nairobi, which still serve
+/// `endorsing_rights` instead of `attestation_rights`
+const ENDORSING_RIGHTS_PROTOCOLS: &[&str] = &[
+    "PrihK96n", "Ps9mPmXa", "PtCJ7pwo", "PsYLVpVv", "PsddFKi3", "Pt24m4xi", "PsBabyM1", "PsBABY5H",
+    "PsCARTHA", "PsDELPH1", "PtEdoTez", "PtEdo2Zk", "PsFLoren", "PtGRANAD", "PtHangzH", "PtHangz2",
+    "Psithaca", "PtJakart", "PtKathma", "PtLimaPt", "PtMumbai", "PtNairob",
+];
+
+/// Rights rpc of the attesting (formerly endorsing) bakers for the protocol
+fn attestation_rights_rpc(protocol: &str) -> &'static str {
+    if ENDORSING_RIGHTS_PROTOCOLS
+        .iter()
+        .any(|prefix| protocol.starts_with(prefix))
+    {
+        "endorsing_rights"
+    } else {
+        "attestation_rights"
+    }
+}
+
+/// Levels spread evenly from the first block to the level, ending at the level
+fn crawled_levels(level: i32, blocks: usize) -> Vec<i32> {
+    let blocks = blocks.min(level.max(0) as usize) as i64;
+    (1..=blocks)
+        .map(|block| (i64::from(level) * block / blocks) as i32)
+        .dedup()
+        .collect()
+}
+
+fn is_address(value: &str) -> bool {
+    ["tz1", "tz2", "tz3", "tz4", "KT1"]
+        .iter()
+        .any(|prefix| value.starts_with(prefix))
+        && value.len() == 36
+}
+
+/// Crawls the node and writes the url list of the chain up to the level, the progress is
+/// printed to stderr, so the list can be written to stdout
+pub async fn generate_urls(env: GenerateUrlsEnv) -> Result<(), failure::Error> {
+    let GenerateUrlsEnv {
+        node,
+        level,
+        cycles,
+        blocks,
+        max_urls,
+        seed,
+        output,
+    } = env;
+
+    let client = http_client()?;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // the cycles of every crawled level follow the constants of the protocol validating it
+    let constants = ConstantsResolver::resolve_up_to(&client, &node, &level.to_string()).await?;
+    let levels = crawled_levels(level, blocks);
+
+    let mut block_urls = Vec::new();
+    let mut addresses = Addresses::default();
+    interruptible(async {
+        for level in &levels {
+            eprintln!("[{}] Crawling block {}", node, level);
+            let operations = get_json(
+                &client,
+                &node,
+                &format!("chains/main/blocks/{}/operations", level),
+            )
+            .await?;
+            addresses.collect(&operations, None);
+
+            let cycle = constants.level_cycle(*level);
+            block_urls.extend(vec![
+                format!("chains/main/blocks/{}", level),
+                format!("chains/main/blocks/{}/header", level),
+                format!("chains/main/blocks/{}/operations", level),
+                format!("chains/main/blocks/{}/context/constants", level),
+                format!("chains/main/blocks/{}/votes/listings", level),
+                format!(
+                    "chains/main/blocks/{}/context/raw/json/cycle/{}",
+                    level, cycle
+                ),
+            ]);
+        }
+        Ok(())
+    })
+    .await?;
+
+    // rights of the last cycles, requested from the last level
+    let last_cycle = constants.level_cycle(level);
+    let attestation_rights = attestation_rights_rpc(constants.protocol_at(level));
+    let rights_urls = ((last_cycle - cycles + 1).max(0)..=last_cycle)
+        .flat_map(|cycle| {
+            vec![
+                format!(
+                    "chains/main/blocks/{}/helpers/baking_rights?cycle={}&all=true",
+                    level, cycle
+                ),
+                format!(
+                    "chains/main/blocks/{}/helpers/{}?cycle={}",
+                    level, attestation_rights, cycle
+                ),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut contract_urls = addresses
+        .contracts
+        .iter()
+        .flat_map(|contract| {
+            vec![
+                format!(
+                    "chains/main/blocks/{}/context/contracts/{}",
+                    level, contract
+                ),
+                format!(
+                    "chains/main/blocks/{}/context/contracts/{}/balance",
+                    level, contract
+                ),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut delegate_urls = addresses
+        .delegates
+        .iter()
+        .map(|delegate| {
+            format!(
+                "chains/main/blocks/{}/context/delegates/{}",
+                level, delegate
+            )
+        })
+        .collect::<Vec<_>>();
+    block_urls.shuffle(&mut rng);
+    contract_urls.shuffle(&mut rng);
+    delegate_urls.shuffle(&mut rng);
+
+    let urls = within_budget(
+        vec![block_urls, rights_urls, contract_urls, delegate_urls],
+        max_urls,
+    );
+
+    let mut out: Box<dyn Write> = if output == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(File::create(&output)?)
+    };
+    writeln!(
+        out,
+        "# Generated from {} up to level {}, seed {}",
+        node, level, seed
+    )?;
+    for url in &urls {
+        writeln!(out, "{}", url)?;
+    }
+    eprintln!(
+        "[{}] Generated {} urls from {} blocks, {} contracts and {} delegates",
+        node,
+        urls.len(),
+        levels.len(),
+        addresses.contracts.len(),
+        addresses.delegates.len()
+    );
+    Ok(())
+}
+
+/// Takes the urls of the categories in turns until the budget is spent, so every category
+/// is represented when the budget is small
+fn within_budget(categories: Vec<Vec<String>>, max_urls: usize) -> Vec<String> {
+    let mut categories = categories
+        .into_iter()
+        .map(Vec::into_iter)
+        .collect::<Vec<_>>();
+    let mut urls = Vec::new();
+
+    while urls.len() < max_urls {
+        let before = urls.len();
+        for category in categories.iter_mut() {
+            if urls.len() == max_urls {
+                break;
+            }
+            if let Some(url) = category.next() {
+                urls.push(url);
+            }
+        }
+        if urls.len() == before {
+            break;
+        }
+    }
+    urls
+}
+
+async fn get_json(
+    client: &reqwest::Client,
+    node: &Url,
+    rpc: &str,
+) -> Result<Value, failure::Error> {
+    Ok(client
+        .get(format!("{}{}", node, rpc))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crawled_levels_end_at_the_level() {
+        assert_eq!(crawled_levels(100, 4), vec![25, 50, 75, 100]);
+        assert_eq!(crawled_levels(10, 3), vec![3, 6, 10]);
+        // never more blocks than levels
+        assert_eq!(crawled_levels(3, 10), vec![1, 2, 3]);
+        assert_eq!(crawled_levels(0, 10), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn attestation_rights_rpc_by_protocol() {
+        assert_eq!(
+            attestation_rights_rpc("PtNairobiyssHuh87hEhfVBGCVrK3WnS8Z2FT4ymB5tAa4r1nQf"),
+            "endorsing_rights"
+        );
+        assert_eq!(
+            attestation_rights_rpc("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb"),
+            "endorsing_rights"
+        );
+        assert_eq!(
+            attestation_rights_rpc("ProxfordYmVfjWnRcgjWH36fW6PArwqykTFzotUxRs6gmTcZDuH"),
+            "attestation_rights"
+        );
+    }
+
+    #[test]
+    fn address_prefixes() {
+        assert!(is_address(&format!("tz1{}", "a".repeat(33))));
+        assert!(is_address(&format!("KT1{}", "a".repeat(33))));
+        assert!(!is_address(&format!("tz1{}", "a".repeat(32))));
+        assert!(!is_address(&format!("edpk{}", "a".repeat(32))));
+    }
+}
@@ -0,0 +1,647 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Write as _;
+use std::fs;
+use std::sync::Mutex;
+
+use itertools::Itertools;
+use url::Url;
+
+use crate::suite::{PhaseReport, PhaseStatus};
+use crate::sweep::SweepStep;
+use crate::types::{Branch, LatencyPercentile};
+use crate::wrk::{calc_throughput, us_to_ms, WrkResultMap};
+
+/// Results of a branch for a single rpc, the latencies of the distribution are in microseconds
+#[derive(Debug, Clone)]
+pub struct BranchLatencies {
+    pub branch: String,
+    pub throughput: f32,
+    pub distribution: Vec<LatencyPercentile>,
+    /// Duration of the single request before the warm-up in milliseconds
+    pub cold_ms: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcLatencies {
+    pub rpc: String,
+    pub branches: Vec<BranchLatencies>,
+    /// Names of the branches in the order they have been measured in
+    pub order: Vec<String>,
+}
+
+/// Steps of the connection sweep of every branch for a single rpc
+#[derive(Debug, Clone)]
+pub struct RpcSweep {
+    pub rpc: String,
+    pub branches: Vec<(String, Vec<SweepStep>)>,
+}
+
+/// Head levels of a bootstrapping node by the seconds since the start of the monitoring
+#[derive(Debug, Clone)]
+pub struct LevelCurve {
+    pub node: String,
+    pub points: Vec<(f64, i32)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexerMismatch {
+    pub target: String,
+    pub reference: String,
+    pub level: i32,
+    pub diff: String,
+}
+
+/// Data of the report, recorded by the subcommands while they run
+#[derive(Debug, Clone, Default)]
+pub struct ReportData {
+    pub rpcs: Vec<RpcLatencies>,
+    pub sweeps: Vec<RpcSweep>,
+    pub bootstrap: Vec<LevelCurve>,
+    pub indexer: Vec<IndexerMismatch>,
+    pub phases: Vec<PhaseReport>,
+}
+
+// the suite runs the subcommands in the same process, so they all record to the same report
+static RECORDED: Mutex<ReportData> = Mutex::new(ReportData {
+    rpcs: Vec::new(),
+    sweeps: Vec::new(),
+    bootstrap: Vec::new(),
+    indexer: Vec::new(),
+    phases: Vec::new(),
+});
+
+fn record(f: impl FnOnce(&mut ReportData)) {
+    f(&mut RECORDED.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+pub fn record_rpc(rpc: &str, results: &WrkResultMap) {
+    let branches = results
+        .iter()
+        .sorted_by_key(|(branch, _)| branch.sort_key)
+        .map(|(branch, result)| BranchLatencies {
+            branch: branch.name.clone(),
+            throughput: calc_throughput(result.requests(), result.duration()),
+            distribution: result.latency_distribution().clone(),
+            cold_ms: None,
+        })
+        .collect();
+    record(|data| {
+        data.rpcs.push(RpcLatencies {
+            rpc: rpc.to_string(),
+            branches,
+            order: Vec::new(),
+        })
+    });
+}
+
+/// Durations of the cold requests of the last recorded results of the rpc
+pub fn record_cold(rpc: &str, cold: &[(Branch, f32)]) {
+    record(|data| {
+        if let Some(latencies) = data.rpcs.iter_mut().rev().find(|r| r.rpc == rpc) {
+            for latencies in &mut latencies.branches {
+                if let Some((_, ms)) = cold.iter().find(|(b, _)| b.name == latencies.branch) {
+                    latencies.cold_ms = Some(*ms);
+                }
+            }
+        }
+    });
+}
+
+/// Order the branches of the last recorded results of the rpc have been measured in
+pub fn record_order(rpc: &str, order: &[Branch]) {
+    record(|data| {
+        if let Some(latencies) = data.rpcs.iter_mut().rev().find(|r| r.rpc == rpc) {
+            latencies.order = order.iter().map(|b| b.name.clone()).collect();
+        }
+    });
+}
+
+pub fn record_sweep(rpc: &str, sweeps: &[(Branch, Vec<SweepStep>)]) {
+    let branches = sweeps
+        .iter()
+        .map(|(branch, steps)| (branch.name.clone(), steps.clone()))
+        .collect();
+    record(|data| {
+        data.sweeps.push(RpcSweep {
+            rpc: rpc.to_string(),
+            branches,
+        })
+    });
+}
+
+pub fn record_level(node: &Url, elapsed: f64, level: i32) {
+    record(|data| {
+        let node = node.to_string();
+        match data.bootstrap.iter_mut().find(|curve| curve.node == node) {
+            Some(curve) => curve.points.push((elapsed, level)),
+            None => data.bootstrap.push(LevelCurve {
+                node,
+                points: vec![(elapsed, level)],
+            }),
+        }
+    });
+}
+
+pub fn record_indexer_mismatch(target: &str, reference: &str, level: i32, diff: &str) {
+    record(|data| {
+        data.indexer.push(IndexerMismatch {
+            target: target.to_string(),
+            reference: reference.to_string(),
+            level,
+            diff: diff.to_string(),
+        })
+    });
+}
+
+pub fn record_phases(phases: &[PhaseReport]) {
+    record(|data| data.phases = phases.to_vec());
+}
+
+/// Writes everything recorded so far as a single html file, which does not load anything
+pub fn write_html_report(file: &str) -> Result<(), failure::Error> {
+    let data = RECORDED.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    fs::write(file, render(&data))?;
+    println!("Html report written to {}", file);
+    Ok(())
+}
+
+const COLOURS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+const WIDTH: f64 = 560.0;
+const HEIGHT: f64 = 300.0;
+const MARGIN: f64 = 50.0;
+/// Bins of the latency histograms
+const HISTOGRAM_BINS: usize = 20;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn tick(value: f64) -> String {
+    if value.abs() >= 100.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn render(data: &ReportData) -> String {
+    let mut html = String::new();
+    html.push_str(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Tezos node test report</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; }\n\
+         td, th { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+         .passed { color: #2ca02c; } .failed { color: #d62728; } .skipped { color: #7f7f7f; }\n\
+         .charts { display: flex; flex-wrap: wrap; gap: 1em; }\n\
+         pre { background: #f6f6f6; padding: 1em; overflow-x: auto; }\n\
+         </style>\n</head>\n<body>\n<h1>Tezos node test report</h1>\n",
+    );
+
+    if !data.phases.is_empty() {
+        html.push_str("<h2>Phases</h2>\n<table>\n<tr><th>phase</th><th>status</th><th>duration</th><th>error</th></tr>\n");
+        for phase in &data.phases {
+            let status = match phase.status {
+                PhaseStatus::Passed => "passed",
+                PhaseStatus::Failed => "failed",
+                PhaseStatus::Skipped => "skipped",
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"{}\">{}</td><td>{:.1}s</td><td>{}</td></tr>",
+                escape(&phase.phase),
+                status,
+                status,
+                phase.duration,
+                escape(phase.error.as_deref().unwrap_or(""))
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if !data.bootstrap.is_empty() {
+        html.push_str("<h2>Bootstrap</h2>\n");
+        let series = data
+            .bootstrap
+            .iter()
+            .map(|curve| {
+                let points = curve
+                    .points
+                    .iter()
+                    .map(|(elapsed, level)| (*elapsed, f64::from(*level)))
+                    .collect();
+                (curve.node.clone(), points)
+            })
+            .collect_vec();
+        html.push_str(&line_chart("Level over time", "s", "level", &series));
+    }
+
+    if !data.rpcs.is_empty() {
+        html.push_str("<h2>Rpc performance</h2>\n");
+    }
+    for rpc in &data.rpcs {
+        let _ = writeln!(html, "<h3><code>{}</code></h3>", escape(&rpc.rpc));
+        if !rpc.order.is_empty() {
+            let _ = writeln!(
+                html,
+                "<p>Measured in the order: {}</p>",
+                escape(&rpc.order.join(", "))
+            );
+        }
+        html.push_str("<div class=\"charts\">\n");
+        let bars = rpc
+            .branches
+            .iter()
+            .map(|b| (b.branch.clone(), f64::from(b.throughput)))
+            .collect_vec();
+        html.push_str(&bar_chart("Throughput", "req/s", &bars));
+        let cold = rpc
+            .branches
+            .iter()
+            .filter_map(|b| Some((b.branch.clone(), f64::from(b.cold_ms?))))
+            .collect_vec();
+        if !cold.is_empty() {
+            html.push_str(&bar_chart("Cold request", "ms", &cold));
+        }
+        if rpc.branches.iter().any(|b| !b.distribution.is_empty()) {
+            html.push_str(&line_chart(
+                "Latency histogram (up to p99.9)",
+                "ms",
+                "% of requests",
+                &histograms(&rpc.branches),
+            ));
+            let cdfs = rpc
+                .branches
+                .iter()
+                .map(|b| {
+                    let points = b
+                        .distribution
+                        .iter()
+                        .map(|p| (f64::from(us_to_ms(&p.latency)), f64::from(p.percentile)))
+                        .collect();
+                    (b.branch.clone(), points)
+                })
+                .collect_vec();
+            html.push_str(&line_chart("Latency CDF", "ms", "percentile", &cdfs));
+        }
+        html.push_str("</div>\n");
+    }
+
+    if !data.sweeps.is_empty() {
+        html.push_str("<h2>Connection sweeps</h2>\n");
+    }
+    for sweep in &data.sweeps {
+        let _ = writeln!(html, "<h3><code>{}</code></h3>", escape(&sweep.rpc));
+        html.push_str("<div class=\"charts\">\n");
+        let series = |value: fn(&SweepStep) -> f32| {
+            sweep
+                .branches
+                .iter()
+                .map(|(branch, steps)| {
+                    let points = steps
+                        .iter()
+                        .map(|step| (step.connections as f64, f64::from(value(step))))
+                        .collect();
+                    (branch.clone(), points)
+                })
+                .collect_vec()
+        };
+        html.push_str(&line_chart(
+            "Throughput by connections",
+            "connections",
+            "req/s",
+            &series(|step| step.throughput),
+        ));
+        html.push_str(&line_chart(
+            "Mean latency by connections",
+            "connections",
+            "ms",
+            &series(|step| step.latency_mean),
+        ));
+        html.push_str("</div>\n");
+    }
+
+    if !data.indexer.is_empty() {
+        html.push_str("<h2>Indexer mismatches</h2>\n");
+        for mismatch in &data.indexer {
+            let _ = writeln!(
+                html,
+                "<h3>{}: block {} differs from {}</h3>\n<pre>{}</pre>",
+                escape(&mismatch.target),
+                mismatch.level,
+                escape(&mismatch.reference),
+                escape(&mismatch.diff)
+            );
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Share of the requests of every histogram bin, interpolated from the distributions, the
+/// bins span the latencies up to the greatest p99.9, so a single outlier does not hide the rest
+fn histograms(branches: &[BranchLatencies]) -> Vec<(String, Vec<(f64, f64)>)> {
+    let ms = |p: &LatencyPercentile| f64::from(us_to_ms(&p.latency));
+    let upper = branches
+        .iter()
+        .flat_map(|b| {
+            b.distribution
+                .iter()
+                .filter(|p| p.percentile <= 99.9)
+                .map(ms)
+        })
+        .fold(0.0, f64::max);
+    let bin = upper.max(f64::EPSILON) / HISTOGRAM_BINS as f64;
+
+    branches
+        .iter()
+        .map(|b| {
+            // percentile of the requests faster than the latency
+            let cdf = |latency: f64| {
+                let mut below = (0.0, 0.0);
+                for p in &b.distribution {
+                    let point = (ms(p), f64::from(p.percentile));
+                    if point.0 >= latency {
+                        if point.0 <= below.0 {
+                            return point.1;
+                        }
+                        return below.1
+                            + (point.1 - below.1) * (latency - below.0) / (point.0 - below.0);
+                    }
+                    below = point;
+                }
+                100.0
+            };
+            let points = (0..HISTOGRAM_BINS)
+                .map(|i| {
+                    let start = i as f64 * bin;
+                    (start + bin / 2.0, cdf(start + bin) - cdf(start))
+                })
+                .collect();
+            (b.branch.clone(), points)
+        })
+        .collect()
+}
+
+fn legend(names: &[String]) -> String {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"10\" height=\"10\" fill=\"{}\"/>\
+                 <text x=\"{}\" y=\"{}\" font-size=\"11\">{}</text>",
+                WIDTH - MARGIN - 120.0,
+                MARGIN / 2.0 + 14.0 * i as f64,
+                COLOURS[i % COLOURS.len()],
+                WIDTH - MARGIN - 105.0,
+                MARGIN / 2.0 + 14.0 * i as f64 + 9.0,
+                escape(name)
+            )
+        })
+        .collect()
+}
+
+/// Axes with five ticks on the y axis and on the x axis, unless it has no values, returns the
+/// svg and the mapping of the values
+fn axes(
+    title: &str,
+    x_label: Option<&str>,
+    y_label: &str,
+    x_max: f64,
+    y_max: f64,
+) -> (String, impl Fn(f64, f64) -> (f64, f64)) {
+    let (x_max, y_max) = (x_max.max(f64::EPSILON), y_max.max(f64::EPSILON));
+    let map = move |x: f64, y: f64| {
+        (
+            MARGIN + x / x_max * (WIDTH - 2.0 * MARGIN),
+            HEIGHT - MARGIN - y / y_max * (HEIGHT - 2.0 * MARGIN),
+        )
+    };
+
+    let mut svg = format!(
+        "<text x=\"{}\" y=\"16\" font-size=\"13\" text-anchor=\"middle\">{}</text>\
+         <line x1=\"{m}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"black\"/>\
+         <line x1=\"{m}\" y1=\"{m}\" x2=\"{m}\" y2=\"{b}\" stroke=\"black\"/>\
+         <text x=\"{r}\" y=\"{}\" font-size=\"11\" text-anchor=\"end\">{}</text>\
+         <text x=\"4\" y=\"{}\" font-size=\"11\">{}</text>",
+        WIDTH / 2.0,
+        escape(title),
+        HEIGHT - 8.0,
+        escape(x_label.unwrap_or("")),
+        MARGIN - 10.0,
+        escape(y_label),
+        m = MARGIN,
+        b = HEIGHT - MARGIN,
+        r = WIDTH - MARGIN,
+    );
+    for i in 0..=5 {
+        let (x, _) = map(x_max * i as f64 / 5.0, 0.0);
+        let (_, y) = map(0.0, y_max * i as f64 / 5.0);
+        if x_label.is_some() {
+            let _ = write!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" font-size=\"10\" text-anchor=\"middle\">{}</text>",
+                x,
+                HEIGHT - MARGIN + 14.0,
+                tick(x_max * i as f64 / 5.0)
+            );
+        }
+        let _ = write!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" font-size=\"10\" text-anchor=\"end\">{}</text>",
+            MARGIN - 4.0,
+            y + 3.0,
+            tick(y_max * i as f64 / 5.0)
+        );
+    }
+    (svg, map)
+}
+
+fn line_chart(
+    title: &str,
+    x_label: &str,
+    y_label: &str,
+    series: &[(String, Vec<(f64, f64)>)],
+) -> String {
+    let all = series.iter().flat_map(|(_, points)| points);
+    let x_max = all.clone().map(|p| p.0).fold(0.0, f64::max);
+    let y_max = all.map(|p| p.1).fold(0.0, f64::max);
+    let (mut svg, map) = axes(title, Some(x_label), y_label, x_max, y_max);
+
+    for (i, (_, points)) in series.iter().enumerate() {
+        let points = points
+            .iter()
+            .map(|(x, y)| {
+                let (x, y) = map(*x, *y);
+                format!("{:.1},{:.1}", x, y)
+            })
+            .join(" ");
+        let _ = write!(
+            svg,
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+            points,
+            COLOURS[i % COLOURS.len()]
+        );
+    }
+    svg.push_str(&legend(
+        &series.iter().map(|(name, _)| name.clone()).collect_vec(),
+    ));
+    format!(
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">{}</svg>\n",
+        WIDTH, HEIGHT, svg
+    )
+}
+
+fn bar_chart(title: &str, y_label: &str, bars: &[(String, f64)]) -> String {
+    let y_max = bars.iter().map(|(_, value)| *value).fold(0.0, f64::max);
+    let (mut svg, map) = axes(title, None, y_label, bars.len() as f64, y_max);
+
+    for (i, (name, value)) in bars.iter().enumerate() {
+        let (left, top) = map(i as f64 + 0.15, *value);
+        let (right, bottom) = map(i as f64 + 0.85, 0.0);
+        let _ = write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\">\
+             <title>{}: {:.2}</title></rect>",
+            left,
+            top,
+            right - left,
+            bottom - top,
+            COLOURS[i % COLOURS.len()],
+            escape(name),
+            value
+        );
+    }
+    svg.push_str(&legend(
+        &bars.iter().map(|(name, _)| name.clone()).collect_vec(),
+    ));
+    format!(
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">{}</svg>\n",
+        WIDTH, HEIGHT, svg
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Latencies spread evenly up to the p99.9 latency in milliseconds, the slowest at ten times it
+    fn uniform(branch: &str, p999: f32) -> BranchLatencies {
+        let percentile = |percentile, ms: f32| LatencyPercentile {
+            percentile,
+            latency: ms * 1000.0,
+        };
+        BranchLatencies {
+            branch: branch.to_string(),
+            throughput: 1000.0,
+            distribution: vec![
+                percentile(0.0, 0.0),
+                percentile(99.9, p999),
+                percentile(100.0, p999 * 10.0),
+            ],
+            cold_ms: None,
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn histograms_share_the_bins() {
+        let histograms = histograms(&[uniform("fast", 9.99), uniform("slow", 19.98)]);
+        assert_eq!(histograms.len(), 2);
+        let (fast, slow) = (&histograms[0], &histograms[1]);
+        assert_eq!(fast.0, "fast");
+        assert_eq!(slow.0, "slow");
+
+        // the bins span the greatest p99.9 and are centered on their points
+        assert_eq!(slow.1.len(), HISTOGRAM_BINS);
+        let bin = 19.98 / HISTOGRAM_BINS as f64;
+        for (i, ((fast_ms, _), (slow_ms, _))) in fast.1.iter().zip(&slow.1).enumerate() {
+            assert!(close(*fast_ms, (i as f64 + 0.5) * bin));
+            assert!(close(*fast_ms, *slow_ms));
+        }
+
+        // the slow branch is even over all the bins, the fast one over the first half
+        assert!(slow.1.iter().all(|(_, share)| close(*share, 99.9 / 20.0)));
+        assert!(fast.1[..10]
+            .iter()
+            .all(|(_, share)| close(*share, 99.9 / 10.0)));
+        // the outliers above the p99.9 are out of the bins
+        assert!(fast.1[10..].iter().all(|(_, share)| *share < 0.01));
+        let total = |points: &[(f64, f64)]| points.iter().map(|(_, share)| share).sum::<f64>();
+        assert!(total(&fast.1) < 100.0);
+        assert!(close(total(&slow.1), 99.9));
+    }
+
+    #[test]
+    fn histograms_without_latencies() {
+        let empty = BranchLatencies {
+            branch: "empty".to_string(),
+            throughput: 0.0,
+            distribution: Vec::new(),
+            cold_ms: None,
+        };
+        let histograms = histograms(&[empty]);
+        assert!(histograms[0]
+            .1
+            .iter()
+            .all(|(ms, share)| ms.is_finite() && *share == 0.0));
+    }
+
+    #[test]
+    fn cold_requests_next_to_the_throughput() {
+        let mut warm = uniform("warm", 10.0);
+        let mut cold = uniform("cold", 10.0);
+        let rpc = |branches| ReportData {
+            rpcs: vec![RpcLatencies {
+                rpc: "chains/main/blocks/head".to_string(),
+                branches,
+                order: Vec::new(),
+            }],
+            ..ReportData::default()
+        };
+        assert!(!render(&rpc(vec![warm.clone(), cold.clone()])).contains("Cold request"));
+
+        warm.cold_ms = Some(12.5);
+        cold.cold_ms = Some(250.0);
+        let html = render(&rpc(vec![warm, cold]));
+        assert!(html.contains("Cold request"));
+        assert!(html.contains("<title>warm: 12.50</title>"));
+        assert!(html.contains("<title>cold: 250.00</title>"));
+    }
+
+    #[test]
+    fn sweeps_by_connections() {
+        let step = |connections, throughput| SweepStep {
+            connections,
+            throughput,
+            latency_mean: 1.5,
+            latency_max: 3.0,
+        };
+        let html = render(&ReportData {
+            sweeps: vec![RpcSweep {
+                rpc: "chains/main/blocks/head".to_string(),
+                branches: vec![
+                    ("ocaml".to_string(), vec![step(1, 100.0), step(2, 180.0)]),
+                    ("tezedge".to_string(), vec![step(1, 120.0), step(2, 240.0)]),
+                ],
+            }],
+            ..ReportData::default()
+        });
+        assert!(html.contains("<h2>Connection sweeps</h2>"));
+        assert!(html.contains("Throughput by connections"));
+        assert!(html.contains("Mean latency by connections"));
+        assert_eq!(html.matches("<polyline").count(), 4);
+    }
+}
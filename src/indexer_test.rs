@@ -1,28 +1,35 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::thread;
 use std::time::Duration;
 
-use assert_json_diff::assert_json_eq;
+use assert_json_diff::assert_json_eq_no_panic;
+use failure::{bail, format_err};
+use futures::future::try_join_all;
 use url::Url;
 
 use crate::configuration::IndexerTestEnv;
+use crate::html_report;
+use crate::types::reference_index;
+use crate::utils::{http_client, interruptible};
 
-fn get_indexer_data(
+async fn get_indexer_data(
+    client: &reqwest::Client,
     to_block_header: i32,
-    node: Url,
-    indexer: Url,
-) -> Result<reqwest::blocking::Response, failure::Error> {
+    node: &Url,
+    indexer: &Url,
+) -> Result<reqwest::Response, failure::Error> {
     loop {
-        let response =
-            reqwest::blocking::get(&format!("{}/explorer/block/{}", indexer, to_block_header));
+        let response = client
+            .get(format!("{}explorer/block/{}", indexer, to_block_header))
+            .send()
+            .await;
 
         match response {
             Ok(res) => {
                 if !res.status().is_success() {
                     println!("[{}] Indexer still indexing. Sleeping for 10s", node);
-                    thread::sleep(Duration::from_secs(10));
+                    tokio::time::sleep(Duration::from_secs(10)).await;
                     continue;
                 } else {
                     return Ok(res);
@@ -30,41 +37,79 @@ fn get_indexer_data(
             }
             Err(_e) => {
                 println!("[{:?} or {}] Service not started yet", node, indexer);
-                thread::sleep(Duration::from_secs(10));
+                tokio::time::sleep(Duration::from_secs(10)).await;
                 continue;
             }
         }
     }
 }
 
-pub(crate) fn test_indexer(env: IndexerTestEnv) -> Result<(), failure::Error> {
-    let IndexerTestEnv {
-        tezedge_node,
-        tezedge_indexer,
-        ocaml_node,
-        ocaml_indexer,
-        level,
-    } = env;
+async fn get_indexed_block(
+    client: &reqwest::Client,
+    indexer: &Url,
+    level: i32,
+) -> Result<serde_json::Value, failure::Error> {
+    let response = client
+        .get(format!("{}explorer/block/{}", indexer, level))
+        .send()
+        .await?;
+    Ok(serde_json::from_str(&response.text().await?)?)
+}
+
+pub async fn test_indexer(env: IndexerTestEnv) -> Result<(), failure::Error> {
+    let IndexerTestEnv { targets, level } = env;
+
+    let client = http_client()?;
+    let reference = &targets[reference_index(&targets)];
+    let reference_indexer = reference
+        .indexer
+        .clone()
+        .ok_or_else(|| format_err!("[{}] Missing indexer url", reference.name))?;
 
-    // wait for the indexer to be fully indexed to the chosen point
-    get_indexer_data(level, tezedge_node, tezedge_indexer.clone())?;
-    get_indexer_data(level, ocaml_node, ocaml_indexer.clone())?;
+    // the last compared block is reported, when the test is interrupted
+    let mut checked = 0;
+    let result = interruptible(async {
+        // wait for the indexers to be fully indexed to the chosen point
+        try_join_all(targets.iter().filter_map(|target| {
+            target
+                .indexer
+                .as_ref()
+                .map(|indexer| get_indexer_data(&client, level, &target.url, indexer))
+        }))
+        .await?;
 
-    for n in 0..level {
-        println!("Checking and comparing indexed block {}", n);
-        let response_node1 =
-            reqwest::blocking::get(&format!("{}/explorer/block/{}", tezedge_indexer, n))?;
-        let response_node2 =
-            reqwest::blocking::get(&format!("{}/explorer/block/{}", ocaml_indexer, n))?;
+        for n in 0..level {
+            println!("Checking and comparing indexed block {}", n);
+            let reference_json = get_indexed_block(&client, &reference_indexer, n).await?;
 
-        let tezedge_json: serde_json::value::Value =
-            serde_json::from_str(&response_node1.text()?).expect("JSON was not well-formatted");
+            for target in targets.iter().filter(|t| t.name != reference.name) {
+                let indexer = target
+                    .indexer
+                    .as_ref()
+                    .ok_or_else(|| format_err!("[{}] Missing indexer url", target.name))?;
+                let json = get_indexed_block(&client, indexer, n).await?;
 
-        let ocaml_json: serde_json::value::Value =
-            serde_json::from_str(&response_node2.text()?).expect("JSON was not well-formatted");
+                println!("[{}] Comparing with {}", target.name, reference.name);
+                if let Err(diff) = assert_json_eq_no_panic(&json, &reference_json) {
+                    html_report::record_indexer_mismatch(&target.name, &reference.name, n, &diff);
+                    bail!(
+                        "[{}] Indexed block {} differs from {}:\n{}",
+                        target.name,
+                        n,
+                        reference.name,
+                        diff
+                    );
+                }
+            }
+            checked = n + 1;
+        }
+        Ok(())
+    })
+    .await;
 
-        assert_json_eq!(tezedge_json, ocaml_json);
+    match result {
+        Ok(()) => println!("Json responses are identical!"),
+        Err(_) => println!("Indexed blocks identical before the failure: {}", checked),
     }
-    println!("Json responses are identical!");
-    Ok(())
+    result
 }
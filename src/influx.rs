@@ -0,0 +1,587 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::UdpSocket;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::bail;
+use url::Url;
+
+use crate::types::Branch;
+use crate::utils::http_client;
+use crate::wrk::{calc_max_latency, calc_throughput, us_to_ms, WrkResultMap};
+
+/// Interval of the writes of the buffered points to a http sink
+pub const HTTP_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Destination of the points in the InfluxDB line protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfluxSink {
+    /// Appends the lines to the file
+    File(String),
+    /// Sends every line in a datagram to `udp://host:port`
+    Udp(String),
+    /// Posts the buffered lines to the write endpoint, e.g.
+    /// `http://localhost:8086/write?db=tezos` or `http://localhost:8086/api/v2/write?bucket=tezos`
+    Http(Url),
+}
+
+impl FromStr for InfluxSink {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("udp://") {
+            if address.is_empty() {
+                bail!("expected udp://HOST:PORT");
+            }
+            Ok(InfluxSink::Udp(address.to_string()))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(InfluxSink::Http(s.parse()?))
+        } else if s.is_empty() {
+            bail!("expected a file, udp://HOST:PORT or a http(s) url");
+        } else {
+            Ok(InfluxSink::File(s.to_string()))
+        }
+    }
+}
+
+/// Value of a field of a point
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    Text(String),
+}
+
+/// A single measurement, timestamped when it is created
+#[derive(Debug, Clone)]
+pub struct Point {
+    measurement: String,
+    tags: Vec<(String, String)>,
+    fields: Vec<(String, FieldValue)>,
+    timestamp: u128,
+}
+
+impl Point {
+    pub fn new(measurement: &str) -> Self {
+        Self {
+            measurement: measurement.to_string(),
+            tags: Vec::new(),
+            fields: Vec::new(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos()),
+        }
+    }
+
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        if !value.is_empty() {
+            self.tags.push((key.to_string(), value.to_string()));
+        }
+        self
+    }
+
+    /// The node label and branch type tags of the branch
+    pub fn branch(self, branch: &Branch) -> Self {
+        self.tag("node", &branch.name).tag(
+            "branch_type",
+            &branch.branch_type.to_string().to_ascii_lowercase(),
+        )
+    }
+
+    pub fn field(mut self, key: &str, value: FieldValue) -> Self {
+        self.fields.push((key.to_string(), value));
+        self
+    }
+
+    pub fn float(self, key: &str, value: impl Into<f64>) -> Self {
+        self.field(key, FieldValue::Float(value.into()))
+    }
+
+    /// The point in the line protocol, the tags are sorted as recommended for the writes, none
+    /// when no field is left, as a line without fields is invalid
+    pub fn line(&self, commit: Option<&str>) -> Option<String> {
+        let escape = |s: &str, chars: &[char]| {
+            let mut escaped = String::new();
+            for c in s.chars() {
+                if c == '\\' || chars.contains(&c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        };
+        let key = |s: &str| escape(s, &[',', '=', ' ']);
+
+        let mut tags = self.tags.clone();
+        if let Some(commit) = commit {
+            tags.push(("commit".to_string(), commit.to_string()));
+        }
+        tags.sort();
+
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        for (tag, value) in &tags {
+            line.push_str(&format!(",{}={}", key(tag), key(value)));
+        }
+        // the line protocol has no representation of nan and infinity
+        let fields = self
+            .fields
+            .iter()
+            .filter(|(_, value)| !matches!(value, FieldValue::Float(v) if !v.is_finite()))
+            .map(|(field, value)| {
+                let value = match value {
+                    FieldValue::Float(v) => v.to_string(),
+                    FieldValue::Integer(v) => format!("{}i", v),
+                    FieldValue::Text(v) => format!("\"{}\"", escape(v, &['"'])),
+                };
+                format!("{}={}", key(field), value)
+            })
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            return None;
+        }
+        line.push_str(&format!(" {} {}", fields.join(","), self.timestamp));
+        Some(line)
+    }
+}
+
+/// Lines kept for a failing http sink, the oldest lines are dropped beyond it
+pub const MAX_PENDING_LINES: usize = 100_000;
+
+/// The sink opened for the writes
+enum Output {
+    File(File),
+    Udp(UdpSocket),
+    Http,
+}
+
+struct Writer {
+    sink: InfluxSink,
+    output: Output,
+    commit: Option<String>,
+    /// Lines waiting for the next post to a http sink
+    pending: Vec<String>,
+}
+
+// the measurements are emitted from the async tasks and from the blocking wrk runners
+static WRITER: Mutex<Option<Writer>> = Mutex::new(None);
+
+/// Sends the points emitted from now on to the sink, tagged with the commit, the file is
+/// opened and the udp socket bound only once
+pub fn configure(sink: InfluxSink, commit: Option<String>) -> Result<(), failure::Error> {
+    let output = match &sink {
+        InfluxSink::File(file) => {
+            Output::File(OpenOptions::new().create(true).append(true).open(file)?)
+        }
+        InfluxSink::Udp(address) => {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect(address.as_str())?;
+            Output::Udp(socket)
+        }
+        InfluxSink::Http(_) => Output::Http,
+    };
+    *WRITER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Writer {
+        sink,
+        output,
+        commit,
+        pending: Vec::new(),
+    });
+    Ok(())
+}
+
+/// Writes the point to the configured sink, a failing sink does not fail the test
+pub fn emit(point: Point) {
+    let mut writer = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    let writer = match writer.as_mut() {
+        Some(writer) => writer,
+        None => return,
+    };
+    let line = match point.line(writer.commit.as_deref()) {
+        Some(line) => line,
+        None => return,
+    };
+
+    let result = match &mut writer.output {
+        Output::File(file) => writeln!(file, "{}", line),
+        Output::Udp(socket) => socket.send(line.as_bytes()).map(|_| ()),
+        Output::Http => {
+            writer.pending.push(line);
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Cannot write to the influx sink {:?}: {}", writer.sink, e);
+    }
+}
+
+/// Emits the wrk results of every branch for the rpc
+pub fn emit_wrk_results(measurement: &str, rpc: &str, results: &WrkResultMap) {
+    for (branch, result) in results {
+        emit(
+            Point::new(measurement)
+                .branch(branch)
+                .tag("rpc", rpc)
+                .float(
+                    "throughput",
+                    calc_throughput(result.requests(), result.duration()),
+                )
+                .float("latency_p50_ms", us_to_ms(result.latency_p50()))
+                .float("latency_p99_ms", us_to_ms(result.latency_p99()))
+                .float("latency_max_ms", calc_max_latency(result.latency_max()))
+                .float("error_rate", result.error_rate()),
+        );
+    }
+}
+
+/// Emits the durations of the cold requests of the rpc before the warm-up
+pub fn emit_cold_requests(rpc: &str, cold: &[(Branch, f32)]) {
+    for (branch, ms) in cold {
+        emit(
+            Point::new("cold_request")
+                .branch(branch)
+                .tag("rpc", rpc)
+                .float("duration_ms", *ms),
+        );
+    }
+}
+
+/// Posts the pending lines to a http sink
+pub async fn flush() {
+    let (url, lines) = {
+        let mut writer = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+        match writer.as_mut() {
+            Some(Writer {
+                sink: InfluxSink::Http(url),
+                pending,
+                ..
+            }) if !pending.is_empty() => (url.clone(), std::mem::take(pending)),
+            _ => return,
+        }
+    };
+
+    let result = async {
+        let response = http_client()?
+            .post(url.clone())
+            .body(lines.join("\n"))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("{}", response.status());
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        eprintln!(
+            "Cannot write {} points to the influx sink {}, keeping them for the next write: {}",
+            lines.len(),
+            url,
+            e
+        );
+        keep_pending(lines);
+    }
+}
+
+/// Puts the lines of a failed post back before the lines emitted meanwhile
+fn keep_pending(mut lines: Vec<String>) {
+    let mut writer = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    let writer = match writer.as_mut() {
+        Some(writer) => writer,
+        None => return,
+    };
+    lines.append(&mut writer.pending);
+    if lines.len() > MAX_PENDING_LINES {
+        let dropped = lines.len() - MAX_PENDING_LINES;
+        eprintln!(
+            "Dropping the {} oldest points for the influx sink {:?}",
+            dropped, writer.sink
+        );
+        lines.drain(..dropped);
+    }
+    writer.pending = lines;
+}
+
+/// Posts the pending lines periodically until the process exits
+pub fn spawn_flush() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(HTTP_FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+
+    // the tests writing to the sinks share the global writer
+    static SINKS: Mutex<()> = Mutex::new(());
+
+    fn point(measurement: &str) -> Point {
+        let mut point = Point::new(measurement);
+        point.timestamp = 1_600_000_000_000_000_000;
+        point
+    }
+
+    /// The line without the timestamp of the emitted point
+    fn without_timestamp(line: &str) -> &str {
+        line.rsplit_once(' ').map_or(line, |(line, _)| line)
+    }
+
+    #[test]
+    fn line_sorts_tags_and_adds_commit() {
+        let point = point("latency")
+            .tag("rpc", "chains/main/blocks/head")
+            .tag("node", "tezedge")
+            .float("throughput", 1000.5);
+
+        assert_eq!(
+            point.line(Some("abc123")).unwrap(),
+            "latency,commit=abc123,node=tezedge,rpc=chains/main/blocks/head \
+             throughput=1000.5 1600000000000000000"
+        );
+        assert_eq!(
+            point.line(None).unwrap(),
+            "latency,node=tezedge,rpc=chains/main/blocks/head throughput=1000.5 \
+             1600000000000000000"
+        );
+    }
+
+    #[test]
+    fn line_escapes_keys_and_values() {
+        let point = point("my measurement,x")
+            .tag("rpc path", "a=b,c d")
+            .field("re\"sult", FieldValue::Text("say \"hi\", a=b".to_string()));
+
+        assert_eq!(
+            point.line(None).unwrap(),
+            "my\\ measurement\\,x,rpc\\ path=a\\=b\\,c\\ d \
+             re\"sult=\"say \\\"hi\\\", a=b\" 1600000000000000000"
+        );
+    }
+
+    #[test]
+    fn line_formats_integers_and_drops_non_finite_floats() {
+        let point = point("chaos")
+            .field("level", FieldValue::Integer(-42))
+            .float("nan", f64::NAN)
+            .float("infinity", f64::INFINITY)
+            .float("seconds", 0.25);
+
+        assert_eq!(
+            point.line(None).unwrap(),
+            "chaos level=-42i,seconds=0.25 1600000000000000000"
+        );
+    }
+
+    #[test]
+    fn line_skips_points_without_fields() {
+        assert_eq!(point("empty").tag("node", "ocaml").line(None), None);
+        assert_eq!(
+            point("non_finite")
+                .float("nan", f64::NAN)
+                .float("infinity", f64::NEG_INFINITY)
+                .line(Some("abc123")),
+            None
+        );
+    }
+
+    #[test]
+    fn tag_skips_empty_values() {
+        assert_eq!(
+            point("m")
+                .tag("node", "")
+                .float("v", 1.0)
+                .line(None)
+                .unwrap(),
+            "m v=1 1600000000000000000"
+        );
+    }
+
+    #[test]
+    fn sink_from_str() {
+        assert_eq!(
+            "udp://localhost:8089".parse::<InfluxSink>().unwrap(),
+            InfluxSink::Udp("localhost:8089".to_string())
+        );
+        assert_eq!(
+            "http://localhost:8086/write?db=tezos"
+                .parse::<InfluxSink>()
+                .unwrap(),
+            InfluxSink::Http("http://localhost:8086/write?db=tezos".parse().unwrap())
+        );
+        assert_eq!(
+            "/tmp/influx.lines".parse::<InfluxSink>().unwrap(),
+            InfluxSink::File("/tmp/influx.lines".to_string())
+        );
+        assert!("udp://".parse::<InfluxSink>().is_err());
+        assert!("".parse::<InfluxSink>().is_err());
+    }
+
+    #[test]
+    fn emit_sends_datagrams_to_udp_sink() {
+        let _sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        configure(
+            InfluxSink::Udp(receiver.local_addr().unwrap().to_string()),
+            Some("abc123".to_string()),
+        )
+        .unwrap();
+
+        // a point without fields is not sent
+        emit(Point::new("empty").tag("node", "ocaml"));
+        emit(
+            Point::new("bootstrap")
+                .tag("node", "tezedge")
+                .field("level", FieldValue::Integer(100)),
+        );
+        *WRITER.lock().unwrap() = None;
+
+        let mut buffer = [0; 1024];
+        let (received, _) = receiver.recv_from(&mut buffer).unwrap();
+        let line = std::str::from_utf8(&buffer[..received]).unwrap();
+        assert_eq!(
+            without_timestamp(line),
+            "bootstrap,commit=abc123,node=tezedge level=100i"
+        );
+    }
+
+    /// Accepts a single http request, answers it with the status and returns its request line
+    /// and body
+    fn http_stub(status: &'static str) -> (Url, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?db=tezos", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n",
+                status
+            )
+            .unwrap();
+            sender
+                .send((
+                    request_line.trim().to_string(),
+                    String::from_utf8(body).unwrap(),
+                ))
+                .unwrap();
+        });
+        (url.parse().unwrap(), receiver)
+    }
+
+    #[test]
+    fn flush_posts_pending_lines_to_http_sink() {
+        let _sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+        let (url, requests) = http_stub("204 No Content");
+        configure(InfluxSink::Http(url), None).unwrap();
+
+        emit(Point::new("soak").tag("node", "b").float("rss_kb", 2048.0));
+        emit(Point::new("soak").tag("node", "a").float("rss_kb", 1024.0));
+        emit(Point::new("soak").float("nan", f64::NAN));
+        tokio::runtime::Runtime::new().unwrap().block_on(flush());
+        let pending = WRITER.lock().unwrap().take().unwrap().pending;
+
+        let (request_line, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request_line, "POST /write?db=tezos HTTP/1.1");
+        assert_eq!(
+            body.lines().map(without_timestamp).collect::<Vec<_>>(),
+            vec!["soak,node=b rss_kb=2048", "soak,node=a rss_kb=1024"]
+        );
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn failed_flush_keeps_the_lines() {
+        let _sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (url, requests) = http_stub("500 Internal Server Error");
+        configure(InfluxSink::Http(url), None).unwrap();
+
+        emit(Point::new("soak").tag("node", "a").float("rss_kb", 1.0));
+        runtime.block_on(flush());
+        requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        emit(Point::new("soak").tag("node", "b").float("rss_kb", 2.0));
+
+        // the next flush posts the kept lines before the new ones
+        let (url, requests) = http_stub("204 No Content");
+        WRITER.lock().unwrap().as_mut().unwrap().sink = InfluxSink::Http(url);
+        runtime.block_on(flush());
+        let pending = WRITER.lock().unwrap().take().unwrap().pending;
+
+        let (_, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            body.lines().map(without_timestamp).collect::<Vec<_>>(),
+            vec!["soak,node=a rss_kb=1", "soak,node=b rss_kb=2"]
+        );
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn emit_appends_lines_to_file_sink() {
+        let _sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+        let file = std::env::temp_dir().join(format!(
+            "tezos-node-bootstrap-influx-{}.lines",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&file);
+        configure(
+            InfluxSink::File(file.to_string_lossy().to_string()),
+            Some("abc123".to_string()),
+        )
+        .unwrap();
+
+        emit(Point::new("latency").float("throughput", 10.0));
+        emit(Point::new("latency").float("throughput", 20.0));
+        *WRITER.lock().unwrap() = None;
+
+        let lines = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            lines.lines().map(without_timestamp).collect::<Vec<_>>(),
+            vec![
+                "latency,commit=abc123 throughput=10",
+                "latency,commit=abc123 throughput=20"
+            ]
+        );
+        // the file is opened when the sink is configured
+        assert!(configure(
+            InfluxSink::File("/nonexistent/tezos-node-bootstrap/influx.lines".to_string()),
+            None
+        )
+        .is_err());
+    }
+}
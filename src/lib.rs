@@ -0,0 +1,56 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Harness for bootstrapping Tezos nodes and comparing their rpc correctness and performance.
+//!
+//! Every subcommand of the `tezos-node-bootstrap` binary is available as a function taking
+//! its env struct from [`configuration`] and returning a `Result`.
+
+/// Waits for nodes to be bootstrapped to a level
+pub mod bootstrap;
+/// Bootstrap under a constant rate of rpc requests
+pub mod bootstrap_load;
+/// Restarts of a bootstrapping node and the verification of its recovery
+pub mod chaos;
+/// Compares the rpc responses of the targets to the reference target
+pub mod compare_test;
+/// Command line args, env structs and the suite configuration file
+pub mod configuration;
+/// Protocol constants read from the nodes
+pub mod constants;
+/// Crawls a node for the url lists of the tests
+pub mod generate_urls;
+/// Self-contained html report of a run
+pub mod html_report;
+/// Compares the indexers connected to the targets
+pub mod indexer_test;
+/// Sink of the measurements in the InfluxDB line protocol
+pub mod influx;
+/// Live metrics of a run in the Prometheus format
+pub mod metrics;
+/// Weighted random mix of rpc requests issued concurrently
+pub mod mixed_workload;
+/// Replays a request log against the targets
+pub mod replay;
+/// Compares the responses sampled during the load tests
+pub mod sampling;
+/// Parameterized rpc sequences of the sequential-test
+pub mod scenario;
+/// Order and concurrency of the measured branches
+pub mod schedule;
+/// Measures the duration of a sequence of rpc requests
+pub mod sequential_request_test;
+/// Endurance test with windowed snapshots of a steady mixed load
+pub mod soak;
+/// Runs the subcommands as phases of a test suite
+pub mod suite;
+/// Summary table of the performance-test
+pub mod summary;
+/// Finds the saturation point of the nodes by ramping the wrk connections
+pub mod sweep;
+pub mod types;
+pub mod utils;
+/// Throughput and max latency comparison using wrk
+pub mod wrk;
+/// Latency test with a constant request rate using wrk2
+pub mod wrk2;
@@ -1,43 +1,100 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-// PoC, needs refactoring
-use crate::configuration::{
-    bootstrap_app, BootstrapEnv, IndexerTestEnv, RpcPerformanceTestEnv, RpcLatencyTestEnv, SequentialTestEnv,
-};
-
-mod bootstrap;
-mod configuration;
-mod indexer_test;
-mod sequential_request_test;
-mod types;
-mod utils;
-mod wrk;
-mod wrk2;
-
-fn main() {
+use std::process;
+
+use tezos_node_bootstrap::configuration::{bootstrap_app, ConfigError, RunSuiteEnv, SuiteConfig};
+use tezos_node_bootstrap::{html_report, influx, metrics, suite};
+
+#[tokio::main]
+async fn main() {
     let matches = bootstrap_app().get_matches();
+    let html_report = matches.value_of("html-report").map(str::to_string);
+    let metrics_file = matches.value_of("metrics-file").map(str::to_string);
+
+    if let Some(address) = matches.value_of("metrics-listen") {
+        if let Err(e) = metrics::serve(address).await {
+            eprintln!("Cannot serve the metrics on {}: {}", address, e);
+            process::exit(2)
+        }
+    }
+    if let Some(file) = &metrics_file {
+        metrics::spawn_metrics_file(file.clone());
+    }
+    if let Some(sink) = matches.value_of("influx") {
+        match sink.parse() {
+            Ok(parsed) => {
+                let commit = matches.value_of("commit").map(str::to_string);
+                if let Err(e) = influx::configure(parsed, commit) {
+                    eprintln!("Cannot open the influx sink {}: {}", sink, e);
+                    process::exit(2)
+                }
+                influx::spawn_flush();
+            }
+            Err(e) => {
+                eprintln!("Invalid influx sink {}: {}", sink, e);
+                process::exit(2)
+            }
+        }
+    }
+
+    let result = match matches.value_of("config") {
+        Some(config) => {
+            let mut suite = SuiteConfig::from_file(config).unwrap_or_else(|e| {
+                eprintln!("Cannot load config file {}: {}", config, e);
+                process::exit(2)
+            });
+            for key in matches.values_of("unset").into_iter().flatten() {
+                suite.unset(key);
+            }
+            let args: Vec<String> = std::env::args().collect();
+            let command_line = suite.command_line(&matches, &args);
+            println!("Running: {}", command_line[1..].join(" "));
 
-    if let Some(subcommand) = matches.subcommand_matches("bootstrap") {
-        let env = BootstrapEnv::from_args(subcommand);
-        bootstrap::start_bootstrap(env);
-    } else if let Some(ref subcommand) = matches.subcommand_matches("performance-test") {
-        let env = RpcPerformanceTestEnv::from_args(subcommand);
-        if let Err(e) = wrk::test_rpc_performance(env) {
-            panic!("Error in wrk tests: {}", e)
+            let matches = bootstrap_app().get_matches_from(command_line);
+            run(&matches, Some(&suite), &args[0]).await
         }
-    } else if let Some(ref subcommand) = matches.subcommand_matches("latency-test") {
-        let env = RpcLatencyTestEnv::from_args(subcommand);
-        if let Err(e) = wrk2::test_rpc_performance(env) {
-            panic!("Error in wrk tests: {}", e)
+        None => run(&matches, None, "").await,
+    };
+
+    influx::flush().await;
+    // the final values of the run
+    if let Some(file) = metrics_file {
+        if let Err(e) = metrics::write_metrics_file(&file) {
+            eprintln!("Cannot write the metrics file {}: {}", file, e);
+        }
+    }
+    // the report also covers the failed runs
+    if let Some(file) = html_report {
+        if let Err(e) = html_report::write_html_report(&file) {
+            eprintln!("Cannot write the html report {}: {}", file, e);
         }
-    } else if let Some(ref subcommand) = matches.subcommand_matches("indexer-test") {
-        let env = IndexerTestEnv::from_args(subcommand);
-        if let Err(e) = indexer_test::test_indexer(env) {
-            panic!("Error in indexer tests: {}", e)
+    }
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        if e.downcast_ref::<ConfigError>().is_some() {
+            process::exit(2)
+        }
+        process::exit(1)
+    }
+}
+
+async fn run(
+    matches: &clap::ArgMatches<'_>,
+    suite: Option<&SuiteConfig>,
+    bin: &str,
+) -> Result<(), failure::Error> {
+    match (matches.subcommand_matches("run-suite"), suite) {
+        (Some(subcommand), Some(suite)) => {
+            let env = RunSuiteEnv::from_args(subcommand)?;
+            if suite::run_suite(suite, env, bin).await?.passed {
+                Ok(())
+            } else {
+                failure::bail!("Test suite failed")
+            }
         }
-    } else if let Some(ref subcommand) = matches.subcommand_matches("sequential-test") {
-        let env = SequentialTestEnv::from_args(subcommand);
-        sequential_request_test::test_sequential_requests(env)
+        (Some(_), None) => failure::bail!("The run-suite subcommand requires the --config arg"),
+        _ => suite::run_subcommand(matches).await,
     }
 }
@@ -0,0 +1,435 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+use crate::types::{RequestError, WrkResult};
+
+/// Upper bounds of the latency histogram buckets in seconds
+pub const LATENCY_BUCKETS: [f64; 13] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Interval of the rewrites of the textfile-collector file
+pub const METRICS_FILE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations of every bucket, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Clone)]
+struct NodeLevel {
+    level: i32,
+    blocks_per_second: f64,
+    updated: Instant,
+}
+
+/// Live metrics of the running test
+#[derive(Debug, Default)]
+struct Metrics {
+    levels: BTreeMap<String, NodeLevel>,
+    latencies: BTreeMap<String, Histogram>,
+    errors: BTreeMap<(String, String), u64>,
+    /// Durations of the last cold requests in seconds by the target and the rpc
+    cold_requests: BTreeMap<(String, String), f64>,
+}
+
+// every subcommand of the suite runs in the same process
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    levels: BTreeMap::new(),
+    latencies: BTreeMap::new(),
+    errors: BTreeMap::new(),
+    cold_requests: BTreeMap::new(),
+});
+
+fn update(f: impl FnOnce(&mut Metrics)) {
+    f(&mut METRICS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Head level of a monitored node, the blocks per second are measured between the updates
+pub fn set_head_level(node: &Url, level: i32) {
+    let now = Instant::now();
+    update(|metrics| {
+        let blocks_per_second = match metrics.levels.get(node.as_str()) {
+            Some(previous) if now > previous.updated => {
+                f64::from(level - previous.level) / (now - previous.updated).as_secs_f64()
+            }
+            _ => 0.0,
+        };
+        metrics.levels.insert(
+            node.to_string(),
+            NodeLevel {
+                level,
+                blocks_per_second,
+                updated: now,
+            },
+        );
+    });
+}
+
+/// Latency of a request of the harness to the target, also of a failed one
+pub fn observe_latency(target: &str, latency: Duration) {
+    let seconds = latency.as_secs_f64();
+    update(|metrics| {
+        let histogram = metrics.latencies.entry(target.to_string()).or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    });
+}
+
+/// Requests of a wrk run against the target, spread over the buckets by the latency
+/// distribution of the run, and its errors
+pub fn observe_wrk_result(target: &str, result: &WrkResult) {
+    let requests = result.requests().round().max(0.0) as u64;
+    // requests of the run up to the bound, by the greatest percentile within the bound
+    let cumulative = |le: f64| {
+        let percentile = result
+            .latency_distribution()
+            .iter()
+            .filter(|p| f64::from(p.latency) * 0.000_001 <= le)
+            .map(|p| f64::from(p.percentile))
+            .fold(0.0, f64::max);
+        (requests as f64 * percentile / 100.0).round() as u64
+    };
+    let errors = result.errors();
+    update(|metrics| {
+        let histogram = metrics.latencies.entry(target.to_string()).or_default();
+        let mut below = 0;
+        for (bucket, le) in LATENCY_BUCKETS.iter().enumerate() {
+            let up_to = cumulative(*le).max(below);
+            histogram.buckets[bucket] += up_to - below;
+            below = up_to;
+        }
+        histogram.sum += f64::from(*result.latency_mean()) * 0.000_001 * requests as f64;
+        histogram.count += requests;
+
+        for (error, count) in [
+            (RequestError::Connect, errors.connect),
+            (RequestError::Read, errors.read),
+            (RequestError::Write, errors.write),
+            (RequestError::Status, errors.status),
+            (RequestError::Timeout, errors.timeout),
+        ] {
+            if count > 0.0 {
+                *metrics
+                    .errors
+                    .entry((target.to_string(), error.name().to_string()))
+                    .or_default() += count.round() as u64;
+            }
+        }
+    });
+}
+
+/// Failed request of the harness to the target, by the wrk error categories
+pub fn count_error(target: &str, kind: &str) {
+    update(|metrics| {
+        *metrics
+            .errors
+            .entry((target.to_string(), kind.to_string()))
+            .or_default() += 1
+    });
+}
+
+/// Duration of the single request of the rpc to the target before the warm-up
+pub fn set_cold_request(target: &str, rpc: &str, duration: Duration) {
+    update(|metrics| {
+        metrics.cold_requests.insert(
+            (target.to_string(), rpc.to_string()),
+            duration.as_secs_f64(),
+        );
+    });
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics in the Prometheus text exposition format
+pub fn render() -> String {
+    let metrics = METRICS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut text = String::new();
+
+    let _ = writeln!(text, "# HELP tezos_node_head_level Head level of the node");
+    let _ = writeln!(text, "# TYPE tezos_node_head_level gauge");
+    for (node, level) in &metrics.levels {
+        let _ = writeln!(
+            text,
+            "tezos_node_head_level{{node=\"{}\"}} {}",
+            label(node),
+            level.level
+        );
+    }
+    let _ = writeln!(
+        text,
+        "# HELP tezos_node_blocks_per_second Applied blocks per second between the last two head levels"
+    );
+    let _ = writeln!(text, "# TYPE tezos_node_blocks_per_second gauge");
+    for (node, level) in &metrics.levels {
+        let _ = writeln!(
+            text,
+            "tezos_node_blocks_per_second{{node=\"{}\"}} {}",
+            label(node),
+            level.blocks_per_second
+        );
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP tezos_node_rpc_latency_seconds Latency of the rpc requests made by the harness"
+    );
+    let _ = writeln!(text, "# TYPE tezos_node_rpc_latency_seconds histogram");
+    for (target, histogram) in &metrics.latencies {
+        let target = label(target);
+        let mut cumulative = 0;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "tezos_node_rpc_latency_seconds_bucket{{target=\"{}\",le=\"{}\"}} {}",
+                target, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            text,
+            "tezos_node_rpc_latency_seconds_bucket{{target=\"{}\",le=\"+Inf\"}} {}",
+            target, histogram.count
+        );
+        let _ = writeln!(
+            text,
+            "tezos_node_rpc_latency_seconds_sum{{target=\"{}\"}} {}",
+            target, histogram.sum
+        );
+        let _ = writeln!(
+            text,
+            "tezos_node_rpc_latency_seconds_count{{target=\"{}\"}} {}",
+            target, histogram.count
+        );
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP tezos_node_rpc_errors_total Failed rpc requests made by the harness"
+    );
+    let _ = writeln!(text, "# TYPE tezos_node_rpc_errors_total counter");
+    for ((target, kind), count) in &metrics.errors {
+        let _ = writeln!(
+            text,
+            "tezos_node_rpc_errors_total{{target=\"{}\",kind=\"{}\"}} {}",
+            label(target),
+            label(kind),
+            count
+        );
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP tezos_node_cold_request_seconds Duration of the request before the warm-up"
+    );
+    let _ = writeln!(text, "# TYPE tezos_node_cold_request_seconds gauge");
+    for ((target, rpc), seconds) in &metrics.cold_requests {
+        let _ = writeln!(
+            text,
+            "tezos_node_cold_request_seconds{{target=\"{}\",rpc=\"{}\"}} {}",
+            label(target),
+            label(rpc),
+            seconds
+        );
+    }
+    text
+}
+
+/// Serves the metrics on `GET /metrics` of the address until the process exits
+pub async fn serve(address: &str) -> Result<(), failure::Error> {
+    let listener = TcpListener::bind(address).await?;
+    println!("Serving the metrics on http://{}/metrics", address);
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]);
+                let response = if request.starts_with("GET /metrics ") {
+                    let body = render();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string()
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Writes the metrics for the textfile collector of the node exporter, the file is replaced
+/// atomically, so the collector never reads a partial file
+pub fn write_metrics_file(file: &str) -> Result<(), failure::Error> {
+    let temp = format!("{}.tmp", file);
+    fs::write(&temp, render())?;
+    fs::rename(&temp, file)?;
+    Ok(())
+}
+
+/// Rewrites the metrics file periodically until the process exits
+pub fn spawn_metrics_file(file: String) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(METRICS_FILE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = write_metrics_file(&file) {
+                eprintln!("Cannot write the metrics file {}: {}", file, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WrkErrors;
+
+    /// Rendered lines of the metric, the metrics are global, so every test has its own target
+    fn lines(metric: &str, target: &str) -> Vec<String> {
+        render()
+            .lines()
+            .filter(|line| line.starts_with(metric) && line.contains(target))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn latency_buckets_are_cumulative() {
+        let target = "buckets\"target";
+        for millis in &[1, 3, 3, 200, 60_000] {
+            observe_latency(target, Duration::from_millis(*millis));
+        }
+
+        let buckets = lines("tezos_node_rpc_latency_seconds_bucket", "buckets");
+        assert_eq!(buckets.len(), LATENCY_BUCKETS.len() + 1);
+        assert_eq!(
+            buckets[..3],
+            [
+                "tezos_node_rpc_latency_seconds_bucket{target=\"buckets\\\"target\",le=\"0.001\"} 1",
+                "tezos_node_rpc_latency_seconds_bucket{target=\"buckets\\\"target\",le=\"0.005\"} 3",
+                "tezos_node_rpc_latency_seconds_bucket{target=\"buckets\\\"target\",le=\"0.01\"} 3",
+            ]
+        );
+        assert!(buckets[6].ends_with("le=\"0.25\"} 4"));
+        // the latency above every bucket is only counted by +Inf
+        assert!(buckets[LATENCY_BUCKETS.len() - 1].ends_with("le=\"30\"} 4"));
+        assert!(buckets[LATENCY_BUCKETS.len()].ends_with("le=\"+Inf\"} 5"));
+        assert_eq!(
+            lines("tezos_node_rpc_latency_seconds_count", "buckets"),
+            ["tezos_node_rpc_latency_seconds_count{target=\"buckets\\\"target\"} 5"]
+        );
+        let sum = lines("tezos_node_rpc_latency_seconds_sum", "buckets");
+        let sum: f64 = sum[0].rsplit(' ').next().unwrap().parse().unwrap();
+        assert!((sum - 60.207).abs() < 1e-9);
+    }
+
+    #[test]
+    fn errors_and_head_levels() {
+        count_error("errors-target", "timeout");
+        count_error("errors-target", "timeout");
+        count_error("errors-target", "status");
+        assert_eq!(
+            lines("tezos_node_rpc_errors_total", "errors-target"),
+            [
+                "tezos_node_rpc_errors_total{target=\"errors-target\",kind=\"status\"} 1",
+                "tezos_node_rpc_errors_total{target=\"errors-target\",kind=\"timeout\"} 2",
+            ]
+        );
+
+        let node: Url = "http://levels-node:8732/".parse().unwrap();
+        set_head_level(&node, 10);
+        assert_eq!(
+            lines("tezos_node_head_level", "levels-node"),
+            ["tezos_node_head_level{node=\"http://levels-node:8732/\"} 10"]
+        );
+        assert_eq!(
+            lines("tezos_node_blocks_per_second", "levels-node"),
+            ["tezos_node_blocks_per_second{node=\"http://levels-node:8732/\"} 0"]
+        );
+    }
+
+    #[test]
+    fn cold_requests_by_rpc() {
+        set_cold_request(
+            "cold-target",
+            "chains/main/blocks/head",
+            Duration::from_millis(250),
+        );
+        set_cold_request(
+            "cold-target",
+            "chains/main/blocks/head",
+            Duration::from_millis(500),
+        );
+        set_cold_request("cold-target", "network/version", Duration::from_millis(2));
+        assert_eq!(
+            lines("tezos_node_cold_request_seconds", "cold-target"),
+            [
+                "tezos_node_cold_request_seconds{target=\"cold-target\",rpc=\"chains/main/blocks/head\"} 0.5",
+                "tezos_node_cold_request_seconds{target=\"cold-target\",rpc=\"network/version\"} 0.002",
+            ]
+        );
+    }
+
+    #[test]
+    fn wrk_results_by_their_distribution() {
+        // 100 requests of 0.5ms to 50ms
+        let latencies = (1..=100).map(|i| i as f32 * 500.0).collect::<Vec<_>>();
+        let errors = WrkErrors {
+            timeout: 3.0,
+            status: 2.0,
+            ..WrkErrors::default()
+        };
+        let result =
+            WrkResult::from_latencies(1_000_000.0, &latencies).with_errors(errors, Vec::new());
+        observe_wrk_result("wrk-target", &result);
+
+        let buckets = lines("tezos_node_rpc_latency_seconds_bucket", "wrk-target");
+        let counts = buckets
+            .iter()
+            .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+            .collect::<Vec<u64>>();
+        // up to the p1 of 0.5ms, the p10 of 5ms, the p20 of 10ms, the p50 of 25ms and the rest
+        assert_eq!(
+            counts,
+            [1, 10, 20, 50, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100]
+        );
+        let sum = lines("tezos_node_rpc_latency_seconds_sum", "wrk-target");
+        let sum: f64 = sum[0].rsplit(' ').next().unwrap().parse().unwrap();
+        assert!((sum - 100.0 * 0.02525).abs() < 1e-6);
+        assert_eq!(
+            lines("tezos_node_rpc_errors_total", "wrk-target"),
+            [
+                "tezos_node_rpc_errors_total{target=\"wrk-target\",kind=\"status\"} 2",
+                "tezos_node_rpc_errors_total{target=\"wrk-target\",kind=\"timeout\"} 3",
+            ]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use itertools::Itertools;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::configuration::RpcPerformanceTestEnv;
+use crate::html_report;
+use crate::influx;
+use crate::metrics;
+use crate::summary::Summary;
+use crate::types::{
+    reference_index, Branch, RequestError, RpcRequest, StatusCount, WrkErrors, WrkResult,
+};
+use crate::utils::{get_requests, http_client, interruptible, rpc_request};
+use crate::wrk::{
+    calc_max_latency, calc_throughput, calculate_and_display_statistics, check_regressions,
+    us_to_ms,
+};
+
+/// Outcome of a single request of the mix
+#[derive(Debug, Clone)]
+pub(crate) struct Sample {
+    pub index: usize,
+    /// Latency in microseconds
+    pub latency: f32,
+    pub status: Option<u16>,
+    pub error: Option<RequestError>,
+}
+
+/// Sends the request of the url with the index to the branch and observes its outcome
+pub(crate) async fn sample(
+    client: &reqwest::Client,
+    branch: &Branch,
+    rpc: &RpcRequest,
+    index: usize,
+) -> Sample {
+    let start = Instant::now();
+    let (status, error) = match rpc_request(client, &branch.url, rpc).send().await {
+        Ok(response) => {
+            let status = response.status();
+            match response.bytes().await {
+                Ok(_) if status.is_client_error() || status.is_server_error() => {
+                    (Some(status.as_u16()), Some(RequestError::Status))
+                }
+                Ok(_) => (Some(status.as_u16()), None),
+                Err(_) => (None, Some(RequestError::Read)),
+            }
+        }
+        Err(e) if e.is_timeout() => (None, Some(RequestError::Timeout)),
+        Err(e) if e.is_connect() => (None, Some(RequestError::Connect)),
+        Err(_) => (None, Some(RequestError::Write)),
+    };
+    metrics::observe_latency(&branch.name, start.elapsed());
+    if let Some(error) = error {
+        metrics::count_error(&branch.name, error.name());
+    }
+    Sample {
+        index,
+        latency: start.elapsed().as_micros() as f32,
+        status,
+        error,
+    }
+}
+
+/// Requests of a single branch by the index of the url
+struct Samples {
+    duration: f32,
+    by_url: Vec<Vec<Sample>>,
+}
+
+/// Summary of the samples in the wrk format, the latencies are in microseconds
+pub(crate) fn summarize(duration: f32, samples: &[&Sample]) -> WrkResult {
+    let latencies = samples
+        .iter()
+        .filter(|s| s.status.is_some())
+        .map(|s| s.latency)
+        .collect::<Vec<_>>();
+
+    let mut errors = WrkErrors::default();
+    let mut statuses: BTreeMap<u16, f32> = BTreeMap::new();
+    for sample in samples {
+        if let Some(error) = sample.error {
+            errors.count(error);
+        }
+        if let Some(status) = sample.status {
+            *statuses.entry(status).or_default() += 1.0;
+        }
+    }
+
+    WrkResult::from_latencies(duration, &latencies).with_errors(
+        errors,
+        statuses
+            .into_iter()
+            .map(|(status, count)| StatusCount { status, count })
+            .collect(),
+    )
+}
+
+/// Runs the weighted mix of the urls on every connection of the branch for the duration
+async fn run_mix(
+    client: &reqwest::Client,
+    branch: &Branch,
+    urls: &[RpcRequest],
+    connections: usize,
+    duration: Duration,
+) -> Result<Samples, failure::Error> {
+    let weights = WeightedIndex::new(urls.iter().map(|rpc| rpc.weight))?;
+    let start = Instant::now();
+
+    let connections = (0..connections).map(|connection| {
+        let weights = &weights;
+        async move {
+            // every connection draws the same sequence on every branch
+            let mut rng = StdRng::seed_from_u64(connection as u64);
+            let mut samples = Vec::new();
+
+            while start.elapsed() < duration {
+                let index = weights.sample(&mut rng);
+                samples.push(sample(client, branch, &urls[index], index).await);
+            }
+            samples
+        }
+    });
+    let finished = join_all(connections).await;
+
+    let mut by_url = vec![Vec::new(); urls.len()];
+    for sample in finished.into_iter().flatten() {
+        by_url[sample.index].push(sample);
+    }
+    Ok(Samples {
+        duration: start.elapsed().as_micros() as f32,
+        by_url,
+    })
+}
+
+/// Runs the weighted mix on the branch for the duration and summarizes all its requests
+pub(crate) async fn measure_mix(
+    client: &reqwest::Client,
+    branch: &Branch,
+    urls: &[RpcRequest],
+    connections: usize,
+    duration: Duration,
+) -> Result<WrkResult, failure::Error> {
+    let samples = run_mix(client, branch, urls, connections, duration).await?;
+    let all = samples.by_url.iter().flatten().collect_vec();
+    Ok(summarize(samples.duration, &all))
+}
+
+/// Performance test issuing a weighted random mix of the urls concurrently, the aggregate
+/// results are compared to the reference branch with the thresholds of the performance-test
+pub async fn test_mixed_workload(env: RpcPerformanceTestEnv) -> Result<(), failure::Error> {
+    let RpcPerformanceTestEnv {
+        targets,
+        url_file,
+        wrk_test_duration,
+        gates,
+        connections,
+        warm_up,
+        markdown,
+        ..
+    } = env;
+
+    let client = http_client()?;
+    let branches = targets
+        .iter()
+        .enumerate()
+        .map(|(sort_key, target)| target.branch(sort_key))
+        .collect_vec();
+    let reference = branches[reference_index(&targets)].clone();
+    let urls = get_requests(&url_file)?;
+    let total_weight: u32 = urls.iter().map(|rpc| rpc.weight).sum();
+
+    let mut outputs = HashMap::new();
+    interruptible(async {
+        for branch in &branches {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            println!(
+                "Running the mixed workload of {} urls for {} on {} connections",
+                urls.len(),
+                branch.name,
+                connections
+            );
+            if warm_up > 0 {
+                println!("Warming up {} for {}s", branch.name, warm_up);
+                run_mix(
+                    &client,
+                    branch,
+                    &urls,
+                    connections,
+                    Duration::from_secs(warm_up),
+                )
+                .await?;
+            }
+            let samples = run_mix(
+                &client,
+                branch,
+                &urls,
+                connections,
+                Duration::from_secs(wrk_test_duration),
+            )
+            .await?;
+
+            println!(
+                "{:<80} {:>7} {:>10} {:>12} {:>12} {:>8}",
+                "url", "weight", "req/s", "mean ms", "max ms", "errors"
+            );
+            for (rpc, url_samples) in urls.iter().zip(&samples.by_url) {
+                let result = summarize(samples.duration, &url_samples.iter().collect_vec());
+                println!(
+                    "{:<80} {:>6.1}% {:>10.2} {:>12.3} {:>12.3} {:>7.2}%",
+                    rpc.to_string(),
+                    f64::from(rpc.weight) * 100.0 / f64::from(total_weight),
+                    calc_throughput(result.requests(), result.duration()),
+                    us_to_ms(result.latency_mean()),
+                    calc_max_latency(result.latency_max()),
+                    result.error_rate() * 100.0
+                );
+            }
+            println!();
+
+            let all = samples.by_url.iter().flatten().collect_vec();
+            outputs.insert(branch.clone(), summarize(samples.duration, &all));
+        }
+        Ok(())
+    })
+    .await?;
+
+    println!("Aggregate results of the mixed workload:");
+    let regressions = calculate_and_display_statistics(&outputs, &reference, &gates);
+
+    let mut summary = Summary::default();
+    summary.add("mixed workload", &outputs, &reference, &regressions);
+    html_report::record_rpc("mixed workload", &outputs);
+    influx::emit_wrk_results("performance", "mixed workload", &outputs);
+    summary.display();
+    if let Some(markdown) = markdown {
+        summary.write_markdown(&markdown)?;
+    }
+    check_regressions(&regressions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(status: Option<u16>, error: Option<RequestError>) -> Sample {
+        Sample {
+            index: 0,
+            latency: 1000.0,
+            status,
+            error,
+        }
+    }
+
+    #[test]
+    fn summarize_counts_every_error() {
+        let samples = [
+            sample(Some(200), None),
+            sample(Some(200), None),
+            sample(Some(500), Some(RequestError::Status)),
+            sample(None, Some(RequestError::Connect)),
+            sample(None, Some(RequestError::Read)),
+            sample(None, Some(RequestError::Write)),
+            sample(None, Some(RequestError::Timeout)),
+        ];
+        let result = summarize(1_000_000.0, &samples.iter().collect_vec());
+
+        // the responses with an error status are requests too
+        assert_eq!(*result.requests(), 3.0);
+        assert_eq!(
+            *result.errors(),
+            WrkErrors {
+                connect: 1.0,
+                read: 1.0,
+                write: 1.0,
+                status: 1.0,
+                timeout: 1.0,
+            }
+        );
+        assert_eq!(result.statuses_display(), "200: 2, 500: 1");
+        assert!((result.error_rate() - 5.0 / 7.0).abs() < f32::EPSILON);
+    }
+}
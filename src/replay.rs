@@ -0,0 +1,461 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, FixedOffset};
+use failure::{bail, format_err};
+use serde_json::Value;
+use tokio::sync::Semaphore;
+
+use crate::configuration::ReplayEnv;
+use crate::metrics;
+use crate::types::{RequestError, Target};
+use crate::utils::{http_client, interruptible};
+
+/// Pacing of the replayed requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Original pacing multiplied by the factor
+    Scaled(f64),
+    /// Every request is sent as soon as a connection is free
+    Max,
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        ReplaySpeed::Scaled(1.0)
+    }
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(ReplaySpeed::Max);
+        }
+        match s.parse::<f64>() {
+            Ok(factor) if factor > 0.0 => Ok(ReplaySpeed::Scaled(factor)),
+            _ => bail!("expected a positive factor or max"),
+        }
+    }
+}
+
+/// A request of the log, the offset is relative to the first request
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedRequest {
+    pub offset: Duration,
+    pub method: reqwest::Method,
+    pub path: String,
+}
+
+/// Reads a request log, either JSONL with `time`, `method` and `path` fields, where the time
+/// is RFC 3339 or seconds since the epoch, or a common/combined access log
+pub fn read_log(file: &str) -> Result<Vec<LoggedRequest>, failure::Error> {
+    let mut requests = Vec::new();
+
+    for (n, line) in fs::read_to_string(file)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = if line.starts_with('{') {
+            parse_json_line(line)
+        } else {
+            parse_access_log_line(line)
+        };
+        requests.push(parsed.map_err(|e| format_err!("{}:{}: {}", file, n + 1, e))?);
+    }
+
+    // the offsets are still the timestamps in microseconds
+    requests.sort_by_key(|(time, _, _)| *time);
+    let first = requests.first().map(|(time, _, _)| *time).unwrap_or(0);
+    Ok(requests
+        .into_iter()
+        .map(|(time, method, path)| LoggedRequest {
+            offset: Duration::from_micros((time - first) as u64),
+            method,
+            path: path.trim_start_matches('/').to_string(),
+        })
+        .collect())
+}
+
+type ParsedLine = (i64, reqwest::Method, String);
+
+fn parse_json_line(line: &str) -> Result<ParsedLine, failure::Error> {
+    let json: Value = serde_json::from_str(line)?;
+    let time = match json.get("time").or_else(|| json.get("timestamp")) {
+        Some(Value::String(time)) => DateTime::parse_from_rfc3339(time)?.timestamp_micros(),
+        Some(Value::Number(secs)) => (secs.as_f64().unwrap_or(0.0) * 1_000_000.0) as i64,
+        _ => bail!("missing time"),
+    };
+    let method = json["method"].as_str().unwrap_or("GET").parse()?;
+    let path = json["path"]
+        .as_str()
+        .ok_or_else(|| format_err!("missing path"))?;
+    Ok((time, method, path.to_string()))
+}
+
+/// `host - - [10/Oct/2020:13:55:36 +0000] "GET /path HTTP/1.1" 200 ...`
+fn parse_access_log_line(line: &str) -> Result<ParsedLine, failure::Error> {
+    let time = line
+        .split('[')
+        .nth(1)
+        .and_then(|rest| rest.split(']').next())
+        .ok_or_else(|| format_err!("missing [time]"))?;
+    let time = DateTime::<FixedOffset>::parse_from_str(time, "%d/%b/%Y:%H:%M:%S %z")?;
+
+    let request = line
+        .split('"')
+        .nth(1)
+        .ok_or_else(|| format_err!("missing \"request\""))?;
+    let mut parts = request.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => {
+            Ok((time.timestamp_micros(), method.parse()?, path.to_string()))
+        }
+        _ => bail!("invalid request {:?}", request),
+    }
+}
+
+/// Endpoint family of the path, the block ids, levels, hashes and addresses are replaced by
+/// placeholders, e.g. `chains/main/blocks/{block}/context/contracts/{address}`
+pub fn endpoint_family(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or("");
+    let mut previous = "";
+    path.split('/')
+        .map(|segment| {
+            let family = if previous == "blocks" {
+                "{block}"
+            } else if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+                "{n}"
+            } else if segment.len() == 36
+                && ["tz1", "tz2", "tz3", "tz4", "KT1"]
+                    .iter()
+                    .any(|prefix| segment.starts_with(prefix))
+            {
+                "{address}"
+            } else if segment.len() >= 50 && segment.chars().all(|c| c.is_ascii_alphanumeric()) {
+                "{hash}"
+            } else {
+                segment
+            };
+            previous = segment;
+            family
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Latencies in microseconds and the number of failed requests
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    pub latencies: Vec<f64>,
+    pub errors: usize,
+}
+
+impl LatencyStats {
+    pub fn requests(&self) -> usize {
+        self.latencies.len() + self.errors
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.errors as f64 / self.requests().max(1) as f64
+    }
+
+    /// Latency of the percentile in milliseconds, the latencies have to be sorted
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1] * 0.001
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>8} {:>7.2}% {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+            self.requests(),
+            self.error_rate() * 100.0,
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(100.0)
+        )
+    }
+}
+
+/// Replays the requests against the target, returns the latency of every successful request
+/// and whether the request failed, in the order of the log
+async fn replay_target(
+    client: &reqwest::Client,
+    target: &Target,
+    requests: &[LoggedRequest],
+    speed: ReplaySpeed,
+    connections: usize,
+) -> Result<Vec<Result<f64, ()>>, failure::Error> {
+    let semaphore = Arc::new(Semaphore::new(connections));
+    let start = tokio::time::Instant::now();
+    let mut handles = Vec::new();
+
+    for request in requests {
+        if let ReplaySpeed::Scaled(factor) = speed {
+            tokio::time::sleep_until(start + request.offset.div_f64(factor)).await;
+        }
+        let permit = semaphore.clone().acquire_owned().await?;
+        let client = client.clone();
+        let method = request.method.clone();
+        let url = format!("{}{}", target.url, request.path);
+        let name = target.name.clone();
+
+        handles.push(tokio::spawn(async move {
+            let req_start = Instant::now();
+            let result = match client.request(method, &url).send().await {
+                Ok(response) if response.status().is_success() => {
+                    response.bytes().await.map_err(|_| RequestError::Read)
+                }
+                Ok(_) => Err(RequestError::Status),
+                Err(e) if e.is_timeout() => Err(RequestError::Timeout),
+                Err(e) if e.is_connect() => Err(RequestError::Connect),
+                Err(_) => Err(RequestError::Write),
+            };
+            drop(permit);
+            metrics::observe_latency(&name, req_start.elapsed());
+            if let Err(error) = result {
+                metrics::count_error(&name, error.name());
+            }
+            result
+                .map(|_| req_start.elapsed().as_micros() as f64)
+                .map_err(|_| ())
+        }));
+    }
+
+    let mut results = Vec::new();
+    for handle in handles {
+        results.push(handle.await?);
+    }
+    Ok(results)
+}
+
+/// Replays the request log against every target and reports the latency distribution and
+/// the error rate per target and per endpoint family
+pub async fn replay(env: ReplayEnv) -> Result<(), failure::Error> {
+    let ReplayEnv {
+        targets,
+        log,
+        speed,
+        connections,
+    } = env;
+
+    let client = http_client()?;
+    let requests = read_log(&log)?;
+    let families = requests
+        .iter()
+        .map(|r| format!("{} {}", r.method, endpoint_family(&r.path)))
+        .collect::<Vec<_>>();
+    println!(
+        "Replaying {} requests spanning {:.1}s at {:?}",
+        requests.len(),
+        requests
+            .last()
+            .map(|r| r.offset.as_secs_f64())
+            .unwrap_or(0.0),
+        speed
+    );
+
+    let mut reports = Vec::new();
+    let result = interruptible(async {
+        for target in &targets {
+            println!("[{}] Replaying", target.name);
+            let replay_start = Instant::now();
+            let results = replay_target(&client, target, &requests, speed, connections).await?;
+            println!(
+                "[{}] Replayed in {:.1}s",
+                target.name,
+                replay_start.elapsed().as_secs_f64()
+            );
+
+            let mut total = LatencyStats::default();
+            let mut by_family: BTreeMap<&str, LatencyStats> = BTreeMap::new();
+            for (family, result) in families.iter().zip(results) {
+                let stats = by_family.entry(family).or_default();
+                match result {
+                    Ok(latency) => {
+                        stats.latencies.push(latency);
+                        total.latencies.push(latency);
+                    }
+                    Err(()) => {
+                        stats.errors += 1;
+                        total.errors += 1;
+                    }
+                }
+            }
+            for stats in by_family.values_mut().chain(Some(&mut total)) {
+                stats.latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            reports.push((target.name.clone(), total, by_family));
+        }
+        Ok(())
+    })
+    .await;
+
+    let header = format!(
+        "{:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "requests", "errors", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    for (name, total, by_family) in &reports {
+        println!("------------------------------------------------------");
+        println!("[{}] {:<70} {}", name, "endpoint", header);
+        for (family, stats) in by_family {
+            println!("[{}] {:<70} {}", name, family, stats);
+        }
+        println!("[{}] {:<70} {}", name, "total", total);
+    }
+    if result.is_err() {
+        println!("Finished targets before the failure: {}", reports.len());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: &str = "chains/main/blocks/1234/context/contracts/\
+        tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx/balance";
+
+    #[test]
+    fn combined_log_line() {
+        let line = r#"10.0.0.1 - - [10/Oct/2020:13:55:36 +0200] "GET /chains/main/blocks/head?x=1 HTTP/1.1" 200 2326 "-" "curl/7.68.0""#;
+        let (time, method, path) = parse_access_log_line(line).unwrap();
+        assert_eq!(
+            time,
+            DateTime::parse_from_rfc3339("2020-10-10T11:55:36Z")
+                .unwrap()
+                .timestamp_micros()
+        );
+        assert_eq!(method, reqwest::Method::GET);
+        assert_eq!(path, "/chains/main/blocks/head?x=1");
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let error = |line| parse_access_log_line(line).unwrap_err().to_string();
+        assert_eq!(error("10.0.0.1 - - GET /"), "missing [time]");
+        assert_eq!(
+            error(r#"10.0.0.1 - - [10/Oct/2020:13:55:36 +0000] GET /"#),
+            "missing \"request\""
+        );
+        assert_eq!(
+            error(r#"10.0.0.1 - - [10/Oct/2020:13:55:36 +0000] "GET" 200"#),
+            "invalid request \"GET\""
+        );
+        assert!(parse_access_log_line(r#"10.0.0.1 - - [yesterday] "GET / HTTP/1.1""#).is_err());
+
+        let error = |line| parse_json_line(line).unwrap_err().to_string();
+        assert_eq!(error(r#"{"path": "/"}"#), "missing time");
+        assert_eq!(error(r#"{"time": 1}"#), "missing path");
+        assert!(parse_json_line(r#"{"time": 1, "path": "/""#).is_err());
+    }
+
+    #[test]
+    fn json_line_defaults_to_get() {
+        assert_eq!(
+            parse_json_line(r#"{"time": 1.5, "path": "/version"}"#).unwrap(),
+            (1_500_000, reqwest::Method::GET, "/version".to_string())
+        );
+        assert_eq!(
+            parse_json_line(
+                r#"{"timestamp": "1970-01-01T00:00:02Z", "method": "POST", "path": "/x"}"#
+            )
+            .unwrap(),
+            (2_000_000, reqwest::Method::POST, "/x".to_string())
+        );
+    }
+
+    #[test]
+    fn log_is_sorted_by_time() {
+        let file = std::env::temp_dir().join(format!(
+            "tezos-node-bootstrap-replay-{}.log",
+            std::process::id()
+        ));
+        std::fs::write(
+            &file,
+            "# comment\n\
+             {\"time\": 12.5, \"path\": \"/b\"}\n\
+             \n\
+             {\"time\": 10, \"method\": \"post\", \"path\": \"/a\"}\n",
+        )
+        .unwrap();
+        let requests = read_log(&file.to_string_lossy());
+        std::fs::remove_file(&file).unwrap();
+
+        let requests = requests.unwrap();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|r| (r.offset, r.path.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Duration::from_secs(0), "a"),
+                (Duration::from_millis(2500), "b")
+            ]
+        );
+    }
+
+    #[test]
+    fn families_of_the_blocks() {
+        assert_eq!(
+            endpoint_family(LEVEL),
+            "chains/main/blocks/{block}/context/contracts/{address}/balance"
+        );
+        assert_eq!(
+            endpoint_family("chains/main/blocks/head~2/helpers/baking_rights?level=12"),
+            "chains/main/blocks/{block}/helpers/baking_rights"
+        );
+        assert_eq!(
+            endpoint_family("chains/main/blocks/head/operations/3/12"),
+            "chains/main/blocks/{block}/operations/{n}/{n}"
+        );
+        assert_eq!(
+            endpoint_family(
+                "chains/main/blocks/BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2/header"
+            ),
+            "chains/main/blocks/{block}/header"
+        );
+        assert_eq!(
+            endpoint_family(
+                "chains/main/mempool/BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2"
+            ),
+            "chains/main/mempool/{hash}"
+        );
+    }
+
+    #[test]
+    fn percentiles_of_the_latencies() {
+        let stats = LatencyStats {
+            latencies: (1..=10).map(|ms| ms as f64 * 1000.0).collect(),
+            errors: 2,
+        };
+        assert_eq!(stats.requests(), 12);
+        assert!((stats.error_rate() - 2.0 / 12.0).abs() < 1e-9);
+        assert_eq!(stats.percentile(0.0), 1.0);
+        assert_eq!(stats.percentile(50.0), 5.0);
+        assert_eq!(stats.percentile(90.0), 9.0);
+        assert_eq!(stats.percentile(99.0), 10.0);
+        assert_eq!(stats.percentile(100.0), 10.0);
+
+        let empty = LatencyStats::default();
+        assert_eq!(empty.percentile(50.0), 0.0);
+        assert_eq!(empty.error_rate(), 0.0);
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+use std::path::PathBuf;
+
+use assert_json_diff::assert_json_eq_no_panic;
+use failure::bail;
+use serde_json::Value;
+
+use crate::types::{Branch, BranchType, RpcRequest};
+use crate::utils::{http_client, rpc_request, runtime};
+use crate::wrk::Regression;
+
+/// Most responses sampled by a single wrk thread
+pub const MAX_SAMPLES_PER_THREAD: usize = 10;
+
+/// Responses sampled by the wrk script of a single run, every wrk thread writes the bodies
+/// to its own file `<prefix>.<thread>` as json strings, one per line
+#[derive(Debug, Clone)]
+pub struct Sampling {
+    pub rate: f32,
+    pub prefix: PathBuf,
+}
+
+impl Sampling {
+    pub fn new(rate: f32, branch: &Branch) -> Self {
+        let prefix = std::env::temp_dir().join(format!(
+            "tezos-node-bootstrap-samples-{}-{}",
+            std::process::id(),
+            branch.sort_key
+        ));
+        Self { rate, prefix }
+    }
+
+    /// Args of the wrk script: the file prefix and the sampled fraction
+    pub fn script_args(&self) -> Vec<String> {
+        vec![
+            self.prefix.to_string_lossy().to_string(),
+            self.rate.to_string(),
+        ]
+    }
+
+    /// Reads and removes the sampled bodies
+    pub fn take_samples(&self) -> Result<Vec<Value>, failure::Error> {
+        let dir = self.prefix.parent().unwrap_or_else(|| "/".as_ref());
+        let name = format!(
+            "{}.",
+            self.prefix
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        let mut samples = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path
+                .file_name()
+                .is_some_and(|f| f.to_string_lossy().starts_with(&name))
+            {
+                continue;
+            }
+            for line in fs::read_to_string(&path)?.lines() {
+                let body: String = serde_json::from_str(line)?;
+                // non-json responses are compared as strings
+                samples.push(serde_json::from_str(&body).unwrap_or(Value::String(body)));
+            }
+            fs::remove_file(&path)?;
+        }
+        Ok(samples)
+    }
+}
+
+/// Branch the sampled responses are compared to, the first ocaml node or the reference
+pub fn sampling_reference<'a>(branches: &'a [Branch], reference: &'a Branch) -> &'a Branch {
+    branches
+        .iter()
+        .find(|b| b.branch_type == BranchType::Ocaml)
+        .unwrap_or(reference)
+}
+
+/// The rpc with the head pinned to the current head block of the sampling reference, so the
+/// sampled responses of every branch are comparable to its response also when the chain moves
+/// during the test, e.g. `chains/main/blocks/head~2` becomes `chains/main/blocks/<hash>~2`
+pub fn pin_head(rpc: &RpcRequest, reference: &Branch) -> Result<RpcRequest, failure::Error> {
+    const HEAD: &str = "blocks/head";
+    let end = match rpc.path.find(HEAD) {
+        Some(start) => start + HEAD.len(),
+        None => return Ok(rpc.clone()),
+    };
+    if !matches!(rpc.path[end..].chars().next(), None | Some('/' | '~' | '?')) {
+        return Ok(rpc.clone());
+    }
+
+    // the load runners run on the blocking thread pool of the runtime
+    let hash_url = format!("{}{}/hash", reference.url, &rpc.path[..end]);
+    let hash = runtime()?.block_on(async {
+        let response = http_client()?.get(&hash_url).send().await?;
+        if !response.status().is_success() {
+            bail!(
+                "[{}] Head hash request {} failed with {}",
+                reference.name,
+                hash_url,
+                response.status()
+            );
+        }
+        Ok::<_, failure::Error>(response.json::<String>().await?)
+    })?;
+    println!("[{}] Pinned the head to {}", reference.name, hash);
+
+    let mut pinned = rpc.clone();
+    pinned.path = format!(
+        "{}{}{}",
+        &rpc.path[..end - "head".len()],
+        hash,
+        &rpc.path[end..]
+    );
+    Ok(pinned)
+}
+
+/// Compares the samples of every other branch to the response of the sampling reference and
+/// returns a fatal regression for every branch with a diverging sampled body, the head of the
+/// rpc has to be pinned by `pin_head`
+pub fn check_samples(
+    rpc: &RpcRequest,
+    reference: &Branch,
+    samples: &[(Branch, Vec<Value>)],
+) -> Result<Vec<Regression>, failure::Error> {
+    // the load runners run on the blocking thread pool of the runtime
+    let expected = runtime()?.block_on(async {
+        let response = rpc_request(&http_client()?, &reference.url, rpc)
+            .send()
+            .await?;
+        // an error of the reference must not turn the responses of the others into divergences
+        if !response.status().is_success() {
+            bail!(
+                "[{}] Reference response of {} failed with {}",
+                reference.name,
+                rpc,
+                response.status()
+            );
+        }
+        let text = response.text().await?;
+        Ok::<_, failure::Error>(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    })?;
+
+    let mut regressions = Vec::new();
+    for (branch, bodies) in samples.iter().filter(|(b, _)| b != reference) {
+        let mismatches = bodies
+            .iter()
+            .filter_map(|body| assert_json_eq_no_panic(body, &expected).err())
+            .collect::<Vec<_>>();
+        println!(
+            "[{}] Sampled responses: {}, diverging from {}: {}",
+            branch.name,
+            bodies.len(),
+            reference.name,
+            mismatches.len()
+        );
+        if let Some(diff) = mismatches.first() {
+            println!("[{}] First diverging response:\n{}", branch.name, diff);
+            regressions.push(Regression {
+                branch: branch.clone(),
+                message: format!(
+                    "[Correctness] [{}] Sampled responses for {} diverge from {}!",
+                    branch.name, rpc, reference.name
+                ),
+                fatal: true,
+            });
+        }
+    }
+    Ok(regressions)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+
+    /// Node answering a single http request with the status and the json body, the requested
+    /// path is received from the channel
+    pub(crate) fn node_stub(
+        status: &'static str,
+        body: &'static str,
+    ) -> (Branch, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            sender.send(path.to_string()).unwrap();
+        });
+        let branch = Branch::new(0, "ocaml", url.parse().unwrap(), BranchType::Ocaml);
+        (branch, receiver)
+    }
+
+    fn pinned(path: &str, branch: &Branch) -> Result<String, failure::Error> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        pin_head(&RpcRequest::get(path, 1), branch).map(|rpc| rpc.path)
+    }
+
+    #[test]
+    fn pin_head_keeps_the_other_blocks() {
+        // nothing is requested, the stub would answer a single request only
+        let (branch, requests) = node_stub("200 OK", "\"BLockHash\"");
+        for path in &[
+            "chains/main/blocks/1000/header",
+            "chains/main/blocks/headers",
+            "chains/main/blocks",
+            "network/version",
+        ] {
+            assert_eq!(pinned(path, &branch).unwrap(), *path);
+        }
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn pin_head_replaces_the_head_by_its_hash() {
+        for (path, expected) in &[
+            ("chains/main/blocks/head", "chains/main/blocks/BLockHash"),
+            (
+                "chains/main/blocks/head~2/header",
+                "chains/main/blocks/BLockHash~2/header",
+            ),
+            (
+                "chains/main/blocks/head/helpers/baking_rights?all=true",
+                "chains/main/blocks/BLockHash/helpers/baking_rights?all=true",
+            ),
+        ] {
+            let (branch, requests) = node_stub("200 OK", "\"BLockHash\"");
+            assert_eq!(pinned(path, &branch).unwrap(), *expected);
+            assert_eq!(requests.recv().unwrap(), "/chains/main/blocks/head/hash");
+        }
+    }
+
+    #[test]
+    fn pin_head_fails_on_a_failing_reference() {
+        let (branch, _) = node_stub("500 Internal Server Error", "\"\"");
+        let error = pinned("chains/main/blocks/head/header", &branch).unwrap_err();
+        assert!(error.to_string().contains("failed with 500"), "{}", error);
+    }
+}
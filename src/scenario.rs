@@ -0,0 +1,285 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::constants::ConstantsResolver;
+
+/// Scenario used by the sequential-test when no --scenario file is given
+pub const DEFAULT_SCENARIO: &str = r#"
+[[request]]
+path = "chains/main/blocks/{rights_level}/helpers/baking_rights?cycle={cycle}&all=true"
+for-each = "cycle"
+"#;
+
+/// Values a request template is expanded over
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForEach {
+    /// Every tested cycle
+    Cycle,
+    /// Levels of the tested cycles, every `step` levels
+    Level,
+    /// Active delegates of the head block, at most `limit`, combined with every tested cycle
+    /// when the path contains a cycle or level placeholder
+    Delegate,
+}
+
+/// A parameterized rpc, the `path` can contain these placeholders:
+///
+/// * `{cycle}` - the cycle
+/// * `{level}` - the level, or the first level of the cycle
+/// * `{rights_level}` - the first level at which the rights of the cycle can be requested
+/// * `{delegate}` - the delegate
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct RequestTemplate {
+    pub path: String,
+    pub for_each: ForEach,
+    pub step: Option<i32>,
+    pub limit: Option<usize>,
+}
+
+/// Sequence of request templates, read from a TOML file with `[[request]]` tables
+#[derive(Deserialize, Debug, Clone)]
+pub struct Scenario {
+    #[serde(rename = "request")]
+    pub requests: Vec<RequestTemplate>,
+}
+
+impl FromStr for Scenario {
+    type Err = failure::Error;
+
+    fn from_str(scenario: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(scenario)?)
+    }
+}
+
+impl Scenario {
+    pub fn from_file(file: &str) -> Result<Self, failure::Error> {
+        fs::read_to_string(file)?.parse()
+    }
+
+    pub fn uses_delegates(&self) -> bool {
+        self.requests
+            .iter()
+            .any(|r| r.for_each == ForEach::Delegate)
+    }
+
+    /// Rpc paths of the scenario for the cycles `1..cycles`
+    pub fn expand(
+        &self,
+        constants: &ConstantsResolver,
+        cycles: i32,
+        delegates: &[String],
+    ) -> Vec<String> {
+        let mut paths = Vec::new();
+
+        for template in &self.requests {
+            match template.for_each {
+                ForEach::Cycle => {
+                    for cycle in 1..cycles {
+                        paths.push(fill(
+                            &template.path,
+                            constants,
+                            cycle,
+                            constants.cycle_first_level(cycle),
+                            None,
+                        ));
+                    }
+                }
+                ForEach::Level => {
+                    let step = template
+                        .step
+                        .unwrap_or_else(|| {
+                            constants
+                                .constants_at(constants.cycle_first_level(1))
+                                .blocks_per_cycle
+                        })
+                        .max(1);
+                    let last_level = constants.cycle_first_level(cycles) - 1;
+                    let mut level = constants.cycle_first_level(1);
+                    while level <= last_level {
+                        paths.push(fill(
+                            &template.path,
+                            constants,
+                            constants.level_cycle(level),
+                            level,
+                            None,
+                        ));
+                        level += step;
+                    }
+                }
+                ForEach::Delegate => {
+                    let limit = template.limit.unwrap_or(delegates.len());
+                    let per_cycle = ["{cycle}", "{level}", "{rights_level}"]
+                        .iter()
+                        .any(|placeholder| template.path.contains(placeholder));
+                    let cycles = if per_cycle { 1..cycles } else { 1..2 };
+
+                    for delegate in delegates.iter().take(limit) {
+                        for cycle in cycles.clone() {
+                            paths.push(fill(
+                                &template.path,
+                                constants,
+                                cycle,
+                                constants.cycle_first_level(cycle),
+                                Some(delegate),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        paths
+    }
+}
+
+fn fill(
+    template: &str,
+    constants: &ConstantsResolver,
+    cycle: i32,
+    level: i32,
+    delegate: Option<&str>,
+) -> String {
+    template
+        .replace("{cycle}", &cycle.to_string())
+        .replace("{level}", &level.to_string())
+        .replace(
+            "{rights_level}",
+            &constants.rights_first_level(cycle).to_string(),
+        )
+        .replace("{delegate}", delegate.unwrap_or(""))
+}
+
+/// Active delegates of the head block
+pub async fn fetch_delegates(
+    client: &reqwest::Client,
+    node: &Url,
+) -> Result<Vec<String>, failure::Error> {
+    let delegates: Value = client
+        .get(format!(
+            "{}chains/main/blocks/head/context/delegates?active=true",
+            node
+        ))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    Ok(delegates
+        .as_array()
+        .map(|delegates| {
+            delegates
+                .iter()
+                .filter_map(|d| d.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{Constants, ProtocolPeriod};
+
+    /// Cycles of 8 blocks from level 1, the rights are known 2 cycles ahead
+    fn constants() -> ConstantsResolver {
+        ConstantsResolver::new(vec![ProtocolPeriod {
+            protocol: "P1".to_string(),
+            first_level: 1,
+            first_cycle: 0,
+            constants: Constants {
+                blocks_per_cycle: 8,
+                preserved_cycles: 2,
+            },
+        }])
+    }
+
+    fn scenario(path: &str, for_each: &str, options: &str) -> Scenario {
+        format!(
+            "[[request]]\npath = {:?}\nfor-each = {:?}\n{}",
+            path, for_each, options
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn default_scenario_for_each_cycle() {
+        let scenario: Scenario = DEFAULT_SCENARIO.parse().unwrap();
+        assert!(!scenario.uses_delegates());
+        assert_eq!(
+            scenario.expand(&constants(), 4, &[]),
+            vec![
+                "chains/main/blocks/1/helpers/baking_rights?cycle=1&all=true",
+                "chains/main/blocks/1/helpers/baking_rights?cycle=2&all=true",
+                "chains/main/blocks/9/helpers/baking_rights?cycle=3&all=true",
+            ]
+        );
+    }
+
+    #[test]
+    fn for_each_level() {
+        let path = "chains/main/blocks/{level}/helpers/endorsing_rights?cycle={cycle}";
+        assert_eq!(
+            scenario(path, "level", "step = 5").expand(&constants(), 3, &[]),
+            vec![
+                "chains/main/blocks/9/helpers/endorsing_rights?cycle=1",
+                "chains/main/blocks/14/helpers/endorsing_rights?cycle=1",
+                "chains/main/blocks/19/helpers/endorsing_rights?cycle=2",
+                "chains/main/blocks/24/helpers/endorsing_rights?cycle=2",
+            ]
+        );
+        // a cycle step by default
+        assert_eq!(
+            scenario(path, "level", "").expand(&constants(), 3, &[]),
+            vec![
+                "chains/main/blocks/9/helpers/endorsing_rights?cycle=1",
+                "chains/main/blocks/17/helpers/endorsing_rights?cycle=2",
+            ]
+        );
+    }
+
+    #[test]
+    fn for_each_delegate() {
+        let delegates = vec!["tz1a".to_string(), "tz1b".to_string(), "tz1c".to_string()];
+
+        let scenario_per_cycle = scenario(
+            "chains/main/blocks/head/helpers/baking_rights?cycle={cycle}&delegate={delegate}",
+            "delegate",
+            "limit = 2",
+        );
+        assert!(scenario_per_cycle.uses_delegates());
+        assert_eq!(
+            scenario_per_cycle.expand(&constants(), 3, &delegates),
+            vec![
+                "chains/main/blocks/head/helpers/baking_rights?cycle=1&delegate=tz1a",
+                "chains/main/blocks/head/helpers/baking_rights?cycle=2&delegate=tz1a",
+                "chains/main/blocks/head/helpers/baking_rights?cycle=1&delegate=tz1b",
+                "chains/main/blocks/head/helpers/baking_rights?cycle=2&delegate=tz1b",
+            ]
+        );
+
+        // without a cycle placeholder every delegate is requested once
+        assert_eq!(
+            scenario(
+                "chains/main/blocks/head/context/delegates/{delegate}",
+                "delegate",
+                ""
+            )
+            .expand(&constants(), 3, &delegates),
+            vec![
+                "chains/main/blocks/head/context/delegates/tz1a",
+                "chains/main/blocks/head/context/delegates/tz1b",
+                "chains/main/blocks/head/context/delegates/tz1c",
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::str::FromStr;
+
+use failure::bail;
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::types::Branch;
+use crate::utils::runtime;
+
+/// Order the branches are measured in for every rpc
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BranchOrder {
+    /// The order of the targets
+    #[default]
+    Fixed,
+    /// A seeded random order for every rpc
+    Random,
+    /// The order of the targets rotated by one for every rpc
+    RoundRobin,
+}
+
+impl FromStr for BranchOrder {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(BranchOrder::Fixed),
+            "random" => Ok(BranchOrder::Random),
+            "round-robin" => Ok(BranchOrder::RoundRobin),
+            _ => bail!("expected one of fixed, random, round-robin"),
+        }
+    }
+}
+
+/// Orders the branches of the consecutive rpcs
+pub struct BranchScheduler {
+    order: BranchOrder,
+    rng: StdRng,
+    rpcs: usize,
+}
+
+impl BranchScheduler {
+    pub fn new(order: BranchOrder, seed: u64) -> Self {
+        Self {
+            order,
+            rng: StdRng::seed_from_u64(seed),
+            rpcs: 0,
+        }
+    }
+
+    /// Branches in the order of the next rpc, the order is printed for the report
+    pub fn next_order(&mut self, branches: &[Branch]) -> Vec<Branch> {
+        let mut ordered = branches.to_vec();
+        match self.order {
+            BranchOrder::Fixed => (),
+            BranchOrder::Random => ordered.shuffle(&mut self.rng),
+            BranchOrder::RoundRobin => ordered.rotate_left(self.rpcs % branches.len().max(1)),
+        }
+        self.rpcs += 1;
+
+        println!(
+            "Branch order: {}",
+            ordered.iter().map(|b| b.name.as_str()).join(", ")
+        );
+        ordered
+    }
+}
+
+/// Measures every branch, one after the other with a pause between them, or all at once
+/// when the nodes do not share hardware
+pub fn measure_branches<T, F>(
+    branches: &[Branch],
+    concurrent: bool,
+    measure: F,
+) -> Result<Vec<(Branch, T)>, failure::Error>
+where
+    T: Send,
+    F: Fn(&Branch) -> Result<T, failure::Error> + Sync,
+{
+    if !concurrent {
+        return branches
+            .iter()
+            .map(|branch| {
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                Ok((branch.clone(), measure(branch)?))
+            })
+            .collect();
+    }
+
+    // the measurements can block on the runtime, e.g. for the sampled responses
+    let runtime = runtime()?;
+    std::thread::scope(|scope| {
+        let handles = branches
+            .iter()
+            .map(|branch| {
+                let (runtime, measure) = (&runtime, &measure);
+                scope.spawn(move || {
+                    let _guard = runtime.enter();
+                    measure(branch).map(|result| (branch.clone(), result))
+                })
+            })
+            .collect_vec();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| bail!("The measurement thread panicked"))
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BranchType;
+
+    fn branches() -> Vec<Branch> {
+        ["a", "b", "c", "d"]
+            .iter()
+            .enumerate()
+            .map(|(sort_key, name)| {
+                let url = format!("http://{}:8732/", name).parse().unwrap();
+                Branch::new(sort_key, name, url, BranchType::Feature)
+            })
+            .collect()
+    }
+
+    fn orders(order: BranchOrder, seed: u64, rpcs: usize) -> Vec<String> {
+        let branches = branches();
+        let mut scheduler = BranchScheduler::new(order, seed);
+        (0..rpcs)
+            .map(|_| {
+                scheduler
+                    .next_order(&branches)
+                    .iter()
+                    .map(|branch| branch.name.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn branch_order_from_str() {
+        assert_eq!("fixed".parse::<BranchOrder>().unwrap(), BranchOrder::Fixed);
+        assert_eq!(
+            "round-robin".parse::<BranchOrder>().unwrap(),
+            BranchOrder::RoundRobin
+        );
+        assert!("shuffled".parse::<BranchOrder>().is_err());
+    }
+
+    #[test]
+    fn fixed_and_round_robin_orders() {
+        assert_eq!(orders(BranchOrder::Fixed, 0, 2), vec!["abcd", "abcd"]);
+        assert_eq!(
+            orders(BranchOrder::RoundRobin, 0, 5),
+            vec!["abcd", "bcda", "cdab", "dabc", "abcd"]
+        );
+    }
+
+    #[test]
+    fn random_order_is_reproduced_by_the_seed() {
+        let random = orders(BranchOrder::Random, 42, 10);
+        assert_eq!(random, orders(BranchOrder::Random, 42, 10));
+        assert_ne!(random, orders(BranchOrder::Random, 43, 10));
+        // every rpc gets its own permutation of the branches
+        assert!(random.iter().unique().count() > 1);
+        for order in &random {
+            assert_eq!(order.chars().sorted().collect::<String>(), "abcd");
+        }
+    }
+}
@@ -3,48 +3,317 @@
 
 use std::time::{Duration, Instant};
 
+use assert_json_diff::assert_json_eq_no_panic;
+use failure::bail;
+use serde_json::Value;
+use url::Url;
+
 use crate::configuration::SequentialTestEnv;
+use crate::constants::ConstantsResolver;
+use crate::influx::{self, FieldValue, Point};
+use crate::replay::endpoint_family;
+use crate::scenario::fetch_delegates;
+use crate::utils::{http_client, interruptible};
 
-pub(crate) fn test_sequential_requests(env: SequentialTestEnv) {
-    let SequentialTestEnv { nodes, cycles } = env;
-    for node in nodes {
-        let start = Instant::now();
-
-        for cycle in 1..cycles {
-            // get the first 3 cycles form the first block
-            let url = if cycle < 4 {
-                format!(
-                    "{}/chains/main/blocks/1/helpers/baking_rights?cycle={}&all=true",
-                    node, cycle
-                )
-            } else {
-                // allways get the rights from the first block possible
-                let block_level = (cycle * 2048) - (3 * 2048) + 1;
-                format!(
-                    "{}/chains/main/blocks/{}/helpers/baking_rights?cycle={}&all=true",
-                    node, block_level, cycle
-                )
-            };
-            let req_start = Instant::now();
-            let response = reqwest::blocking::get(&url).unwrap();
-            let req_elapsed = req_start.elapsed();
+/// Timings of a single request of the scenario on both nodes
+#[derive(Debug, Clone)]
+pub struct AlignedRequest {
+    pub path: String,
+    pub baseline: f64,
+    pub compared: f64,
+    pub same_body: bool,
+}
 
-            if !response.status().is_success() {
-                panic!("Request {} failed!", url)
-            }
+impl AlignedRequest {
+    pub fn delta(&self) -> f64 {
+        self.compared - self.baseline
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.compared / self.baseline
+    }
+}
+
+async fn timed_request(
+    client: &reqwest::Client,
+    node: &Url,
+    path: &str,
+) -> Result<(f64, Value), failure::Error> {
+    let url = format!("{}{}", node, path);
+    let req_start = Instant::now();
+    let response = client.get(&url).send().await?;
+    let req_elapsed = req_start.elapsed();
+
+    if !response.status().is_success() {
+        bail!("Request {} failed!", url)
+    }
+    // non-json responses are compared as strings
+    let text = response.text().await?;
+    let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+
+    Ok((extract_secs(req_elapsed), body))
+}
+
+/// Requests the path from the baseline node and right after it from the compared node
+async fn aligned_request(
+    client: &reqwest::Client,
+    baseline: &Url,
+    compared: &Url,
+    path: String,
+) -> Result<AlignedRequest, failure::Error> {
+    let (baseline_secs, baseline_body) = timed_request(client, baseline, &path).await?;
+    let (compared_secs, compared_body) = timed_request(client, compared, &path).await?;
+
+    Ok(AlignedRequest {
+        path,
+        baseline: baseline_secs,
+        compared: compared_secs,
+        same_body: assert_json_eq_no_panic(&compared_body, &baseline_body).is_ok(),
+    })
+}
+
+/// Runs every request of the scenario on both nodes in turn, so the two timings of a request
+/// are aligned, and fails on differing responses or when the second node takes longer in total
+/// than the first one by more than the duration threshold
+pub async fn test_sequential_requests(env: SequentialTestEnv) -> Result<(), failure::Error> {
+    let SequentialTestEnv {
+        nodes,
+        cycles,
+        scenario,
+        duration_threshold,
+        duration_no_fail,
+    } = env;
+
+    let client = http_client()?;
+    let (baseline, compared) = (&nodes[0], &nodes[1]);
+    // the aligned requests are reported, even when the test is interrupted
+    let mut aligned = Vec::new();
+
+    // both nodes run the same paths, generated from the constants of the first node
+    let constants = ConstantsResolver::resolve(&client, baseline).await?;
+    let delegates = if scenario.uses_delegates() {
+        fetch_delegates(&client, baseline).await?
+    } else {
+        Vec::new()
+    };
+    for period in constants.periods() {
+        println!("[{}] Protocol {}", baseline, period);
+    }
+
+    let result = interruptible(async {
+        for path in scenario.expand(&constants, cycles, &delegates) {
+            let request = aligned_request(&client, baseline, compared, path).await?;
             println!(
-                "[{}] Requested {} -> {}s",
-                node,
-                url,
-                extract_secs(req_elapsed)
+                "Requested {} -> {}s / {}s, delta: {:+.6}s, ratio: {:.3}{}",
+                request.path,
+                request.baseline,
+                request.compared,
+                request.delta(),
+                request.ratio(),
+                if request.same_body {
+                    ""
+                } else {
+                    " [BODY MISMATCH]"
+                }
             );
+            for (node, secs) in [(baseline, request.baseline), (compared, request.compared)] {
+                influx::emit(
+                    Point::new("sequential")
+                        .tag("node", node.as_str())
+                        .tag("family", &endpoint_family(&request.path))
+                        .float("duration", secs)
+                        .field("path", FieldValue::Text(request.path.clone()))
+                        .field("same_body", FieldValue::Integer(request.same_body.into())),
+                );
+            }
+            aligned.push(request);
         }
-        let elapsed = start.elapsed();
-        println!("[{}] Duration in seconds: {}s", node, extract_secs(elapsed));
-        println!("--------------------------------------------------------------------")
+        Ok(())
+    })
+    .await;
+
+    let baseline_total: f64 = aligned.iter().map(|r| r.baseline).sum();
+    let compared_total: f64 = aligned.iter().map(|r| r.compared).sum();
+    println!("--------------------------------------------------------------------");
+    println!("[{}] Duration in seconds: {}s", baseline, baseline_total);
+    println!("[{}] Duration in seconds: {}s", compared, compared_total);
+    println!(
+        "Delta: {:+.6}s, ratio: {:.3}",
+        compared_total - baseline_total,
+        compared_total / baseline_total
+    );
+    if result.is_err() {
+        println!("Finished requests before the failure: {}", aligned.len());
     }
+    result?;
+
+    check_aligned(
+        &aligned,
+        baseline,
+        compared,
+        duration_threshold,
+        duration_no_fail,
+    )
+}
+
+/// Fails on the responses of the compared node differing from the baseline and on the total
+/// duration of the compared node exceeding the baseline by more than the threshold
+fn check_aligned(
+    aligned: &[AlignedRequest],
+    baseline: &Url,
+    compared: &Url,
+    duration_threshold: f32,
+    duration_no_fail: bool,
+) -> Result<(), failure::Error> {
+    let mismatches = aligned.iter().filter(|r| !r.same_body).collect::<Vec<_>>();
+    if !mismatches.is_empty() {
+        bail!(
+            "{} responses of {} differ from {}:\n{}",
+            mismatches.len(),
+            compared,
+            baseline,
+            mismatches
+                .iter()
+                .map(|r| r.path.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    let baseline_total: f64 = aligned.iter().map(|r| r.baseline).sum();
+    let compared_total: f64 = aligned.iter().map(|r| r.compared).sum();
+    if compared_total - baseline_total > baseline_total * f64::from(duration_threshold) {
+        if duration_no_fail {
+            println!(
+                "[Duration] Performance regression greater than {}%!",
+                duration_threshold * 100.0
+            )
+        } else {
+            bail!(
+                "[Duration] Performance regression greater than {}%!",
+                duration_threshold * 100.0
+            )
+        }
+    }
+    Ok(())
 }
 
 fn extract_secs(dur: Duration) -> f64 {
     (dur.as_secs() as f64) + (dur.subsec_nanos() as f64 / 1_000_000_000.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    /// Node answering every request with the body, the requested paths are recorded in the log
+    /// with the name of the node
+    fn node_stub(name: &'static str, body: &'static str, log: Arc<Mutex<Vec<String>>>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or("");
+                log.lock().unwrap().push(format!("{} {}", name, path));
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url.parse().unwrap()
+    }
+
+    fn request(path: &str, baseline: f64, compared: f64, same_body: bool) -> AlignedRequest {
+        AlignedRequest {
+            path: path.to_string(),
+            baseline,
+            compared,
+            same_body,
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_aligned() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let baseline = node_stub("baseline", r#"{"level": 1, "hash": "a"}"#, log.clone());
+        let same = node_stub("same", r#"{"hash": "a", "level": 1}"#, log.clone());
+        let other = node_stub("other", r#"{"level": 1, "hash": "b"}"#, log.clone());
+        let client = http_client().unwrap();
+
+        let first = aligned_request(&client, &baseline, &same, "a".to_string())
+            .await
+            .unwrap();
+        let second = aligned_request(&client, &baseline, &other, "b".to_string())
+            .await
+            .unwrap();
+        assert!(first.same_body);
+        assert!(!second.same_body);
+        assert_eq!(second.path, "b");
+        assert!(first.baseline > 0.0 && first.compared > 0.0);
+
+        // both nodes get the request before the next request
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["baseline /a", "same /a", "baseline /b", "other /b"]
+        );
+    }
+
+    #[test]
+    fn duration_gate() {
+        let (baseline, compared): (Url, Url) = (
+            "http://baseline:8732/".parse().unwrap(),
+            "http://compared:8732/".parse().unwrap(),
+        );
+        let check = |aligned: &[AlignedRequest], no_fail| {
+            check_aligned(aligned, &baseline, &compared, 0.1, no_fail)
+        };
+
+        // 10% slower in total, a single slow request does not fail
+        let aligned = [request("a", 1.0, 0.5, true), request("b", 1.0, 1.7, true)];
+        assert_eq!(aligned[1].delta(), 0.7);
+        assert_eq!(aligned[0].ratio(), 0.5);
+        assert!(check(&aligned, false).is_ok());
+
+        let aligned = [request("a", 1.0, 1.0, true), request("b", 1.0, 1.3, true)];
+        let error = check(&aligned, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[Duration] Performance regression greater than 10%!"
+        );
+        assert!(check(&aligned, true).is_ok());
+    }
+
+    #[test]
+    fn differing_responses_fail() {
+        let (baseline, compared): (Url, Url) = (
+            "http://baseline:8732/".parse().unwrap(),
+            "http://compared:8732/".parse().unwrap(),
+        );
+        let aligned = [request("a", 1.0, 1.0, false), request("b", 1.0, 1.0, true)];
+        let error = check_aligned(&aligned, &baseline, &compared, 0.1, true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 responses of http://compared:8732/ differ from http://baseline:8732/:\na"
+        );
+    }
+}
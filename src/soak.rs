@@ -0,0 +1,304 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use failure::{bail, format_err};
+use futures::future::try_join_all;
+use itertools::Itertools;
+
+use crate::configuration::SoakTestEnv;
+use crate::influx::{self, FieldValue, Point};
+use crate::mixed_workload::measure_mix;
+use crate::types::{Branch, RpcRequest, WrkResult};
+use crate::utils::{get_requests, http_client, interruptible};
+use crate::wrk::{calc_max_latency, calc_throughput, us_to_ms};
+
+/// Results of a single window of the soak test
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub window: usize,
+    /// Seconds since the start of the test at the end of the window
+    pub elapsed: f64,
+    pub result: WrkResult,
+    /// Resident memory of the node process in kB
+    pub rss: Option<u64>,
+}
+
+impl Snapshot {
+    pub fn throughput(&self) -> f32 {
+        calc_throughput(self.result.requests(), self.result.duration())
+    }
+
+    pub fn p99(&self) -> f32 {
+        us_to_ms(self.result.latency_p99())
+    }
+}
+
+/// Resident memory of the local process in kB
+pub fn rss_kb(pid: u32) -> Result<u64, failure::Error> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rss| rss.trim().trim_end_matches("kB").trim().parse().ok())
+        .ok_or_else(|| format_err!("Missing VmRSS of the process {}", pid))
+}
+
+fn display_snapshot(branch: &Branch, snapshot: &Snapshot) {
+    println!(
+        "[{}] window {:>3} at {:>8.0}s: {:>10.2} req/s, p50 {:>9.3}ms, p99 {:>9.3}ms, max {:>9.3}ms, errors {:>6.2}%{}",
+        branch.name,
+        snapshot.window,
+        snapshot.elapsed,
+        snapshot.throughput(),
+        us_to_ms(snapshot.result.latency_p50()),
+        snapshot.p99(),
+        calc_max_latency(snapshot.result.latency_max()),
+        snapshot.result.error_rate() * 100.0,
+        snapshot
+            .rss
+            .map_or(String::new(), |rss| format!(", rss {}MB", rss / 1024))
+    );
+}
+
+/// Keeps the mix on the branch for the duration, a snapshot of every window is displayed
+/// and stored in the snapshots of the branch
+async fn soak_branch(
+    env: &SoakTestEnv,
+    client: &reqwest::Client,
+    branch: &Branch,
+    urls: &[RpcRequest],
+    snapshots: &Mutex<Vec<Vec<Snapshot>>>,
+) -> Result<(), failure::Error> {
+    let duration = Duration::from_secs(env.duration);
+    let window = Duration::from_secs(env.window);
+    let pid = env
+        .pids
+        .iter()
+        .find(|(name, _)| *name == branch.name)
+        .map(|(_, pid)| *pid);
+    let start = Instant::now();
+    // the rest of the duration shorter than a window is merged into the last window, so the
+    // compared windows are of the same length at least
+    let windows = (env.duration / env.window).max(1);
+
+    for index in 0..windows {
+        let end = if index + 1 == windows {
+            duration
+        } else {
+            window * (index + 1) as u32
+        };
+        let length = end.saturating_sub(start.elapsed());
+        let result = measure_mix(client, branch, urls, env.connections, length).await?;
+        let snapshot = Snapshot {
+            window: index as usize,
+            elapsed: start.elapsed().as_secs_f64(),
+            result,
+            rss: pid.map(rss_kb).transpose()?,
+        };
+
+        display_snapshot(branch, &snapshot);
+        let mut point = Point::new("soak")
+            .branch(branch)
+            .field("window", FieldValue::Integer(index as i64))
+            .float("throughput", snapshot.throughput())
+            .float("latency_p99_ms", snapshot.p99())
+            .float("error_rate", snapshot.result.error_rate());
+        if let Some(rss) = snapshot.rss {
+            point = point.field("rss_kb", FieldValue::Integer(rss as i64));
+        }
+        influx::emit(point);
+
+        snapshots.lock().unwrap_or_else(|e| e.into_inner())[branch.sort_key].push(snapshot);
+    }
+    Ok(())
+}
+
+/// Degradations between the first and the last window of the branch
+pub fn degradations(
+    branch: &Branch,
+    snapshots: &[Snapshot],
+    p99_threshold: f32,
+    throughput_threshold: f32,
+    error_threshold: f32,
+    memory_threshold: f32,
+) -> Vec<String> {
+    let (first, last) = match (snapshots.first(), snapshots.last()) {
+        (Some(first), Some(last)) if snapshots.len() > 1 => (first, last),
+        _ => return Vec::new(),
+    };
+    let mut degradations = Vec::new();
+
+    if last.p99() - first.p99() > first.p99() * p99_threshold {
+        degradations.push(format!(
+            "[Soak] [{}] p99 latency rose from {:.3}ms to {:.3}ms, more than {}%!",
+            branch.name,
+            first.p99(),
+            last.p99(),
+            p99_threshold * 100.0
+        ));
+    }
+    if first.throughput() - last.throughput() > first.throughput() * throughput_threshold {
+        degradations.push(format!(
+            "[Soak] [{}] Throughput fell from {:.2}req/s to {:.2}req/s, more than {}%!",
+            branch.name,
+            first.throughput(),
+            last.throughput(),
+            throughput_threshold * 100.0
+        ));
+    }
+    if last.result.error_rate() - first.result.error_rate() > error_threshold {
+        degradations.push(format!(
+            "[Soak] [{}] Error rate rose from {:.2}% to {:.2}%, more than {} points!",
+            branch.name,
+            first.result.error_rate() * 100.0,
+            last.result.error_rate() * 100.0,
+            error_threshold * 100.0
+        ));
+    }
+    if let (Some(first_rss), Some(last_rss)) = (first.rss, last.rss) {
+        if last_rss as f32 - first_rss as f32 > first_rss as f32 * memory_threshold {
+            degradations.push(format!(
+                "[Soak] [{}] Memory grew from {}MB to {}MB, more than {}%!",
+                branch.name,
+                first_rss / 1024,
+                last_rss / 1024,
+                memory_threshold * 100.0
+            ));
+        }
+    }
+    degradations
+}
+
+/// Keeps a steady mixed load on every target for hours, reports a snapshot of every window
+/// and fails, when the last window of a target degraded compared to its first window
+pub async fn soak_test(env: SoakTestEnv) -> Result<(), failure::Error> {
+    let client = http_client()?;
+    let branches = env
+        .targets
+        .iter()
+        .enumerate()
+        .map(|(sort_key, target)| target.branch(sort_key))
+        .collect_vec();
+    let urls = get_requests(&env.url_file)?;
+    // the finished windows are also checked, when the test is interrupted
+    let snapshots = Mutex::new(vec![Vec::new(); branches.len()]);
+
+    println!(
+        "Soaking {} targets with the mix of {} urls on {} connections for {}s, windows of {}s",
+        branches.len(),
+        urls.len(),
+        env.connections,
+        env.duration,
+        env.window
+    );
+    let soaks = branches
+        .iter()
+        .map(|branch| soak_branch(&env, &client, branch, &urls, &snapshots));
+    let result = interruptible(async { try_join_all(soaks).await.map(|_| ()) }).await;
+
+    println!("------------------------------------------------------");
+    let snapshots = snapshots.into_inner().unwrap_or_else(|e| e.into_inner());
+    let mut degraded = Vec::new();
+    for (branch, snapshots) in branches.iter().zip(&snapshots) {
+        if let (Some(first), Some(last)) = (snapshots.first(), snapshots.last()) {
+            println!(
+                "[{}] {} windows, first -> last: {:.2} -> {:.2} req/s, p99 {:.3} -> {:.3}ms, errors {:.2} -> {:.2}%",
+                branch.name,
+                snapshots.len(),
+                first.throughput(),
+                last.throughput(),
+                first.p99(),
+                last.p99(),
+                first.result.error_rate() * 100.0,
+                last.result.error_rate() * 100.0
+            );
+        }
+        degraded.extend(degradations(
+            branch,
+            snapshots,
+            env.p99_threshold,
+            env.throughput_threshold,
+            env.error_threshold,
+            env.memory_threshold,
+        ));
+    }
+    for degradation in &degraded {
+        println!("{}", degradation);
+    }
+    result?;
+
+    if !degraded.is_empty() && !env.degradation_no_fail {
+        bail!("{}", degraded.join("\n"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BranchType, WrkErrors};
+
+    fn branch() -> Branch {
+        let url = "http://tezedge:18732/".parse().unwrap();
+        Branch::new(0, "tezedge", url, BranchType::Feature)
+    }
+
+    /// Window of a second with the requests, their latency in milliseconds and the timeouts
+    fn snapshot(window: usize, requests: usize, latency: f32, timeouts: f32, rss: u64) -> Snapshot {
+        let errors = WrkErrors {
+            timeout: timeouts,
+            ..WrkErrors::default()
+        };
+        Snapshot {
+            window,
+            elapsed: (window + 1) as f64,
+            result: WrkResult::from_latencies(1_000_000.0, &vec![latency * 1000.0; requests])
+                .with_errors(errors, Vec::new()),
+            rss: Some(rss),
+        }
+    }
+
+    fn degradations_of(snapshots: &[Snapshot]) -> Vec<String> {
+        degradations(&branch(), snapshots, 0.2, 0.1, 0.01, 0.2)
+    }
+
+    #[test]
+    fn steady_windows_do_not_degrade() {
+        let steady = vec![
+            snapshot(0, 1000, 2.0, 0.0, 100_000),
+            // a worse window in the middle is not compared
+            snapshot(1, 100, 20.0, 100.0, 500_000),
+            snapshot(2, 950, 2.2, 5.0, 110_000),
+        ];
+        assert!(degradations_of(&steady).is_empty());
+        assert!(degradations_of(&steady[..1]).is_empty());
+        assert!(degradations_of(&[]).is_empty());
+    }
+
+    #[test]
+    fn last_window_degradations() {
+        let degraded = degradations_of(&[
+            snapshot(0, 1000, 2.0, 0.0, 102_400),
+            snapshot(1, 800, 3.0, 20.0, 204_800),
+        ]);
+        assert_eq!(
+            degraded,
+            vec![
+                "[Soak] [tezedge] p99 latency rose from 2.000ms to 3.000ms, more than 20%!",
+                "[Soak] [tezedge] Throughput fell from 1000.00req/s to 800.00req/s, more than 10%!",
+                "[Soak] [tezedge] Error rate rose from 0.00% to 2.44%, more than 1 points!",
+                "[Soak] [tezedge] Memory grew from 100MB to 200MB, more than 20%!",
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_of_the_current_process() {
+        assert!(rss_kb(std::process::id()).unwrap() > 0);
+        assert!(rss_kb(u32::MAX).is_err());
+    }
+}
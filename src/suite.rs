@@ -0,0 +1,216 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fs::File;
+use std::panic::AssertUnwindSafe;
+use std::time::Instant;
+
+use failure::format_err;
+use futures::FutureExt;
+use serde::Serialize;
+use tokio::task;
+
+use crate::configuration::{
+    bootstrap_app, BootstrapEnv, BootstrapLoadEnv, ChaosTestEnv, CompareTestEnv, GenerateUrlsEnv,
+    IndexerTestEnv, ReplayEnv, RpcLatencyTestEnv, RpcPerformanceTestEnv, RunSuiteEnv,
+    SequentialTestEnv, SoakTestEnv, SuiteConfig,
+};
+use crate::utils::{interrupted, interruptible, Interrupted};
+use crate::{
+    bootstrap, bootstrap_load, chaos, compare_test, generate_urls, html_report, indexer_test,
+    mixed_workload, replay, sequential_request_test, soak, sweep, wrk, wrk2,
+};
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PhaseStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PhaseReport {
+    pub phase: String,
+    pub status: PhaseStatus,
+    pub duration: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SuiteReport {
+    pub passed: bool,
+    pub phases: Vec<PhaseReport>,
+}
+
+/// Runs a single subcommand, the wrk based tests run on the blocking thread pool
+pub async fn run_subcommand(matches: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    if let Some(subcommand) = matches.subcommand_matches("bootstrap") {
+        let env = BootstrapEnv::from_args(subcommand)?;
+        bootstrap::start_bootstrap(env).await
+    } else if let Some(subcommand) = matches.subcommand_matches("performance-test") {
+        let env = RpcPerformanceTestEnv::from_args(subcommand)?;
+        if env.mixed_workload {
+            return mixed_workload::test_mixed_workload(env)
+                .await
+                .map_err(|e| format_err!("Error in mixed workload tests: {}", e));
+        }
+        if env.sweep {
+            return interruptible(async {
+                task::spawn_blocking(move || sweep::test_saturation(env)).await?
+            })
+            .await
+            .map_err(|e| format_err!("Error in wrk sweep tests: {}", e));
+        }
+        interruptible(async { task::spawn_blocking(move || wrk::test_rpc_performance(env)).await? })
+            .await
+            .map_err(|e| format_err!("Error in wrk tests: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("latency-test") {
+        let env = RpcLatencyTestEnv::from_args(subcommand)?;
+        interruptible(async {
+            task::spawn_blocking(move || wrk2::test_rpc_performance(env)).await?
+        })
+        .await
+        .map_err(|e| format_err!("Error in wrk tests: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("compare-test") {
+        let env = CompareTestEnv::from_args(subcommand)?;
+        compare_test::test_responses(env)
+            .await
+            .map_err(|e| format_err!("Error in compare tests: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("indexer-test") {
+        let env = IndexerTestEnv::from_args(subcommand)?;
+        indexer_test::test_indexer(env)
+            .await
+            .map_err(|e| format_err!("Error in indexer tests: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("generate-urls") {
+        let env = GenerateUrlsEnv::from_args(subcommand)?;
+        generate_urls::generate_urls(env).await
+    } else if let Some(subcommand) = matches.subcommand_matches("replay") {
+        let env = ReplayEnv::from_args(subcommand)?;
+        replay::replay(env).await
+    } else if let Some(subcommand) = matches.subcommand_matches("bootstrap-load") {
+        let env = BootstrapLoadEnv::from_args(subcommand)?;
+        bootstrap_load::bootstrap_load(env)
+            .await
+            .map_err(|e| format_err!("Error in bootstrap under load: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("chaos-test") {
+        let env = ChaosTestEnv::from_args(subcommand)?;
+        chaos::chaos_test(env)
+            .await
+            .map_err(|e| format_err!("Error in chaos tests: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("soak-test") {
+        let env = SoakTestEnv::from_args(subcommand)?;
+        soak::soak_test(env)
+            .await
+            .map_err(|e| format_err!("Error in soak tests: {}", e))
+    } else if let Some(subcommand) = matches.subcommand_matches("sequential-test") {
+        let env = SequentialTestEnv::from_args(subcommand)?;
+        sequential_request_test::test_sequential_requests(env).await
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs the phases of the suite in order, a failed phase stops the suite unless it is listed
+/// in the continue-on-failure arg, Ctrl-C stops the suite and reports the finished phases
+pub async fn run_suite(
+    suite: &SuiteConfig,
+    env: RunSuiteEnv,
+    bin: &str,
+) -> Result<SuiteReport, failure::Error> {
+    let RunSuiteEnv {
+        phases,
+        continue_on_failure,
+        report,
+    } = env;
+
+    let mut reports = Vec::new();
+    let mut stopped = false;
+
+    for phase in phases {
+        if stopped {
+            reports.push(PhaseReport {
+                phase,
+                status: PhaseStatus::Skipped,
+                duration: 0.0,
+                error: None,
+            });
+            continue;
+        }
+
+        println!(
+            "====================== Phase: {} ======================",
+            phase
+        );
+        let start = Instant::now();
+        let result = run_phase(suite, bin, &phase).await;
+        let duration = start.elapsed().as_secs_f64();
+
+        let phase_report = match result {
+            Ok(()) => PhaseReport {
+                phase,
+                status: PhaseStatus::Passed,
+                duration,
+                error: None,
+            },
+            Err(e) => {
+                println!("[{}] Phase failed: {}", phase, e);
+                stopped = !continue_on_failure.contains(&phase)
+                    || e.downcast_ref::<Interrupted>().is_some()
+                    || interrupted();
+                PhaseReport {
+                    phase,
+                    status: PhaseStatus::Failed,
+                    duration,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        reports.push(phase_report);
+    }
+
+    let suite_report = SuiteReport {
+        passed: reports.iter().all(|r| r.status == PhaseStatus::Passed),
+        phases: reports,
+    };
+    display_report(&suite_report);
+    html_report::record_phases(&suite_report.phases);
+
+    if let Some(report) = report {
+        serde_json::to_writer_pretty(File::create(&report)?, &suite_report)?;
+        println!("Report written to {}", report);
+    }
+
+    Ok(suite_report)
+}
+
+async fn run_phase(suite: &SuiteConfig, bin: &str, phase: &str) -> Result<(), failure::Error> {
+    let matches = bootstrap_app().get_matches_from_safe(suite.phase_command_line(bin, phase))?;
+
+    // a panic in a phase must not prevent the report of the remaining phases
+    AssertUnwindSafe(run_subcommand(&matches))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|_| Err(format_err!("Phase {} panicked", phase)))
+}
+
+fn display_report(report: &SuiteReport) {
+    println!("====================== Suite report ======================");
+    for phase in &report.phases {
+        println!(
+            "{:<20} {:<8} {:>10.1}s {}",
+            phase.phase,
+            format!("{:?}", phase.status),
+            phase.duration,
+            phase
+                .error
+                .as_ref()
+                .and_then(|e| e.lines().next())
+                .unwrap_or("")
+        );
+    }
+    println!(
+        "Result: {}",
+        if report.passed { "PASSED" } else { "FAILED" }
+    );
+}
@@ -0,0 +1,391 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Write as _;
+use std::fs;
+
+use itertools::Itertools;
+
+use crate::types::{Branch, BranchType};
+use crate::wrk::{calc_max_latency, calc_throughput, us_to_ms, Regression, WrkResultMap};
+
+/// Outcome of a branch for a single rpc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The branch the others are compared to
+    Reference,
+    /// A gated branch without regressions
+    Passed,
+    /// A gated branch with a regression, also a regression ignored by a no-fail flag, or a
+    /// branch with diverging sampled responses
+    Failed,
+    /// A branch, which is compared, but not gated
+    Compared,
+}
+
+impl Verdict {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Verdict::Reference => "reference",
+            Verdict::Passed => "✓",
+            Verdict::Failed => "✗",
+            Verdict::Compared => "-",
+        }
+    }
+
+    /// Symbol coloured for the terminal, unless NO_COLOR is set
+    fn coloured(&self) -> String {
+        let colour = match self {
+            Verdict::Passed => "32",
+            Verdict::Failed => "31",
+            _ => return self.symbol().to_string(),
+        };
+        if std::env::var_os("NO_COLOR").is_some() {
+            self.symbol().to_string()
+        } else {
+            format!("\x1b[{}m{}\x1b[0m", colour, self.symbol())
+        }
+    }
+}
+
+/// Results of a branch for a single rpc, the latencies are in milliseconds
+#[derive(Debug, Clone)]
+pub struct SummaryRow {
+    pub rpc: String,
+    pub branch: String,
+    pub throughput: f32,
+    pub p50: f32,
+    pub p99: f32,
+    pub max: f32,
+    /// Deltas of the throughput, p50, p99 and max latency to the reference in percent
+    pub deltas: Option<[Option<f32>; 4]>,
+    /// Duration of the single request before the warm-up, with `--cold-warm`
+    pub cold: Option<f32>,
+    /// Position of the branch in the measurement order of the rpc, starting at 1
+    pub position: Option<usize>,
+    pub verdict: Verdict,
+}
+
+impl SummaryRow {
+    fn values(&self) -> [f32; 4] {
+        [self.throughput, self.p50, self.p99, self.max]
+    }
+
+    /// Cells of the row, the verdict is added by the table
+    fn cells(&self, cold: bool, order: bool) -> Vec<String> {
+        let mut cells = vec![self.rpc.clone(), self.branch.clone()];
+        cells.extend(self.values().iter().map(|value| format!("{:.2}", value)));
+        cells.extend((0..4).map(|i| match self.deltas {
+            Some(deltas) => deltas[i].map_or("-".to_string(), |delta| format!("{:+.1}%", delta)),
+            None => String::new(),
+        }));
+        if cold {
+            cells.push(self.cold.map_or(String::new(), |ms| format!("{:.2}", ms)));
+        }
+        if order {
+            cells.push(self.position.map_or(String::new(), |p| p.to_string()));
+        }
+        cells
+    }
+}
+
+const HEADER: [&str; 10] = [
+    "rpc", "branch", "req/s", "p50 ms", "p99 ms", "max ms", "Δ req/s", "Δ p50", "Δ p99", "Δ max",
+];
+
+/// Final table of the performance-test, a row for every rpc and branch
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub rows: Vec<SummaryRow>,
+}
+
+impl Summary {
+    /// Adds the rows of a single rpc, the regressions set the verdicts of the branches
+    pub fn add(
+        &mut self,
+        rpc: &str,
+        results: &WrkResultMap,
+        reference: &Branch,
+        regressions: &[Regression],
+    ) {
+        let row = |branch: &Branch| {
+            let result = &results[branch];
+            SummaryRow {
+                rpc: rpc.to_string(),
+                branch: branch.name.clone(),
+                throughput: calc_throughput(result.requests(), result.duration()),
+                p50: us_to_ms(result.latency_p50()),
+                p99: us_to_ms(result.latency_p99()),
+                max: calc_max_latency(result.latency_max()),
+                deltas: None,
+                cold: None,
+                position: None,
+                verdict: Verdict::Reference,
+            }
+        };
+
+        let reference_row = row(reference);
+        for branch in results.keys().sorted_by_key(|b| b.sort_key) {
+            if branch == reference {
+                self.rows.push(reference_row.clone());
+                continue;
+            }
+            let mut branch_row = row(branch);
+            let mut deltas = [None; 4];
+            for (delta, (value, reference_value)) in deltas
+                .iter_mut()
+                .zip(branch_row.values().iter().zip(reference_row.values()))
+            {
+                if reference_value > 0.0 {
+                    *delta = Some((value - reference_value) / reference_value * 100.0);
+                }
+            }
+            branch_row.deltas = Some(deltas);
+            // only the feature branches are gated, when the reference is a stable build, the
+            // diverging responses fail every branch
+            branch_row.verdict = if regressions.iter().any(|r| &r.branch == branch) {
+                Verdict::Failed
+            } else if reference.branch_type != BranchType::Stable
+                || branch.branch_type != BranchType::Feature
+            {
+                Verdict::Compared
+            } else {
+                Verdict::Passed
+            };
+            self.rows.push(branch_row);
+        }
+    }
+
+    /// Durations of the cold requests of the rpc, the rows of the rpc have to be added
+    pub fn add_cold(&mut self, rpc: &str, cold: &[(Branch, f32)]) {
+        for row in self.rows.iter_mut().filter(|row| row.rpc == rpc) {
+            if let Some((_, ms)) = cold.iter().find(|(branch, _)| branch.name == row.branch) {
+                row.cold = Some(*ms);
+            }
+        }
+    }
+
+    /// Order the branches of the rpc have been measured in
+    pub fn add_order(&mut self, rpc: &str, order: &[Branch]) {
+        for row in self.rows.iter_mut().filter(|row| row.rpc == rpc) {
+            row.position = order
+                .iter()
+                .position(|branch| branch.name == row.branch)
+                .map(|i| i + 1);
+        }
+    }
+
+    /// Whether the table has the column of the cold requests
+    fn has_cold(&self) -> bool {
+        self.rows.iter().any(|row| row.cold.is_some())
+    }
+
+    /// Whether the table has the column of the measurement order
+    fn has_order(&self) -> bool {
+        self.rows.iter().any(|row| row.position.is_some())
+    }
+
+    fn header(&self) -> Vec<&'static str> {
+        let mut header = HEADER.to_vec();
+        if self.has_cold() {
+            header.push("cold ms");
+        }
+        if self.has_order() {
+            header.push("order");
+        }
+        header
+    }
+
+    fn cells(&self) -> Vec<Vec<String>> {
+        let (cold, order) = (self.has_cold(), self.has_order());
+        self.rows.iter().map(|row| row.cells(cold, order)).collect()
+    }
+
+    /// Prints the aligned table
+    pub fn display(&self) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let header = self.header();
+        let rows = self.cells();
+        let widths = (0..header.len())
+            .map(|i| {
+                rows.iter()
+                    .map(|cells| cells[i].chars().count())
+                    .chain(std::iter::once(header[i].chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect_vec();
+        // the rpc and branch columns are aligned left, the numbers right
+        let line = |cells: &[String]| {
+            cells
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (cell, width))| {
+                    let padding = " ".repeat(width - cell.chars().count());
+                    if i < 2 {
+                        format!("{}{}", cell, padding)
+                    } else {
+                        format!("{}{}", padding, cell)
+                    }
+                })
+                .join("  ")
+        };
+
+        println!("====================== Summary ======================");
+        let header = header.iter().map(|h| h.to_string()).collect_vec();
+        println!("{}  verdict", line(&header));
+        for (row, cells) in self.rows.iter().zip(&rows) {
+            println!("{}  {}", line(cells), row.verdict.coloured());
+        }
+        println!();
+    }
+
+    /// The same table in markdown, e.g. for a pull request comment
+    pub fn markdown(&self) -> String {
+        let header = self.header();
+        let mut markdown = String::new();
+        let _ = writeln!(markdown, "| {} | verdict |", header.join(" | "));
+        let _ = writeln!(
+            markdown,
+            "|---|---|{}---|",
+            "---:|".repeat(header.len() - 2)
+        );
+        for (row, mut cells) in self.rows.iter().zip(self.cells()) {
+            cells[0] = format!("`{}`", cells[0].replace('|', "\\|"));
+            let _ = writeln!(
+                markdown,
+                "| {} | {} |",
+                cells.join(" | "),
+                row.verdict.symbol()
+            );
+        }
+        markdown
+    }
+
+    pub fn write_markdown(&self, file: &str) -> Result<(), failure::Error> {
+        fs::write(file, self.markdown())?;
+        println!("Markdown summary written to {}", file);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WrkResult;
+
+    fn branch(sort_key: usize, name: &str, branch_type: BranchType) -> Branch {
+        let url = format!("http://{}:8732/", name).parse().unwrap();
+        Branch::new(sort_key, name, url, branch_type)
+    }
+
+    /// Requests during a second, all with the latency in microseconds
+    fn result(requests: usize, latency: f32) -> WrkResult {
+        WrkResult::from_latencies(1_000_000.0, &vec![latency; requests])
+    }
+
+    fn summary(regressions: &[Regression]) -> Summary {
+        let stable = branch(0, "stable", BranchType::Stable);
+        let results: WrkResultMap = vec![
+            (stable.clone(), result(1000, 1000.0)),
+            (branch(1, "ocaml", BranchType::Ocaml), result(1000, 1000.0)),
+            (
+                branch(2, "feature", BranchType::Feature),
+                result(800, 2000.0),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut summary = Summary::default();
+        summary.add("chains/main/blocks/head|x", &results, &stable, regressions);
+        summary
+    }
+
+    #[test]
+    fn verdicts_of_the_branches() {
+        let verdicts = |summary: Summary| {
+            summary
+                .rows
+                .iter()
+                .map(|row| (row.branch.clone(), row.verdict))
+                .collect_vec()
+        };
+        let expected = |feature| {
+            vec![
+                ("stable".to_string(), Verdict::Reference),
+                ("ocaml".to_string(), Verdict::Compared),
+                ("feature".to_string(), feature),
+            ]
+        };
+
+        assert_eq!(verdicts(summary(&[])), expected(Verdict::Passed));
+        let regression = Regression {
+            branch: branch(2, "feature", BranchType::Feature),
+            message: "[Troughput] [feature] Performance regression greater than 10%!".to_string(),
+            fatal: false,
+        };
+        assert_eq!(verdicts(summary(&[regression])), expected(Verdict::Failed));
+    }
+
+    #[test]
+    fn markdown_table() {
+        assert_eq!(
+            summary(&[]).markdown(),
+            "| rpc | branch | req/s | p50 ms | p99 ms | max ms | Δ req/s | Δ p50 | Δ p99 | Δ max | verdict |\n\
+             |---|---|---:|---:|---:|---:|---:|---:|---:|---:|---|\n\
+             | `chains/main/blocks/head\\|x` | stable | 1000.00 | 1.00 | 1.00 | 1.00 |  |  |  |  | reference |\n\
+             | `chains/main/blocks/head\\|x` | ocaml | 1000.00 | 1.00 | 1.00 | 1.00 | +0.0% | +0.0% | +0.0% | +0.0% | - |\n\
+             | `chains/main/blocks/head\\|x` | feature | 800.00 | 2.00 | 2.00 | 2.00 | -20.0% | +100.0% | +100.0% | +100.0% | ✓ |\n"
+        );
+        assert_eq!(
+            Summary::default().markdown().lines().count(),
+            2,
+            "only the header without rows"
+        );
+    }
+
+    #[test]
+    fn cold_requests_column() {
+        let mut summary = summary(&[]);
+        summary.add_cold(
+            "chains/main/blocks/head|x",
+            &[
+                (branch(0, "stable", BranchType::Stable), 120.0),
+                (branch(2, "feature", BranchType::Feature), 80.5),
+            ],
+        );
+        let markdown = summary.markdown();
+        let lines = markdown.lines().collect_vec();
+        assert!(lines[0].ends_with("| Δ max | cold ms | verdict |"));
+        assert_eq!(
+            lines[1],
+            "|---|---|---:|---:|---:|---:|---:|---:|---:|---:|---:|---|"
+        );
+        assert!(lines[2].ends_with("|  | 120.00 | reference |"));
+        assert!(lines[3].ends_with("| +0.0% |  | - |"));
+        assert!(lines[4].ends_with("| +100.0% | 80.50 | ✓ |"));
+    }
+
+    #[test]
+    fn order_column() {
+        let mut summary = summary(&[]);
+        summary.add_order(
+            "chains/main/blocks/head|x",
+            &[
+                branch(2, "feature", BranchType::Feature),
+                branch(0, "stable", BranchType::Stable),
+                branch(1, "ocaml", BranchType::Ocaml),
+            ],
+        );
+        let markdown = summary.markdown();
+        let lines = markdown.lines().collect_vec();
+        assert!(lines[0].ends_with("| Δ max | order | verdict |"));
+        assert!(lines[2].ends_with("|  | 2 | reference |"));
+        assert!(lines[3].ends_with("| +0.0% | 3 | - |"));
+        assert!(lines[4].ends_with("| +100.0% | 1 | ✓ |"));
+    }
+}
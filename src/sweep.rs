@@ -0,0 +1,216 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use failure::bail;
+use itertools::Itertools;
+
+use crate::configuration::{Gates, RpcPerformanceTestEnv};
+use crate::html_report;
+use crate::metrics;
+use crate::types::{reference_index, Branch, BranchType, RpcRequest};
+use crate::utils::get_requests;
+use crate::wrk::{calc_max_latency, calc_throughput, run_wrk, us_to_ms};
+
+/// Smallest throughput gain of a step, which is not considered saturated
+pub const SATURATION_GAIN: f32 = 0.1;
+
+/// Results of wrk on a number of connections
+#[derive(Debug, Clone)]
+pub struct SweepStep {
+    pub connections: usize,
+    pub throughput: f32,
+    pub latency_mean: f32,
+    pub latency_max: f32,
+}
+
+/// Connection counts of the sweep: the powers of two up to the maximum and the maximum
+pub fn sweep_connections(max_connections: usize) -> Vec<usize> {
+    let mut steps = std::iter::successors(Some(1), |c| Some(c * 2))
+        .take_while(|c| *c < max_connections)
+        .collect_vec();
+    steps.push(max_connections);
+    steps
+}
+
+/// Index of the saturation step, the last step before the throughput stops growing by at
+/// least the saturation gain, or the last step, when the node did not saturate
+pub fn saturation_index(steps: &[SweepStep]) -> usize {
+    steps
+        .windows(2)
+        .position(|pair| pair[1].throughput < pair[0].throughput * (1.0 + SATURATION_GAIN))
+        .unwrap_or(steps.len().saturating_sub(1))
+}
+
+fn sweep_branch(
+    branch: &Branch,
+    rpc: &RpcRequest,
+    duration: &u64,
+    max_connections: usize,
+) -> Result<Vec<SweepStep>, failure::Error> {
+    let mut steps = Vec::new();
+    for connections in sweep_connections(max_connections) {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+
+        let result = run_wrk(branch, rpc, duration, connections, None, false)?;
+        metrics::observe_wrk_result(&branch.name, &result);
+        steps.push(SweepStep {
+            connections,
+            throughput: calc_throughput(result.requests(), result.duration()),
+            latency_mean: us_to_ms(result.latency_mean()),
+            latency_max: calc_max_latency(result.latency_max()),
+        });
+    }
+    Ok(steps)
+}
+
+fn display_sweep(branch: &Branch, steps: &[SweepStep]) {
+    let saturation = saturation_index(steps);
+    println!(
+        "[{}] {:>11} {:>12} {:>12} {:>12}",
+        branch.name, "connections", "req/s", "mean ms", "max ms"
+    );
+    for (i, step) in steps.iter().enumerate() {
+        println!(
+            "[{}] {:>11} {:>12.2} {:>12.3} {:>12.3}{}",
+            branch.name,
+            step.connections,
+            step.throughput,
+            step.latency_mean,
+            step.latency_max,
+            if i == saturation {
+                " <- saturation"
+            } else {
+                ""
+            }
+        );
+    }
+    println!();
+}
+
+/// Ramps the connections of wrk for every rpc and branch, finds the saturation point of every
+/// branch and fails when the saturation throughput of a feature branch is lower than the
+/// throughput of a stable reference by more than the throughput threshold
+pub fn test_saturation(env: RpcPerformanceTestEnv) -> Result<(), failure::Error> {
+    let RpcPerformanceTestEnv {
+        targets,
+        url_file,
+        wrk_test_duration,
+        gates:
+            Gates {
+                throughput_threshold,
+                throughput_no_fail,
+                ..
+            },
+        max_connections,
+        ..
+    } = env;
+
+    let branches = targets
+        .iter()
+        .enumerate()
+        .map(|(sort_key, target)| target.branch(sort_key))
+        .collect_vec();
+    let reference = &branches[reference_index(&targets)];
+
+    for rpc in get_requests(&url_file)? {
+        println!("Sweeping the connections for rpc: {}", rpc);
+        println!();
+
+        let mut saturations = Vec::new();
+        let mut sweeps = Vec::new();
+        for branch in &branches {
+            let steps = sweep_branch(branch, &rpc, &wrk_test_duration, max_connections)?;
+            display_sweep(branch, &steps);
+            saturations.push(steps[saturation_index(&steps)].clone());
+            sweeps.push((branch.clone(), steps));
+        }
+        html_report::record_sweep(&rpc.to_string(), &sweeps);
+
+        let reference_saturation = &saturations[reference.sort_key];
+        println!("Saturation compared to {} node: ", reference.name);
+        for (branch, saturation) in branches.iter().zip(&saturations) {
+            println!(
+                "\t {}: {}req/s on {} connections",
+                branch.name, saturation.throughput, saturation.connections
+            );
+            if branch != reference {
+                println!(
+                    "\t {} - {}: {}req/s",
+                    branch.name,
+                    reference.name,
+                    saturation.throughput - reference_saturation.throughput
+                );
+            }
+        }
+        println!("------------------------------------------------------");
+        println!();
+
+        // only gate the feature branches, when the reference is a stable build
+        if reference.branch_type != BranchType::Stable {
+            continue;
+        }
+        for (branch, saturation) in branches.iter().zip(&saturations) {
+            if branch.branch_type == BranchType::Feature
+                && reference_saturation.throughput * throughput_threshold
+                    < reference_saturation.throughput - saturation.throughput
+            {
+                if throughput_no_fail {
+                    println!(
+                        "[Saturation] [{}] Performance regression greater than {}%!",
+                        branch.name,
+                        throughput_threshold * 100.0
+                    )
+                } else {
+                    bail!(
+                        "[Saturation] [{}] Performance regression greater than {}%!",
+                        branch.name,
+                        throughput_threshold * 100.0
+                    )
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(throughputs: &[f32]) -> Vec<SweepStep> {
+        throughputs
+            .iter()
+            .zip(sweep_connections(1 << (throughputs.len() - 1)))
+            .map(|(throughput, connections)| SweepStep {
+                connections,
+                throughput: *throughput,
+                latency_mean: 1000.0,
+                latency_max: 2000.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sweep_connections_powers_of_two() {
+        assert_eq!(sweep_connections(1), vec![1]);
+        assert_eq!(sweep_connections(8), vec![1, 2, 4, 8]);
+        assert_eq!(sweep_connections(12), vec![1, 2, 4, 8, 12]);
+    }
+
+    #[test]
+    fn saturation_at_the_last_growing_step() {
+        assert_eq!(saturation_index(&steps(&[100.0, 200.0, 215.0, 220.0])), 1);
+        // a drop of the throughput saturates too
+        assert_eq!(saturation_index(&steps(&[100.0, 80.0, 300.0])), 0);
+        // the first step under the saturation gain ends the ramp
+        assert_eq!(saturation_index(&steps(&[100.0, 105.0, 200.0])), 0);
+    }
+
+    #[test]
+    fn saturation_at_the_last_step_without_saturation() {
+        assert_eq!(saturation_index(&steps(&[100.0, 200.0, 400.0])), 2);
+        assert_eq!(saturation_index(&steps(&[100.0])), 0);
+        assert_eq!(saturation_index(&[]), 0);
+    }
+}
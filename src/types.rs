@@ -1,8 +1,11 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::BTreeMap;
 use std::fmt;
+use std::str::FromStr;
 
+use failure::format_err;
 use getset::Getters;
 use serde::Deserialize;
 use url::Url;
@@ -20,17 +23,192 @@ impl fmt::Display for BranchType {
     }
 }
 
+impl FromStr for BranchType {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "baseline" | "stable" => Ok(BranchType::Stable),
+            "feature" | "candidate" => Ok(BranchType::Feature),
+            "ocaml" => Ok(BranchType::Ocaml),
+            _ => Err(format_err!(
+                "Unknown role {:?}, expected one of baseline, feature, ocaml",
+                s
+            )),
+        }
+    }
+}
+
+/// A labeled node endpoint taking part in a comparison.
+///
+/// Parsed from `NAME=URL[,role=ROLE][,indexer=URL]`, the role defaults to `feature`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Target {
+    pub name: String,
+    pub url: Url,
+    pub branch_type: BranchType,
+    pub indexer: Option<Url>,
+}
+
+impl Target {
+    pub fn new(name: &str, url: Url, branch_type: BranchType) -> Self {
+        Self {
+            name: name.to_string(),
+            url,
+            branch_type,
+            indexer: None,
+        }
+    }
+
+    pub fn branch(&self, sort_key: usize) -> Branch {
+        Branch::new(
+            sort_key,
+            &self.name,
+            self.url.clone(),
+            self.branch_type.clone(),
+        )
+    }
+}
+
+impl FromStr for Target {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+
+        let (name, url) = match parts
+            .next()
+            .and_then(|p| p.find('=').map(|i| p.split_at(i)))
+        {
+            Some((name, url)) if !name.is_empty() => (name, &url[1..]),
+            _ => return Err(format_err!("Target {:?} does not start with NAME=URL", s)),
+        };
+        let mut target = Target::new(name, url.parse()?, BranchType::Feature);
+
+        for part in parts {
+            match part.find('=').map(|i| part.split_at(i)) {
+                Some(("role", role)) => target.branch_type = role[1..].parse()?,
+                Some(("indexer", indexer)) => target.indexer = Some(indexer[1..].parse()?),
+                _ => return Err(format_err!("Unknown target option {:?} in {:?}", part, s)),
+            }
+        }
+
+        Ok(target)
+    }
+}
+
+/// A rpc request of the url-file.
+///
+/// A plain line holds the path of a GET request, optionally followed by its weight. A line
+/// starting with `{` is a json object with the `path` and the optional `method`, `headers`,
+/// `body` and `weight`, a json body which is not a string is sent encoded.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcRequest {
+    #[serde(default = "RpcRequest::default_method")]
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+    #[serde(default = "RpcRequest::default_weight")]
+    pub weight: u32,
+}
+
+impl RpcRequest {
+    fn default_method() -> String {
+        "GET".to_string()
+    }
+
+    fn default_weight() -> u32 {
+        1
+    }
+
+    pub fn get(path: &str, weight: u32) -> Self {
+        Self {
+            method: Self::default_method(),
+            path: path.to_string(),
+            headers: BTreeMap::new(),
+            body: None,
+            weight,
+        }
+    }
+
+    /// Whether the request is a plain GET without headers or body
+    pub fn is_plain_get(&self) -> bool {
+        self.method == "GET" && self.headers.is_empty() && self.body.is_none()
+    }
+
+    /// Whether the request sets the header, the header names are case-insensitive
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers
+            .keys()
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    pub fn body_text(&self) -> Option<String> {
+        match &self.body {
+            Some(serde_json::Value::String(body)) => Some(body.clone()),
+            Some(body) => Some(body.to_string()),
+            None => None,
+        }
+    }
+
+    /// Args of the scripts/as_json.lua wrk script following the sampling: the method, the
+    /// body and the `Name: value` headers, wrk is run without a shell so the args are passed
+    /// verbatim
+    pub fn script_args(&self) -> Vec<String> {
+        let mut args = vec![self.method.clone(), self.body_text().unwrap_or_default()];
+        if self.body.is_some() && !self.has_header("Content-Type") {
+            args.push("Content-Type: application/json".to_string());
+        }
+        args.extend(
+            self.headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value)),
+        );
+        args
+    }
+}
+
+impl fmt::Display for RpcRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.method == "GET" {
+            write!(f, "{}", self.path)
+        } else {
+            write!(f, "{} {}", self.method, self.path)
+        }
+    }
+}
+
+/// Index of the target every other target is compared against.
+///
+/// This is the target with the `baseline` role, or the first ocaml node, or the first target.
+pub fn reference_index(targets: &[Target]) -> usize {
+    targets
+        .iter()
+        .position(|t| t.branch_type == BranchType::Stable)
+        .or_else(|| {
+            targets
+                .iter()
+                .position(|t| t.branch_type == BranchType::Ocaml)
+        })
+        .unwrap_or(0)
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Branch {
     pub sort_key: usize,
+    pub name: String,
     pub url: Url,
     pub branch_type: BranchType,
 }
 
 impl Branch {
-    pub fn new(sort_key: usize, url: Url, branch_type: BranchType) -> Self {
+    pub fn new(sort_key: usize, name: &str, url: Url, branch_type: BranchType) -> Self {
         Self {
             sort_key,
+            name: name.to_string(),
             url,
             branch_type,
         }
@@ -43,23 +221,417 @@ impl fmt::Display for Branch {
     }
 }
 
+/// Failed requests of a wrk run by category, `status` counts the non-2xx/3xx responses
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct WrkErrors {
+    pub connect: f32,
+    pub read: f32,
+    pub write: f32,
+    pub status: f32,
+    pub timeout: f32,
+}
+
+impl WrkErrors {
+    pub fn total(&self) -> f32 {
+        self.connect + self.read + self.write + self.status + self.timeout
+    }
+
+    /// Counts a failed request in its category
+    pub fn count(&mut self, error: RequestError) {
+        let counter = match error {
+            RequestError::Connect => &mut self.connect,
+            RequestError::Read => &mut self.read,
+            RequestError::Write => &mut self.write,
+            RequestError::Status => &mut self.status,
+            RequestError::Timeout => &mut self.timeout,
+        };
+        *counter += 1.0;
+    }
+}
+
+/// Category of a failed request made by the harness itself, named like the wrk errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    Connect,
+    Read,
+    Write,
+    /// A response with a 4xx or 5xx status
+    Status,
+    Timeout,
+}
+
+impl RequestError {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RequestError::Connect => "connect",
+            RequestError::Read => "read",
+            RequestError::Write => "write",
+            RequestError::Status => "status",
+            RequestError::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for WrkErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "connect {}, read {}, write {}, status {}, timeout {}",
+            self.connect, self.read, self.write, self.status, self.timeout
+        )
+    }
+}
+
+/// Number of responses with the http status
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StatusCount {
+    pub status: u16,
+    pub count: f32,
+}
+
+/// Percentiles of the latency distribution reported by the wrk script, scripts/as_json.lua
+/// reports the same percentiles
+pub const DISTRIBUTION_PERCENTILES: [f32; 16] = [
+    0.0, 1.0, 5.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0, 90.0, 95.0, 99.0, 99.9, 100.0,
+];
+
+/// Latency of a percentile of the requests in microseconds
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LatencyPercentile {
+    pub percentile: f32,
+    pub latency: f32,
+}
+
 #[derive(Deserialize, Debug, Getters, Clone)]
 pub struct WrkResult {
-    #[get = "pub(crate)"]
+    #[get = "pub"]
     duration: f32,
 
-    #[get = "pub(crate)"]
+    #[get = "pub"]
     requests: f32,
 
-    #[get = "pub(crate)"]
+    #[get = "pub"]
     latency_max: f32,
 
-    #[get = "pub(crate)"]
+    #[get = "pub"]
     latency_min: f32,
 
-    #[get = "pub(crate)"]
+    /// Mean latency, the p50 for a result built from a distribution, which has no mean
+    #[get = "pub"]
     latency_mean: f32,
 
-    #[get = "pub(crate)"]
+    #[get = "pub"]
     latency_stdev: f32,
+
+    #[serde(default)]
+    #[get = "pub"]
+    latency_p50: f32,
+
+    #[serde(default)]
+    #[get = "pub"]
+    latency_p99: f32,
+
+    #[serde(default)]
+    #[get = "pub"]
+    latency_distribution: Vec<LatencyPercentile>,
+
+    #[serde(default)]
+    #[get = "pub"]
+    errors: WrkErrors,
+
+    #[serde(default)]
+    #[get = "pub"]
+    statuses: Vec<StatusCount>,
+}
+
+impl WrkResult {
+    /// Summary of the latencies of the requests made during the duration, both in microseconds
+    pub fn from_latencies(duration: f32, latencies: &[f32]) -> Self {
+        let requests = latencies.len() as f32;
+        let latency_mean = latencies.iter().sum::<f32>() / requests.max(1.0);
+        let variance = latencies
+            .iter()
+            .map(|latency| (latency - latency_mean).powi(2))
+            .sum::<f32>()
+            / requests.max(1.0);
+        let mut sorted = latencies.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |percentile: f32| {
+            let rank = (percentile / 100.0 * requests).ceil() as usize;
+            sorted
+                .get(rank.clamp(1, sorted.len().max(1)) - 1)
+                .cloned()
+                .unwrap_or(0.0)
+        };
+
+        Self {
+            duration,
+            requests,
+            latency_max: latencies.iter().cloned().fold(0.0, f32::max),
+            latency_min: latencies.iter().cloned().reduce(f32::min).unwrap_or(0.0),
+            latency_mean,
+            latency_stdev: variance.sqrt(),
+            latency_p50: percentile(50.0),
+            latency_p99: percentile(99.0),
+            latency_distribution: DISTRIBUTION_PERCENTILES
+                .iter()
+                .map(|p| LatencyPercentile {
+                    percentile: *p,
+                    latency: percentile(*p),
+                })
+                .collect(),
+            errors: WrkErrors::default(),
+            statuses: Vec::new(),
+        }
+    }
+
+    /// Summary of a latency distribution, e.g. reported by wrk2, the durations are in microseconds
+    pub fn from_distribution(
+        duration: f32,
+        requests: f32,
+        latency_distribution: Vec<LatencyPercentile>,
+    ) -> Self {
+        // the latency of the nearest reported percentile above
+        let percentile = |percentile: f32| {
+            latency_distribution
+                .iter()
+                .find(|p| p.percentile >= percentile)
+                .or_else(|| latency_distribution.last())
+                .map_or(0.0, |p| p.latency)
+        };
+        let latency_p50 = percentile(50.0);
+
+        Self {
+            duration,
+            requests,
+            latency_max: percentile(100.0),
+            latency_min: latency_distribution.first().map_or(0.0, |p| p.latency),
+            latency_mean: latency_p50,
+            latency_stdev: 0.0,
+            latency_p50,
+            latency_p99: percentile(99.0),
+            latency_distribution,
+            errors: WrkErrors::default(),
+            statuses: Vec::new(),
+        }
+    }
+
+    pub fn with_errors(mut self, errors: WrkErrors, statuses: Vec<StatusCount>) -> Self {
+        self.errors = errors;
+        self.statuses = statuses;
+        self
+    }
+
+    /// Share of the failed requests, the requests count the responses with any status, the
+    /// other errors are failed attempts
+    pub fn error_rate(&self) -> f32 {
+        let attempts = self.requests + self.errors.total() - self.errors.status;
+        if attempts > 0.0 {
+            self.errors.total() / attempts
+        } else {
+            0.0
+        }
+    }
+
+    /// Status distribution sorted by status, e.g. `200: 950, 500: 50`
+    pub fn statuses_display(&self) -> String {
+        let mut statuses = self.statuses.clone();
+        statuses.sort_by_key(|s| s.status);
+        statuses
+            .iter()
+            .map(|s| format!("{}: {}", s.status, s.count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str, branch_type: BranchType) -> Target {
+        Target::new(
+            name,
+            format!("http://{}:8732", name).parse().unwrap(),
+            branch_type,
+        )
+    }
+
+    #[test]
+    fn target_from_str() {
+        let target: Target = "tezedge=http://127.0.0.1:18732".parse().unwrap();
+        assert_eq!(target.name, "tezedge");
+        assert_eq!(target.url.as_str(), "http://127.0.0.1:18732/");
+        assert_eq!(target.branch_type, BranchType::Feature);
+        assert_eq!(target.indexer, None);
+
+        let target: Target = "ocaml=http://ocaml:8732,role=ocaml,indexer=http://indexer:8080"
+            .parse()
+            .unwrap();
+        assert_eq!(target.name, "ocaml");
+        assert_eq!(target.branch_type, BranchType::Ocaml);
+        assert_eq!(
+            target.indexer.map(String::from),
+            Some("http://indexer:8080/".to_string())
+        );
+
+        let target: Target = "master=http://master:18732,role=Baseline".parse().unwrap();
+        assert_eq!(target.branch_type, BranchType::Stable);
+    }
+
+    #[test]
+    fn target_from_str_errors() {
+        for invalid in &[
+            "",
+            "http://127.0.0.1:18732",
+            "=http://127.0.0.1:18732",
+            "tezedge=not a url",
+            "tezedge=http://127.0.0.1:18732,role=unknown",
+            "tezedge=http://127.0.0.1:18732,colour=blue",
+            "tezedge=http://127.0.0.1:18732,indexer",
+        ] {
+            assert!(invalid.parse::<Target>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn reference_index_prefers_baseline_then_ocaml() {
+        let feature = target("feature", BranchType::Feature);
+        let ocaml = target("ocaml", BranchType::Ocaml);
+        let stable = target("stable", BranchType::Stable);
+
+        assert_eq!(
+            reference_index(&[feature.clone(), ocaml.clone(), stable.clone()]),
+            2
+        );
+        assert_eq!(
+            reference_index(&[feature.clone(), ocaml.clone(), feature.clone()]),
+            1
+        );
+        assert_eq!(reference_index(&[feature.clone(), feature.clone()]), 0);
+        assert_eq!(reference_index(&[stable.clone(), stable]), 0);
+        assert_eq!(reference_index(&[]), 0);
+    }
+
+    #[test]
+    fn from_latencies_summary() {
+        // 1..=100 microseconds, shuffled
+        let latencies = (1..=100)
+            .map(|latency| ((latency * 37) % 100 + 1) as f32)
+            .collect::<Vec<_>>();
+        let result = WrkResult::from_latencies(2_000_000.0, &latencies);
+
+        assert_eq!(*result.duration(), 2_000_000.0);
+        assert_eq!(*result.requests(), 100.0);
+        assert_eq!(*result.latency_min(), 1.0);
+        assert_eq!(*result.latency_max(), 100.0);
+        assert_eq!(*result.latency_mean(), 50.5);
+        assert!((result.latency_stdev() - 28.866).abs() < 0.001);
+        assert_eq!(*result.latency_p50(), 50.0);
+        assert_eq!(*result.latency_p99(), 99.0);
+
+        let distribution = result.latency_distribution();
+        assert_eq!(distribution.len(), DISTRIBUTION_PERCENTILES.len());
+        assert_eq!(distribution[0].latency, 1.0);
+        assert_eq!(
+            distribution[DISTRIBUTION_PERCENTILES.len() - 1].latency,
+            100.0
+        );
+        assert!(distribution
+            .windows(2)
+            .all(|pair| pair[0].latency <= pair[1].latency));
+    }
+
+    #[test]
+    fn from_latencies_without_requests() {
+        let result = WrkResult::from_latencies(1_000_000.0, &[]);
+        assert_eq!(*result.requests(), 0.0);
+        assert_eq!(*result.latency_max(), 0.0);
+        assert_eq!(*result.latency_mean(), 0.0);
+        assert_eq!(*result.latency_p99(), 0.0);
+    }
+
+    #[test]
+    fn error_rate_of_the_attempts() {
+        let latencies = vec![1000.0; 95];
+        let result = WrkResult::from_latencies(1_000_000.0, &latencies);
+        assert_eq!(result.error_rate(), 0.0);
+
+        // the 5 responses with an error status are among the requests, the timeouts are not
+        let errors = WrkErrors {
+            status: 5.0,
+            timeout: 5.0,
+            ..WrkErrors::default()
+        };
+        let statuses = vec![
+            StatusCount {
+                status: 500,
+                count: 5.0,
+            },
+            StatusCount {
+                status: 200,
+                count: 90.0,
+            },
+        ];
+        let result = result.with_errors(errors, statuses);
+        assert_eq!(result.errors().total(), 10.0);
+        assert!((result.error_rate() - 0.1).abs() < f32::EPSILON);
+        assert_eq!(result.statuses_display(), "200: 90, 500: 5");
+
+        // a node refusing every connection
+        let errors = WrkErrors {
+            connect: 20.0,
+            ..WrkErrors::default()
+        };
+        let result = WrkResult::from_latencies(1_000_000.0, &[]).with_errors(errors, Vec::new());
+        assert_eq!(result.error_rate(), 1.0);
+        assert_eq!(
+            WrkResult::from_latencies(1_000_000.0, &[]).error_rate(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn script_args_of_the_request() {
+        assert_eq!(
+            RpcRequest::get("chains/main/blocks/head", 1).script_args(),
+            vec!["GET".to_string(), String::new()]
+        );
+
+        let request = RpcRequest {
+            method: "POST".to_string(),
+            path: "injection/operation".to_string(),
+            headers: vec![
+                ("X-Trace".to_string(), "a: b".to_string()),
+                ("Accept".to_string(), "*/*".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            body: Some(serde_json::json!({"text": "say \"hi\"\n", "n": 1})),
+            weight: 1,
+        };
+        // the body is a single arg of compact json, the header value keeps its colon
+        assert_eq!(
+            request.script_args(),
+            vec![
+                "POST",
+                r#"{"n":1,"text":"say \"hi\"\n"}"#,
+                "Content-Type: application/json",
+                "Accept: */*",
+                "X-Trace: a: b",
+            ]
+        );
+
+        let request = RpcRequest {
+            headers: vec![("content-type".to_string(), "text/plain".to_string())]
+                .into_iter()
+                .collect(),
+            body: Some(serde_json::json!("raw 'text' $HOME")),
+            ..request
+        };
+        assert_eq!(
+            request.script_args(),
+            vec!["POST", "raw 'text' $HOME", "content-type: text/plain"]
+        );
+    }
 }
@@ -1,12 +1,28 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::fmt;
 use std::fs::File;
+use std::future::Future;
 use std::io::{BufRead, BufReader};
+use std::process::Output;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use failure::{bail, format_err};
+use tokio::process::Command;
+use tokio::sync::watch;
+use url::Url;
+
+use crate::types::RpcRequest;
+
+/// Timeout of every rpc request made by the harness, wrk uses the same timeout
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 fn lines(read: impl std::io::Read) -> std::io::Result<Vec<String>> {
-    BufReader::new(read).lines().filter_map(|r| {
-        match r {
+    BufReader::new(read)
+        .lines()
+        .filter_map(|r| match r {
             Err(_) => Some(r),
             Ok(line) => {
                 let line = line.trim();
@@ -16,14 +32,253 @@ fn lines(read: impl std::io::Read) -> std::io::Result<Vec<String>> {
                     Some(Ok(line.trim_start_matches("/").to_string()))
                 }
             }
-        }
-    }).collect::<Result<_, _>>()
+        })
+        .collect::<Result<_, _>>()
 }
 
-pub(crate) fn get_urls(file: &str) -> Result<Vec<String>, failure::Error> {
-    if file == "-" {
-        Ok(lines(std::io::stdin())?)
+/// Requests of the url-file, see [`RpcRequest`] for the line formats
+pub fn get_requests(file: &str) -> Result<Vec<RpcRequest>, failure::Error> {
+    let lines = if file == "-" {
+        lines(std::io::stdin())?
     } else {
-        Ok(lines(File::open(file)?)?)
+        lines(File::open(file)?)?
+    };
+
+    lines
+        .iter()
+        .map(|line| {
+            let mut request = if line.starts_with('{') {
+                serde_json::from_str::<RpcRequest>(line)
+                    .map_err(|e| format_err!("Invalid request {:?}: {}", line, e))?
+            } else {
+                let mut parts = line.split_whitespace();
+                let path = parts.next().unwrap_or("");
+                let weight = match parts.next() {
+                    Some(weight) => weight
+                        .parse()
+                        .map_err(|e| format_err!("Invalid weight in {:?}: {}", line, e))?,
+                    None => 1,
+                };
+                RpcRequest::get(path, weight)
+            };
+            request.method = request.method.to_ascii_uppercase();
+            request.path = request.path.trim_start_matches('/').to_string();
+            reqwest::Method::from_bytes(request.method.as_bytes())
+                .map_err(|e| format_err!("Invalid method in {:?}: {}", line, e))?;
+            if request.weight == 0 {
+                bail!("Invalid weight in {:?}: the weight must be positive", line);
+            }
+            // the headers are passed to wrk as `Name: value` args and have to stay a single line
+            for (name, value) in &request.headers {
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format_err!("Invalid header in {:?}: {}", line, e))?;
+                reqwest::header::HeaderValue::from_str(value)
+                    .map_err(|e| format_err!("Invalid header in {:?}: {}", line, e))?;
+            }
+            Ok(request)
+        })
+        .collect()
+}
+
+/// Builds the request of the url-file for the node
+pub fn rpc_request(
+    client: &reqwest::Client,
+    node: &Url,
+    request: &RpcRequest,
+) -> reqwest::RequestBuilder {
+    // the method has been validated by get_requests
+    let method = reqwest::Method::from_bytes(request.method.as_bytes()).unwrap_or_default();
+    let mut builder = client.request(method, format!("{}{}", node, request.path));
+    for (name, value) in &request.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    if let Some(body) = request.body_text() {
+        if !request.has_header("Content-Type") {
+            builder = builder.header("Content-Type", "application/json");
+        }
+        builder = builder.body(body);
+    }
+    builder
+}
+
+/// Http client shared by the requests of a test, enforcing the request timeout
+pub fn http_client() -> Result<reqwest::Client, failure::Error> {
+    Ok(reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?)
+}
+
+/// Handle of the runtime the blocking load runners are spawned on
+pub fn runtime() -> Result<tokio::runtime::Handle, failure::Error> {
+    tokio::runtime::Handle::try_current()
+        .map_err(|e| format_err!("The load runners must run within a tokio runtime: {}", e))
+}
+
+/// Error returned when the test has been interrupted by Ctrl-C
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Interrupted by Ctrl-C")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Set by the process-wide Ctrl-C handler, a second Ctrl-C exits immediately
+static INTERRUPT: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn interrupt_receiver() -> watch::Receiver<bool> {
+    INTERRUPT
+        .get_or_init(|| {
+            // the handler replaces the default one for the rest of the process, so every later
+            // test, also the blocking ones, is stopped by the flag
+            tokio::spawn(async {
+                let mut presses = 0;
+                while tokio::signal::ctrl_c().await.is_ok() {
+                    presses += 1;
+                    if presses > 1 {
+                        std::process::exit(130);
+                    }
+                    if let Some(interrupt) = INTERRUPT.get() {
+                        interrupt.send_replace(true);
+                    }
+                }
+            });
+            watch::channel(false).0
+        })
+        .subscribe()
+}
+
+/// Whether Ctrl-C has been pressed since the first interruptible test started
+pub fn interrupted() -> bool {
+    INTERRUPT.get().is_some_and(|interrupt| *interrupt.borrow())
+}
+
+/// Runs the future until it completes or Ctrl-C is pressed, dropping the future cancels the test
+pub async fn interruptible<T>(
+    future: impl Future<Output = Result<T, failure::Error>>,
+) -> Result<T, failure::Error> {
+    let mut interrupt = interrupt_receiver();
+    tokio::select! {
+        result = future => result,
+        _ = interrupt.wait_for(|interrupted| *interrupted) => Err(Interrupted.into()),
+    }
+}
+
+/// Runs the command to completion from the blocking load runners, the command is killed when
+/// Ctrl-C is pressed
+pub fn command_output(command: &mut Command) -> Result<Output, failure::Error> {
+    runtime()?.block_on(interruptible(async {
+        Ok(command.kill_on_drop(true).output().await?)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Requests of the url-file with the content, the name has to be unique among the tests
+    fn requests(name: &str, content: &str) -> Result<Vec<RpcRequest>, failure::Error> {
+        let file = std::env::temp_dir().join(format!(
+            "tezos-node-bootstrap-{}-{}.urls",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&file, content).unwrap();
+        let requests = get_requests(&file.to_string_lossy());
+        std::fs::remove_file(&file).unwrap();
+        requests
+    }
+
+    #[test]
+    fn plain_and_json_lines() {
+        let requests = requests(
+            "lines",
+            "# comment\n\
+             /chains/main/blocks/head\n\
+             \n\
+             chains/main/blocks/head/header 3\n\
+             {\"method\": \"post\", \"path\": \"/injection/operation\", \"body\": \"abc\", \
+             \"headers\": {\"X-Trace\": \"a:b\"}, \"weight\": 2}\n\
+             {\"path\": \"chains/main/blocks/head/helpers/scripts/run_operation\", \
+             \"body\": {\"chain_id\": \"NetXdQprcVkpaWU\"}}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            requests,
+            vec![
+                RpcRequest::get("chains/main/blocks/head", 1),
+                RpcRequest::get("chains/main/blocks/head/header", 3),
+                RpcRequest {
+                    method: "POST".to_string(),
+                    path: "injection/operation".to_string(),
+                    headers: vec![("X-Trace".to_string(), "a:b".to_string())]
+                        .into_iter()
+                        .collect(),
+                    body: Some(json!("abc")),
+                    weight: 2,
+                },
+                RpcRequest {
+                    method: "GET".to_string(),
+                    path: "chains/main/blocks/head/helpers/scripts/run_operation".to_string(),
+                    headers: BTreeMap::new(),
+                    body: Some(json!({"chain_id": "NetXdQprcVkpaWU"})),
+                    weight: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let error = |name, content| requests(name, content).unwrap_err().to_string();
+
+        assert!(error("method", r#"{"method": "G T", "path": "a"}"#)
+            .starts_with(r#"Invalid method in "{\"method\": \"G T\", \"path\": \"a\"}": "#));
+        assert_eq!(
+            error("zero", "a 0"),
+            r#"Invalid weight in "a 0": the weight must be positive"#
+        );
+        assert_eq!(
+            error("json-zero", r#"{"path": "a", "weight": 0}"#),
+            r#"Invalid weight in "{\"path\": \"a\", \"weight\": 0}": the weight must be positive"#
+        );
+        assert!(error("negative", "a -1").starts_with(r#"Invalid weight in "a -1": "#));
+        assert!(error("json-negative", r#"{"path": "a", "weight": -1}"#)
+            .starts_with(r#"Invalid request "{\"path\": \"a\", \"weight\": -1}": "#));
+        assert!(error("no-path", r#"{"method": "GET"}"#).contains("missing field `path`"));
+        assert!(error(
+            "header",
+            r#"{"path": "a", "headers": {"X-Trace": "a\r\nHost: b"}}"#
+        )
+        .starts_with("Invalid header in "));
+        assert!(error(
+            "header-name",
+            r#"{"path": "a", "headers": {"X:Trace": "a"}}"#
+        )
+        .starts_with("Invalid header in "));
+        assert!(get_requests("/nonexistent/tezos-node-bootstrap.urls").is_err());
+    }
+
+    #[tokio::test]
+    async fn interruptible_stops_on_the_interrupt() {
+        assert_eq!(interruptible(async { Ok(1) }).await.unwrap(), 1);
+        assert!(!interrupted());
+
+        let pending = interruptible(futures::future::pending::<Result<(), failure::Error>>());
+        INTERRUPT.get().unwrap().send_replace(true);
+        let error = pending.await.unwrap_err();
+        assert_eq!(error.downcast_ref::<Interrupted>(), Some(&Interrupted));
+        assert!(interrupted());
+
+        // the flag is process-wide, the other tests must not see it
+        INTERRUPT.get().unwrap().send_replace(false);
     }
 }
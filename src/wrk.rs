@@ -2,29 +2,51 @@
 // SPDX-License-Identifier: MIT
 
 use std::collections::HashMap;
-use std::process::Command;
+use std::time::{Duration, Instant};
 
-use failure::bail;
+use failure::{bail, format_err};
 use itertools::Itertools;
-
-use crate::configuration::RpcPerformanceTestEnv;
-use crate::types::{Branch, BranchType, WrkResult};
-
-type WrkResultMap = HashMap<Branch, WrkResult>;
-
-fn run_wrk(branch: &Branch, rpc: &str, duration: &u64) -> Result<WrkResult, failure::Error> {
-    let url = format!("{}{}", branch.url, rpc);
+use tokio::process::Command;
+
+use crate::configuration::{Gates, RpcPerformanceTestEnv};
+use crate::html_report;
+use crate::influx;
+use crate::metrics;
+use crate::sampling::{check_samples, pin_head, sampling_reference, Sampling};
+use crate::schedule::{measure_branches, BranchScheduler};
+use crate::summary::Summary;
+use crate::types::{reference_index, Branch, BranchType, RpcRequest, WrkResult};
+use crate::utils::{command_output, http_client, rpc_request, runtime};
+
+pub type WrkResultMap = HashMap<Branch, WrkResult>;
+
+/// Runs wrk against a single rpc of the branch on the number of connections and parses its
+/// json summary, the responses are sampled by the wrk script with the sampling and their
+/// statuses counted when asked for
+pub fn run_wrk(
+    branch: &Branch,
+    rpc: &RpcRequest,
+    duration: &u64,
+    connections: usize,
+    sampling: Option<&Sampling>,
+    count_statuses: bool,
+) -> Result<WrkResult, failure::Error> {
+    let url = format!("{}{}", branch.url, rpc.path);
     println!("URL: {}", url);
 
     // local testing
     // let master_url = format!("{}/{}", "http://116.202.128.230:28732", rpc);
     // let modified_url = format!("{}/{}", "http://116.202.128.230:18732", rpc);
     // let ocaml_url = format!("{}/{}", "http://116.202.128.230:10000", rpc);
-    let duration_string = &format!("-d{}s", duration.clone());
+    let duration_string = &format!("-d{}s", duration);
+    // wrk needs at least one connection per thread
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads_string = &format!("-t{}", threads.min(connections));
+    let connections_string = &format!("-c{}", connections);
 
     let mut wrk_args = vec![
-        "-t1",
-        "-c1",
+        threads_string,
+        connections_string,
         duration_string,
         //"-R1000",
         "--timeout",
@@ -32,21 +54,22 @@ fn run_wrk(branch: &Branch, rpc: &str, duration: &u64) -> Result<WrkResult, fail
         "-s",
         "/scripts/as_json.lua",
         "--",
-        "out.json",
     ];
     println!(
-        "Running wrk for {:?} with arguments: {:?}",
-        branch.url.domain().unwrap_or(""),
-        &wrk_args
+        "Running wrk for {} with arguments: {:?}",
+        branch.name, &wrk_args
     );
     println!();
 
     wrk_args.insert(5, &url);
+    let script_args = script_args(rpc, sampling);
+    wrk_args.extend(script_args.iter().map(String::as_str));
 
-    let output = Command::new("wrk")
-        .args(&wrk_args)
-        .current_dir("/")
-        .output()?;
+    let output = command_output(&mut wrk_command(
+        "wrk",
+        &wrk_args,
+        count_statuses || sampling.is_some(),
+    ))?;
 
     if !output.status.success() {
         println!("{:?}", output);
@@ -60,141 +83,335 @@ fn run_wrk(branch: &Branch, rpc: &str, duration: &u64) -> Result<WrkResult, fail
     let json_out = out
         .lines()
         .last()
-        .unwrap()
-        .to_string();
+        .ok_or_else(|| format_err!("[{}] Empty wrk output", branch.name))?;
 
-    let ret: WrkResult = serde_json::from_str(&json_out).expect("JSON was not well-formated");
-    Ok(ret)
+    serde_json::from_str(json_out)
+        .map_err(|e| format_err!("[{}] Invalid wrk output {}: {}", branch.name, json_out, e))
 }
 
-pub(crate) fn test_rpc_performance(env: RpcPerformanceTestEnv) -> Result<(), failure::Error> {
-    let RpcPerformanceTestEnv {
-        tezedge_new_node,
-        tezedge_old_node,
-        ocaml_node,
-        url_file,
-        wrk_test_duration,
-        max_latency_threshold,
-        throughput_threshold,
-        latency_no_fail,
-        throughput_no_fail,
-    } = env;
+/// Command of the wrk binary, the scripts/as_json.lua script gets the responses only when they
+/// are asked for, since the response callback slows wrk down
+pub fn wrk_command(program: &str, args: &[&str], responses: bool) -> Command {
+    let mut command = Command::new(program);
+    command.args(args).current_dir("/");
+    if responses {
+        command.env("AS_JSON_RESPONSES", "1");
+    }
+    command
+}
 
-    for rpc in super::utils::get_urls(&url_file)? {
-        let ocaml = Branch::new(0, ocaml_node.clone(), BranchType::Ocaml);
-        let tezedge_new = Branch::new(1, tezedge_new_node.clone(), BranchType::Feature);
-        let tezedge_old = tezedge_old_node
-            .as_ref()
-            .map(|b| Branch::new(2, b.clone(), BranchType::Stable));
+/// Args of the scripts/as_json.lua wrk script: the sampling and the request
+pub fn script_args(rpc: &RpcRequest, sampling: Option<&Sampling>) -> Vec<String> {
+    let mut args = match sampling {
+        Some(sampling) => sampling.script_args(),
+        None => vec!["/dev/null".to_string(), "0".to_string()],
+    };
+    args.extend(rpc.script_args());
+    args
+}
 
-        println!("Running wrk for rpc: {}", rpc);
-        println!();
-        let mut outputs: WrkResultMap = HashMap::new();
+/// Duration of a single request of the rpc in milliseconds, measured before wrk warms up the node
+pub fn cold_request(branch: &Branch, rpc: &RpcRequest) -> Result<f32, failure::Error> {
+    // the load runners run on the blocking thread pool of the runtime
+    runtime()?.block_on(async {
+        let req_start = Instant::now();
+        rpc_request(&http_client()?, &branch.url, rpc)
+            .send()
+            .await?
+            .bytes()
+            .await?;
+        Ok(req_start.elapsed().as_secs_f32() * 1000.0)
+    })
+}
 
-        if let Some(tezedge_old) = &tezedge_old {
-            std::thread::sleep(std::time::Duration::from_secs(1));
+/// Measures the branch warmed up by the warm run of the warm-up seconds, after a cold request
+/// when asked for, returns the duration of the cold request in milliseconds and the measurement
+fn measure_warm<T>(
+    branch: &Branch,
+    rpc: &RpcRequest,
+    cold_warm: bool,
+    warm_up: u64,
+    warm: impl FnOnce(&u64) -> Result<(), failure::Error>,
+    measure: impl FnOnce() -> Result<T, failure::Error>,
+) -> Result<(Option<f32>, T), failure::Error> {
+    let cold = if cold_warm {
+        Some(cold_request(branch, rpc)?)
+    } else {
+        None
+    };
+    if warm_up > 0 {
+        println!("Warming up {} for {}s", branch.name, warm_up);
+        warm(&warm_up)?;
+    }
+    Ok((cold, measure()?))
+}
 
-            outputs.insert(
-                tezedge_old.clone(),
-                run_wrk(&tezedge_old, &rpc, &wrk_test_duration)?,
+fn display_cold_requests(cold: &[(Branch, f32)], reference: &Branch) {
+    let reference_cold = cold
+        .iter()
+        .find(|(branch, _)| branch == reference)
+        .map_or(0.0, |(_, ms)| *ms);
+    println!("Cold requests before the warm-up:");
+    for (branch, ms) in cold {
+        if branch == reference {
+            println!("\t {}: {}ms", branch.name, ms);
+        } else {
+            println!(
+                "\t {}: {}ms, {} - {}: {}ms",
+                branch.name,
+                ms,
+                branch.name,
+                reference.name,
+                ms - reference_cold
             );
         }
+    }
+    println!();
+}
 
-        std::thread::sleep(std::time::Duration::from_secs(1));
-
-        outputs.insert(ocaml.clone(), run_wrk(&ocaml, &rpc, &wrk_test_duration)?);
+pub fn test_rpc_performance(env: RpcPerformanceTestEnv) -> Result<(), failure::Error> {
+    let RpcPerformanceTestEnv {
+        targets,
+        url_file,
+        wrk_test_duration,
+        gates,
+        count_statuses,
+        sample_rate,
+        warm_up,
+        cold_warm,
+        branch_order,
+        seed,
+        concurrent_branches,
+        markdown,
+        ..
+    } = env;
 
-        std::thread::sleep(std::time::Duration::from_secs(1));
+    let branches = targets
+        .iter()
+        .enumerate()
+        .map(|(sort_key, target)| target.branch(sort_key))
+        .collect_vec();
+    let reference = branches[reference_index(&targets)].clone();
+    let sampling_reference = sampling_reference(&branches, &reference).clone();
+    let mut scheduler = BranchScheduler::new(branch_order, seed);
+    let mut summary = Summary::default();
+
+    // the summary is also displayed, when a regression stops the test
+    let result = (|| {
+        for rpc in super::utils::get_requests(&url_file)? {
+            println!("Running wrk for rpc: {}", rpc);
+            let ordered = scheduler.next_order(&branches);
+            let measured_rpc = if sample_rate > 0.0 {
+                pin_head(&rpc, &sampling_reference)?
+            } else {
+                rpc.clone()
+            };
+            println!();
+            let mut outputs: WrkResultMap = HashMap::new();
+            let mut samples = Vec::new();
+            let mut cold = Vec::new();
+
+            let measured = measure_branches(&ordered, concurrent_branches, |branch| {
+                let warm = |warm_up: &u64| {
+                    run_wrk(branch, &measured_rpc, warm_up, 1, None, false).map(|_| ())
+                };
+                measure_warm(branch, &measured_rpc, cold_warm, warm_up, warm, || {
+                    let sampling = Some(Sampling::new(sample_rate, branch))
+                        .filter(|_| sample_rate > 0.0 && *branch != sampling_reference);
+                    let result = run_wrk(
+                        branch,
+                        &measured_rpc,
+                        &wrk_test_duration,
+                        1,
+                        sampling.as_ref(),
+                        count_statuses,
+                    )?;
+                    let sampled = sampling.map(|s| s.take_samples()).transpose()?;
+                    Ok((result, sampled))
+                })
+            })?;
+
+            for (branch, (cold_ms, (result, sampled))) in measured
+                .into_iter()
+                .sorted_by_key(|(branch, _)| branch.sort_key)
+            {
+                if let Some(ms) = cold_ms {
+                    cold.push((branch.clone(), ms));
+                }
+                if let Some(sampled) = sampled {
+                    samples.push((branch.clone(), sampled));
+                }
+                metrics::observe_wrk_result(&branch.name, &result);
+                outputs.insert(branch, result);
+            }
+            let mut divergences = Vec::new();
+            if sample_rate > 0.0 {
+                divergences = check_samples(&measured_rpc, &sampling_reference, &samples)?;
+            }
 
-        outputs.insert(
-            tezedge_new.clone(),
-            run_wrk(&tezedge_new, &rpc, &wrk_test_duration)?,
-        );
+            if cold_warm {
+                display_cold_requests(&cold, &reference);
+                println!("Warm results:");
+            }
+            let mut regressions = calculate_and_display_statistics(&outputs, &reference, &gates);
+            regressions.extend(divergences);
+            let name = rpc.to_string();
+            summary.add(&name, &outputs, &reference, &regressions);
+            summary.add_cold(&name, &cold);
+            summary.add_order(&name, &ordered);
+            html_report::record_rpc(&name, &outputs);
+            html_report::record_cold(&name, &cold);
+            html_report::record_order(&name, &ordered);
+            influx::emit_wrk_results("performance", &name, &outputs);
+            influx::emit_cold_requests(&name, &cold);
+            for (branch, ms) in &cold {
+                let duration = Duration::from_secs_f32(ms * 0.001);
+                metrics::set_cold_request(&branch.name, &name, duration);
+            }
+            check_regressions(&regressions)?;
+        }
+        Ok(())
+    })();
 
-        calculate_and_display_statistics(&outputs, max_latency_threshold, throughput_threshold, latency_no_fail, throughput_no_fail);
+    summary.display();
+    if let Some(markdown) = markdown {
+        summary.write_markdown(&markdown)?;
     }
-
-    Ok(())
+    result
 }
 
-fn calculate_and_display_statistics(wrk_results: &WrkResultMap, max_latency_threshold: f32, throughput_threshold: f32, latency_no_fail: bool, throughput_no_fail: bool) {
-    for (res_key, res_val) in wrk_results {
+/// Displays the results of a single rpc, compares them to the reference branch and returns
+/// the regressions
+pub fn calculate_and_display_statistics(
+    wrk_results: &WrkResultMap,
+    reference: &Branch,
+    gates: &Gates,
+) -> Vec<Regression> {
+    for res_key in wrk_results.keys().sorted_by_key(|k| k.sort_key) {
+        let res_val = &wrk_results[res_key];
         println!(
-            "{:?} thoughtput: {}req/s",
-            res_key.url.domain().unwrap_or(""),
+            "{} thoughtput: {}req/s",
+            res_key.name,
             calc_throughput(res_val.requests(), res_val.duration())
         );
         println!(
-            "{:?} max latency: {}ms",
-            res_key.url.domain().unwrap_or(""),
+            "{} max latency: {}ms",
+            res_key.name,
             calc_max_latency(res_val.latency_max())
         );
+        println!(
+            "{} errors: {} ({:.2}%)",
+            res_key.name,
+            res_val.errors(),
+            res_val.error_rate() * 100.0
+        );
+        if !res_val.statuses().is_empty() {
+            println!("{} statuses: {}", res_key.name, res_val.statuses_display());
+        }
         println!();
     }
-    calc_deltas(wrk_results, max_latency_threshold, throughput_threshold, latency_no_fail, throughput_no_fail);
+    let regressions = calc_deltas(wrk_results, reference, gates);
     println!("------------------------------------------------------");
     println!();
+    regressions
 }
 
-fn calc_throughput(req: &f32, dur: &f32) -> f32 {
+pub fn calc_throughput(req: &f32, dur: &f32) -> f32 {
     req / (dur * 0.000001)
 }
 
-fn calc_max_latency(lat: &f32) -> f32 {
+pub fn calc_max_latency(lat: &f32) -> f32 {
     lat * 0.001
 }
 
-fn calc_deltas(wrk_results: &WrkResultMap, max_latency_threshold: f32, throughput_threshold: f32, latency_no_fail: bool, throughput_no_fail: bool) {
-    let keys = wrk_results
-        .keys()
-        .into_iter()
-        .sorted_by_key(|k| k.sort_key)
-        .collect_vec();
-
-    println!("Deltas compared to ocaml node: ");
-    for combo in keys.clone().into_iter().combinations(2) {
-        let left = combo[0];
-        let right = combo[1];
+/// Latency in milliseconds of a latency of wrk in microseconds
+pub fn us_to_ms(latency: &f32) -> f32 {
+    latency * 0.001
+}
 
-        let left_result = wrk_results.get(left).unwrap();
-        let right_result = wrk_results.get(right).unwrap();
+/// Regression of a feature branch beyond a threshold, fatal unless its gate has been
+/// disabled by the no-fail flag
+#[derive(Debug, Clone)]
+pub struct Regression {
+    pub branch: Branch,
+    pub message: String,
+    pub fatal: bool,
+}
 
-        let left_node = left.url.domain().unwrap_or("");
-        let right_node = right.url.domain().unwrap_or("");
+/// Fails with the first fatal regression
+pub fn check_regressions(regressions: &[Regression]) -> Result<(), failure::Error> {
+    match regressions.iter().find(|r| r.fatal) {
+        Some(regression) => bail!("{}", regression.message),
+        None => Ok(()),
+    }
+}
 
-        let left_max_latency = left_result.latency_max();
-        let right_max_latency = right_result.latency_max();
+/// Displays the deltas to the reference branch and collects the regressions of the feature
+/// branches compared to a stable reference by more than the thresholds
+pub fn calc_deltas(
+    wrk_results: &WrkResultMap,
+    reference: &Branch,
+    gates: &Gates,
+) -> Vec<Regression> {
+    let reference_result = &wrk_results[reference];
+    let others = wrk_results
+        .keys()
+        .filter(|k| *k != reference)
+        .sorted_by_key(|k| k.sort_key)
+        .collect_vec();
 
-        let left_max_throughput = calc_throughput(left_result.requests(), left_result.duration());
-        let right_max_throughput =
-            calc_throughput(right_result.requests(), right_result.duration());
+    println!("Deltas compared to {} node: ", reference.name);
+    for other in &others {
+        let other_result = &wrk_results[*other];
 
-        let delta_latency = (left_max_latency - right_max_latency) * 0.001;
-        let delta_throughput = left_max_throughput - right_max_throughput;
+        let delta_latency = (other_result.latency_max() - reference_result.latency_max()) * 0.001;
+        let delta_throughput = calc_throughput(other_result.requests(), other_result.duration())
+            - calc_throughput(reference_result.requests(), reference_result.duration());
 
-        println!("\t {} - {}: {}ms", left_node, right_node, delta_latency);
+        println!(
+            "\t {} - {}: {}ms",
+            other.name, reference.name, delta_latency
+        );
         println!(
             "\t {} - {}: {}req/s",
-            left_node, right_node, delta_throughput
+            other.name, reference.name, delta_throughput
+        );
+        println!(
+            "\t {} - {}: {:+.2}% errors",
+            other.name,
+            reference.name,
+            (other_result.error_rate() - reference_result.error_rate()) * 100.0
         );
         println!();
     }
 
-    // only compare, when stable is present
-    if let Some(stable_key) = keys
-        .clone()
+    // only gate the feature branches, when the reference is a stable build
+    if reference.branch_type != BranchType::Stable {
+        return Vec::new();
+    }
+
+    let Gates {
+        max_latency_threshold,
+        throughput_threshold,
+        error_threshold,
+        latency_no_fail,
+        throughput_no_fail,
+        errors_no_fail,
+    } = *gates;
+    let mut regressions = Vec::new();
+    for new_key in others
         .into_iter()
-        .filter(|key| key.branch_type == BranchType::Stable)
-        .last()
+        .filter(|key| key.branch_type == BranchType::Feature)
     {
-        let new_key = keys
-            .into_iter()
-            .filter(|key| key.branch_type == BranchType::Feature)
-            .last()
-            .unwrap();
-
-        let stable = wrk_results.get(&stable_key).unwrap();
-        let new = wrk_results.get(&new_key).unwrap();
+        let stable = reference_result;
+        let new = &wrk_results[new_key];
+        let mut regression = |message: String, no_fail: bool| {
+            regressions.push(Regression {
+                branch: new_key.clone(),
+                message,
+                fatal: !no_fail,
+            })
+        };
 
         // TODO: remove this after the high variance of the max_latency has been reduced
         const MINIMAL_STABLE_LATENCY_TO_CHECK: f32 = 50.0;
@@ -205,35 +422,180 @@ fn calc_deltas(wrk_results: &WrkResultMap, max_latency_threshold: f32, throughpu
             if stable.latency_max() * max_latency_threshold
                 < new.latency_max() - stable.latency_max()
             {
-                if latency_no_fail {
-                    println!(
-                        "[Max Latency] Perforamnce regression greater than {}%!",
-                        max_latency_threshold * 100.0
-                    )
-                } else {
-                    panic!(
-                        "[Max Latency] Perforamnce regression greater than {}%!",
+                regression(
+                    format!(
+                        "[Max Latency] [{}] Perforamnce regression greater than {}%!",
+                        new_key.name,
                         max_latency_threshold * 100.0
-                    )
-                }
+                    ),
+                    latency_no_fail,
+                );
             }
         }
 
+        // a node failing fast must not look like a throughput improvement
+        if new.error_rate() - stable.error_rate() > error_threshold {
+            regression(
+                format!(
+                    "[Errors] [{}] Error rate {:.2}% greater than {:.2}% by more than {} points!",
+                    new_key.name,
+                    new.error_rate() * 100.0,
+                    stable.error_rate() * 100.0,
+                    error_threshold * 100.0
+                ),
+                errors_no_fail,
+            );
+        }
+
         if new.requests() < stable.requests() {
             // fail the test if the 10% performance happened
             if stable.requests() * throughput_threshold < stable.requests() - new.requests() {
-                if throughput_no_fail {
-                    println!(
-                        "[Troughput] Performance regression greater than {}%!",
-                        throughput_threshold * 100.0
-                    )
-                } else {
-                    panic!(
-                        "[Troughput] Performance regression greater than {}%!",
+                regression(
+                    format!(
+                        "[Troughput] [{}] Performance regression greater than {}%!",
+                        new_key.name,
                         throughput_threshold * 100.0
-                    )
-                }
+                    ),
+                    throughput_no_fail,
+                );
             }
         }
     }
+
+    for regression in regressions.iter().filter(|r| !r.fatal) {
+        println!("{}", regression.message);
+    }
+    regressions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::sampling::tests::node_stub;
+    use crate::types::WrkErrors;
+
+    fn branch(sort_key: usize, branch_type: BranchType) -> Branch {
+        let name = format!("{:?}", branch_type).to_lowercase();
+        let url = format!("http://{}:8732/", name).parse().unwrap();
+        Branch::new(sort_key, &name, url, branch_type)
+    }
+
+    fn result(timeouts: f32) -> WrkResult {
+        let errors = WrkErrors {
+            timeout: timeouts,
+            ..WrkErrors::default()
+        };
+        WrkResult::from_latencies(1_000_000.0, &[1000.0; 90]).with_errors(errors, Vec::new())
+    }
+
+    fn gates(error_threshold: f32, errors_no_fail: bool) -> Gates {
+        Gates {
+            max_latency_threshold: 0.1,
+            throughput_threshold: 0.1,
+            error_threshold,
+            latency_no_fail: false,
+            throughput_no_fail: false,
+            errors_no_fail,
+        }
+    }
+
+    #[test]
+    fn error_rate_regression_of_the_feature_branch() {
+        let stable = branch(0, BranchType::Stable);
+        let feature = branch(1, BranchType::Feature);
+        let results: WrkResultMap = vec![(stable.clone(), result(0.0)), (feature, result(10.0))]
+            .into_iter()
+            .collect();
+
+        // 10% of the requests failed, the same throughput
+        let regressions = calc_deltas(&results, &stable, &gates(0.05, false));
+        assert_eq!(regressions.len(), 1);
+        assert!(regressions[0].message.starts_with("[Errors] [feature]"));
+        assert!(regressions[0].fatal);
+        assert!(check_regressions(&regressions).is_err());
+
+        let regressions = calc_deltas(&results, &stable, &gates(0.05, true));
+        assert!(!regressions[0].fatal);
+        assert!(check_regressions(&regressions).is_ok());
+
+        assert!(calc_deltas(&results, &stable, &gates(0.2, false)).is_empty());
+    }
+
+    #[test]
+    fn only_a_stable_reference_gates() {
+        let ocaml = branch(0, BranchType::Ocaml);
+        let feature = branch(1, BranchType::Feature);
+        let results: WrkResultMap = vec![(ocaml.clone(), result(0.0)), (feature, result(10.0))]
+            .into_iter()
+            .collect();
+
+        assert!(calc_deltas(&results, &ocaml, &gates(0.0, false)).is_empty());
+    }
+
+    #[test]
+    fn cold_request_before_the_warm_up() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let rpc = RpcRequest::get("chains/main/blocks/head", 1);
+        let (branch, requests) = node_stub("200 OK", "{}");
+        let steps = RefCell::new(Vec::new());
+
+        let (cold, measured) = measure_warm(
+            &branch,
+            &rpc,
+            true,
+            5,
+            |warm_up| {
+                // the stub has already answered the cold request
+                let path = requests.recv_timeout(Duration::from_secs(5))?;
+                steps.borrow_mut().push(format!("cold {}", path));
+                steps.borrow_mut().push(format!("warm-up {}s", warm_up));
+                Ok(())
+            },
+            || {
+                steps.borrow_mut().push("measure".to_string());
+                Ok(42)
+            },
+        )
+        .unwrap();
+
+        assert!(cold.unwrap() > 0.0);
+        assert_eq!(measured, 42);
+        assert_eq!(
+            steps.into_inner(),
+            ["cold /chains/main/blocks/head", "warm-up 5s", "measure"]
+        );
+    }
+
+    #[test]
+    fn warm_up_and_cold_request_when_asked_for() {
+        // nothing is requested from the unreachable branch
+        let branch = branch(0, BranchType::Ocaml);
+        let rpc = RpcRequest::get("chains/main/blocks/head", 1);
+        let steps = RefCell::new(Vec::new());
+        let run = |cold_warm, warm_up| {
+            measure_warm(
+                &branch,
+                &rpc,
+                cold_warm,
+                warm_up,
+                |_| {
+                    steps.borrow_mut().push("warm-up");
+                    Ok(())
+                },
+                || {
+                    steps.borrow_mut().push("measure");
+                    Ok(())
+                },
+            )
+        };
+
+        assert_eq!(run(false, 0).unwrap().0, None);
+        assert_eq!(steps.borrow_mut().split_off(0), ["measure"]);
+        assert_eq!(run(false, 1).unwrap().0, None);
+        assert_eq!(steps.borrow_mut().split_off(0), ["warm-up", "measure"]);
+    }
 }
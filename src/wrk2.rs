@@ -2,23 +2,131 @@
 // SPDX-License-Identifier: MIT
 
 use std::collections::HashMap;
-use std::process::Command;
 
 use failure::bail;
+
 use crate::configuration::RpcLatencyTestEnv;
-use crate::types::{Branch, BranchType};
+use crate::html_report;
+use crate::influx::{self, Point};
+use crate::metrics;
+use crate::sampling::{check_samples, pin_head, sampling_reference, Sampling};
+use crate::schedule::{measure_branches, BranchScheduler};
+use crate::summary::Summary;
+use crate::types::{reference_index, Branch, LatencyPercentile, RpcRequest, WrkErrors, WrkResult};
+use crate::utils::command_output;
+use crate::wrk::{
+    calculate_and_display_statistics, check_regressions, script_args, wrk_command, WrkResultMap,
+};
+
+/// Throughput and latency distribution of the text output of wrk2 with `--latency`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Wrk2Result {
+    pub requests: f32,
+    /// Duration of the run in milliseconds
+    pub duration: f32,
+    pub throughput: f32,
+    /// Latencies of the percentiles in milliseconds
+    pub percentiles: Vec<(f32, f32)>,
+    /// Socket errors and the non-2xx/3xx responses as status errors
+    pub errors: WrkErrors,
+}
+
+impl Wrk2Result {
+    pub fn parse(output: &str) -> Self {
+        let mut result = Wrk2Result::default();
+        let mut in_distribution = false;
+
+        for line in output.lines().map(str::trim) {
+            // e.g. `600 requests in 1.00m, 1.20MB read`
+            if let Some((requests, rest)) = line.split_once(" requests in ") {
+                result.requests = requests.parse().unwrap_or(0.0);
+                result.duration = rest
+                    .split(',')
+                    .next()
+                    .and_then(|duration| parse_latency(duration.trim()))
+                    .unwrap_or(0.0);
+            } else if line.starts_with("Latency Distribution") {
+                in_distribution = true;
+            } else if in_distribution {
+                let parsed = line.split_once('%').and_then(|(percentile, latency)| {
+                    Some((percentile.parse().ok()?, parse_latency(latency.trim())?))
+                });
+                match parsed {
+                    Some(percentile) => result.percentiles.push(percentile),
+                    None => in_distribution = false,
+                }
+            } else if let Some(throughput) = line.strip_prefix("Requests/sec:") {
+                result.throughput = throughput.trim().parse().unwrap_or(0.0);
+            } else if let Some(errors) = line.strip_prefix("Socket errors:") {
+                // e.g. `Socket errors: connect 0, read 2, write 0, timeout 1`
+                for (name, count) in errors
+                    .split(',')
+                    .filter_map(|error| error.trim().split_once(' '))
+                {
+                    let count = count.trim().parse().unwrap_or(0.0);
+                    match name {
+                        "connect" => result.errors.connect = count,
+                        "read" => result.errors.read = count,
+                        "write" => result.errors.write = count,
+                        "timeout" => result.errors.timeout = count,
+                        _ => (),
+                    }
+                }
+            } else if let Some(responses) = line.strip_prefix("Non-2xx or 3xx responses:") {
+                result.errors.status = responses.trim().parse().unwrap_or(0.0);
+            }
+        }
+        result
+    }
 
-type WrkResultMap = HashMap<Branch, ()>;
+    /// The result in the format of the wrk json summary, in microseconds
+    pub fn to_wrk_result(&self) -> WrkResult {
+        WrkResult::from_distribution(
+            self.duration * 1000.0,
+            self.requests,
+            self.percentiles
+                .iter()
+                .map(|(percentile, latency)| LatencyPercentile {
+                    percentile: *percentile,
+                    latency: latency * 1000.0,
+                })
+                .collect(),
+        )
+        .with_errors(self.errors, Vec::new())
+    }
+}
 
-fn run_wrk(branch: &Branch, rpc: &str, duration: &u64, rate: u64) -> Result<(), failure::Error> {
-    let url = format!("{}{}", branch.url, rpc);
+/// Latency printed by wrk2, e.g. `812.00us` or `1.23ms`, in milliseconds
+fn parse_latency(latency: &str) -> Option<f32> {
+    let unit = latency.find(|c: char| c.is_ascii_alphabetic())?;
+    let value: f32 = latency[..unit].parse().ok()?;
+    match &latency[unit..] {
+        "us" => Some(value * 0.001),
+        "ms" => Some(value),
+        "s" => Some(value * 1000.0),
+        "m" => Some(value * 60_000.0),
+        "h" => Some(value * 3_600_000.0),
+        _ => None,
+    }
+}
+
+/// Runs wrk2 with a constant request rate against a single rpc of the branch, the responses
+/// are sampled by the wrk script with the sampling
+pub fn run_wrk(
+    branch: &Branch,
+    rpc: &RpcRequest,
+    duration: &u64,
+    rate: u64,
+    sampling: Option<&Sampling>,
+) -> Result<Wrk2Result, failure::Error> {
+    let url = format!("{}{}", branch.url, rpc.path);
     println!("URL: {}", url);
 
     // local testing
     // let master_url = format!("{}/{}", "http://116.202.128.230:28732", rpc);
     // let modified_url = format!("{}/{}", "http://116.202.128.230:18732", rpc);
     // let ocaml_url = format!("{}/{}", "http://116.202.128.230:10000", rpc);
-    let duration_string = &format!("-d{}s", duration.clone());
+    let duration_string = &format!("-d{}s", duration);
     let rate_string = &format!("-R{}", rate);
 
     let mut wrk_args = vec![
@@ -32,18 +140,20 @@ fn run_wrk(branch: &Branch, rpc: &str, duration: &u64, rate: u64) -> Result<(),
         "--latency",
     ];
     println!(
-        "Running wrk for {:?} with arguments: {:?}",
-        branch.url.domain().unwrap_or(""),
-        &wrk_args
+        "Running wrk for {} with arguments: {:?}",
+        branch.name, &wrk_args
     );
     println!();
 
     wrk_args.push(&url);
+    // other requests than plain GETs and the sampling are set up by the wrk script
+    let script_args = script_args(rpc, sampling);
+    if !rpc.is_plain_get() || sampling.is_some() {
+        wrk_args.extend(&["-s", "/scripts/as_json.lua", "--"]);
+        wrk_args.extend(script_args.iter().map(String::as_str));
+    }
 
-    let output = Command::new("wrk2")
-        .args(&wrk_args)
-        .current_dir("/")
-        .output()?;
+    let output = command_output(&mut wrk_command("wrk2", &wrk_args, sampling.is_some()))?;
 
     if !output.status.success() {
         println!("{:?}", output);
@@ -53,49 +163,219 @@ fn run_wrk(branch: &Branch, rpc: &str, duration: &u64, rate: u64) -> Result<(),
     let output = String::from_utf8(output.stdout)?;
     println!("=== Output ===\n{}===========", output);
 
-    Ok(())
+    Ok(Wrk2Result::parse(&output))
 }
 
-pub(crate) fn test_rpc_performance(env: RpcLatencyTestEnv) -> Result<(), failure::Error> {
+pub fn test_rpc_performance(env: RpcLatencyTestEnv) -> Result<(), failure::Error> {
     let RpcLatencyTestEnv {
-        tezedge_new_node,
-        tezedge_old_node,
-        ocaml_node,
+        targets,
         url_file,
         wrk_test_duration,
         wrk_request_rate,
+        gates,
+        sample_rate,
+        branch_order,
+        seed,
+        concurrent_branches,
+        markdown,
     } = env;
 
-    for rpc in super::utils::get_urls(&url_file)? {
-        let ocaml = Branch::new(0, ocaml_node.clone(), BranchType::Ocaml);
-        let tezedge_new = Branch::new(1, tezedge_new_node.clone(), BranchType::Feature);
-        let tezedge_old = tezedge_old_node.as_ref().map(|b| Branch::new(2, b.clone(), BranchType::Stable));
+    let branches: Vec<Branch> = targets
+        .iter()
+        .enumerate()
+        .map(|(sort_key, target)| target.branch(sort_key))
+        .collect();
+    let reference = &branches[reference_index(&targets)];
+    let sampling_reference = sampling_reference(&branches, reference);
+    let mut scheduler = BranchScheduler::new(branch_order, seed);
+    let mut summary = Summary::default();
 
-        println!("Running wrk for rpc: {}", rpc);
-        println!();
-        let mut outputs: WrkResultMap = HashMap::new();
+    // the summary is also displayed, when a regression stops the test
+    let result = (|| {
+        for rpc in super::utils::get_requests(&url_file)? {
+            println!("Running wrk for rpc: {}", rpc);
+            let ordered = scheduler.next_order(&branches);
+            let measured_rpc = if sample_rate > 0.0 {
+                pin_head(&rpc, sampling_reference)?
+            } else {
+                rpc.clone()
+            };
+            println!();
+            let mut outputs: WrkResultMap = HashMap::new();
+            let mut samples = Vec::new();
 
+            let mut measured = measure_branches(&ordered, concurrent_branches, |branch| {
+                let sampling = Some(Sampling::new(sample_rate, branch))
+                    .filter(|_| sample_rate > 0.0 && branch != sampling_reference);
+                let result = run_wrk(
+                    branch,
+                    &measured_rpc,
+                    &wrk_test_duration,
+                    wrk_request_rate,
+                    sampling.as_ref(),
+                )?;
+                let sampled = sampling.map(|s| s.take_samples()).transpose()?;
+                Ok((result, sampled))
+            })?;
 
-        if let Some(tezedge_old) = &tezedge_old {
-            std::thread::sleep(std::time::Duration::from_secs(1));
+            measured.sort_by_key(|(branch, _)| branch.sort_key);
+            for (branch, (result, sampled)) in measured {
+                if let Some(sampled) = sampled {
+                    samples.push((branch.clone(), sampled));
+                }
+                let mut point = Point::new("latency")
+                    .branch(&branch)
+                    .tag("rpc", &rpc.to_string())
+                    .float("throughput", result.throughput);
+                for (percentile, latency) in &result.percentiles {
+                    point = point.float(&format!("latency_p{}_ms", percentile), *latency);
+                }
+                influx::emit(point);
+                let result = result.to_wrk_result();
+                metrics::observe_wrk_result(&branch.name, &result);
+                outputs.insert(branch, result);
+            }
+            let mut divergences = Vec::new();
+            if sample_rate > 0.0 {
+                divergences = check_samples(&measured_rpc, sampling_reference, &samples)?;
+            }
 
-            outputs.insert(
-                tezedge_old.clone(),
-                run_wrk(&tezedge_old, &rpc, &wrk_test_duration, wrk_request_rate)?,
-            );
+            let mut regressions = calculate_and_display_statistics(&outputs, reference, &gates);
+            regressions.extend(divergences);
+            let name = rpc.to_string();
+            summary.add(&name, &outputs, reference, &regressions);
+            summary.add_order(&name, &ordered);
+            html_report::record_rpc(&name, &outputs);
+            html_report::record_order(&name, &ordered);
+            check_regressions(&regressions)?;
         }
+        Ok(())
+    })();
 
-        std::thread::sleep(std::time::Duration::from_secs(1));
+    summary.display();
+    if let Some(markdown) = markdown {
+        summary.write_markdown(&markdown)?;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    use crate::types::BranchType;
+
+    const OUTPUT: &str = "\
+Running 1m test @ http://127.0.0.1:18732/chains/main/blocks/head/header
+  1 threads and 1 connections
+  Thread calibration: mean lat.: 1.112ms, rate sampling interval: 10ms
+  Thread Stats   Avg      Stdev     Max   +/- Stdev
+    Latency     1.10ms  381.53us   4.12ms   71.43%
+    Req/Sec    10.55     31.63   100.00     89.47%
+  Latency Distribution (HdrHistogram - Recorded Latency)
+ 50.000%    1.07ms
+ 75.000%    1.30ms
+ 90.000%    1.59ms
+ 99.000%    2.45ms
+ 99.900%    4.12ms
+100.000%    4.12ms
 
-        outputs.insert(ocaml.clone(), run_wrk(&ocaml, &rpc, &wrk_test_duration, wrk_request_rate)?);
+  Detailed Percentile spectrum:
+       Value   Percentile   TotalCount 1/(1-Percentile)
 
-        std::thread::sleep(std::time::Duration::from_secs(1));
+       0.812     0.000000            1         1.00
+  600 requests in 1.00m, 1.20MB read
+  Socket errors: connect 0, read 2, write 0, timeout 1
+  Non-2xx or 3xx responses: 4
+Requests/sec:     10.00
+Transfer/sec:     20.48KB
+";
 
-        outputs.insert(
-            tezedge_new.clone(),
-            run_wrk(&tezedge_new, &rpc, &wrk_test_duration, wrk_request_rate)?,
+    #[test]
+    fn parse_latency_units() {
+        assert_eq!(parse_latency("500.00us"), Some(0.5));
+        assert_eq!(parse_latency("1.23ms"), Some(1.23));
+        assert_eq!(parse_latency("2.50s"), Some(2500.0));
+        assert_eq!(parse_latency("1.00m"), Some(60_000.0));
+        assert_eq!(parse_latency("1.00h"), Some(3_600_000.0));
+        assert_eq!(parse_latency("1.00KB"), None);
+        assert_eq!(parse_latency("ms"), None);
+        assert_eq!(parse_latency("12"), None);
+    }
+
+    #[test]
+    fn parse_output() {
+        let result = Wrk2Result::parse(OUTPUT);
+        assert_eq!(result.requests, 600.0);
+        assert_eq!(result.duration, 60_000.0);
+        assert_eq!(result.throughput, 10.0);
+        // the detailed spectrum is not a part of the distribution
+        assert_eq!(
+            result.percentiles,
+            vec![
+                (50.0, 1.07),
+                (75.0, 1.30),
+                (90.0, 1.59),
+                (99.0, 2.45),
+                (99.9, 4.12),
+                (100.0, 4.12)
+            ]
+        );
+        assert_eq!(
+            result.errors,
+            WrkErrors {
+                read: 2.0,
+                timeout: 1.0,
+                status: 4.0,
+                ..WrkErrors::default()
+            }
         );
+
+        let wrk_result = result.to_wrk_result();
+        assert_eq!(*wrk_result.duration(), 60_000_000.0);
+        assert_eq!(*wrk_result.latency_p50(), 1070.0);
+        assert_eq!(*wrk_result.latency_max(), 4120.0);
+        assert_eq!(wrk_result.errors().total(), 7.0);
+    }
+
+    #[test]
+    fn parse_output_without_errors() {
+        let output = OUTPUT
+            .lines()
+            .filter(|line| !line.contains("errors") && !line.contains("Non-2xx"))
+            .join("\n");
+        let result = Wrk2Result::parse(&output);
+        assert_eq!(result.errors, WrkErrors::default());
+        assert_eq!(result.to_wrk_result().error_rate(), 0.0);
+        assert_eq!(Wrk2Result::parse("").requests, 0.0);
     }
 
-    Ok(())
+    #[test]
+    fn run_in_the_html_report() {
+        let url = "http://127.0.0.1:18732/".parse().unwrap();
+        let branch = Branch::new(0, "wrk2-node", url, BranchType::Ocaml);
+        let outputs: WrkResultMap =
+            vec![(branch.clone(), Wrk2Result::parse(OUTPUT).to_wrk_result())]
+                .into_iter()
+                .collect();
+        html_report::record_rpc("wrk2/report/rpc", &outputs);
+        html_report::record_order("wrk2/report/rpc", &[branch]);
+
+        let file = std::env::temp_dir().join(format!(
+            "tezos-node-bootstrap-wrk2-{}.html",
+            std::process::id()
+        ));
+        html_report::write_html_report(&file.to_string_lossy()).unwrap();
+        let html = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        let run = html
+            .split("<h3><code>wrk2/report/rpc</code></h3>")
+            .nth(1)
+            .unwrap();
+        assert!(run.starts_with("\n<p>Measured in the order: wrk2-node</p>"));
+        assert!(run.contains("Latency CDF"));
+        assert!(run.contains("<title>wrk2-node: "));
+    }
 }